@@ -0,0 +1,578 @@
+// Aztec Code encoder (ISO/IEC 24778). Text is encoded greedily with the
+// character modes, falling back to binary shift for bytes they cannot hold.
+
+use crate::bit_matrix::BitMatrix;
+use crate::reed_solomon::GaloisField;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AztecMode {
+    Auto,
+    Compact,
+    Full,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CharMode {
+    Upper,
+    Lower,
+    Mixed,
+    Digit,
+    Punct,
+}
+
+/// Error correction as a share of the symbol, plus a fixed 3 words
+const MIN_ECC_PERCENT: usize = 33;
+const MIN_ECC_BITS: usize = 11;
+
+/// Codeword size in bits, indexed by layer count
+const WORD_SIZE: [usize; 33] = [
+    4, 6, 6, 8, 8, 8, 8, 8, 8, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 12, 12, 12, 12, 12, 12, 12, 12,
+    12, 12,
+];
+
+pub fn encode(text: &str, mode: AztecMode) -> Result<BitMatrix, String> {
+    if text.is_empty() {
+        return Err("Aztec: no data to encode".to_string());
+    }
+
+    let bits = encode_high_level(text.as_bytes());
+    let ecc_bits = bits.len() * MIN_ECC_PERCENT / 100 + MIN_ECC_BITS;
+    let total_size_bits = bits.len() + ecc_bits;
+
+    let candidates: Vec<(bool, usize)> = match mode {
+        AztecMode::Compact => (1..=4).map(|l| (true, l)).collect(),
+        AztecMode::Full => (1..=32).map(|l| (false, l)).collect(),
+        AztecMode::Auto => (1..=4).map(|l| (true, l)).chain((4..=32).map(|l| (false, l))).collect(),
+    };
+
+    let mut chosen = None;
+    for (compact, layers) in candidates {
+        let total_bits = total_bits_in_layers(layers, compact);
+        if total_size_bits > total_bits {
+            continue;
+        }
+        let word_size = WORD_SIZE[layers];
+        let stuffed = stuff_bits(&bits, word_size);
+        if compact && stuffed.len() > word_size * 64 {
+            // The compact mode message only counts up to 64 data words
+            continue;
+        }
+        let usable = total_bits - total_bits % word_size;
+        if stuffed.len() + ecc_bits <= usable {
+            chosen = Some((compact, layers, word_size, stuffed));
+            break;
+        }
+    }
+    let (compact, layers, word_size, stuffed) =
+        chosen.ok_or_else(|| "Aztec: data too large for the selected symbol size".to_string())?;
+
+    let total_bits = total_bits_in_layers(layers, compact);
+    let message_words = stuffed.len() / word_size;
+    let message = add_check_words(&stuffed, total_bits, word_size);
+
+    let mode_message = mode_message(compact, layers, message_words);
+
+    Ok(build_matrix(&message, &mode_message, compact, layers))
+}
+
+/// Layer and data word counts with their check words, drawn around the bull's-eye
+fn mode_message(compact: bool, layers: usize, message_words: usize) -> Vec<bool> {
+    let mut bits = Vec::new();
+    if compact {
+        push_bits(&mut bits, layers as u32 - 1, 2);
+        push_bits(&mut bits, message_words as u32 - 1, 6);
+        add_check_words(&bits, 28, 4)
+    } else {
+        push_bits(&mut bits, layers as u32 - 1, 5);
+        push_bits(&mut bits, message_words as u32 - 1, 11);
+        add_check_words(&bits, 40, 4)
+    }
+}
+
+fn total_bits_in_layers(layers: usize, compact: bool) -> usize {
+    ((if compact { 88 } else { 112 }) + 16 * layers) * layers
+}
+
+fn push_bits(bits: &mut Vec<bool>, value: u32, count: usize) {
+    for i in (0..count).rev() {
+        bits.push(value >> i & 1 != 0);
+    }
+}
+
+/// Split into codewords, inserting a complementary bit after any word that
+/// would otherwise be all zeros or all ones (the last bit is the stuffed one)
+fn stuff_bits(bits: &[bool], word_size: usize) -> Vec<bool> {
+    let mut out = Vec::new();
+    let mask = (1u32 << word_size) - 2;
+    let mut i = 0;
+    while i < bits.len() {
+        let mut word = 0u32;
+        for j in 0..word_size {
+            if bits.get(i + j).copied().unwrap_or(true) {
+                word |= 1 << (word_size - 1 - j);
+            }
+        }
+        if word & mask == mask {
+            push_bits(&mut out, word & mask, word_size);
+            i += word_size - 1;
+        } else if word & mask == 0 {
+            push_bits(&mut out, word | 1, word_size);
+            i += word_size - 1;
+        } else {
+            push_bits(&mut out, word, word_size);
+            i += word_size;
+        }
+    }
+    out
+}
+
+/// Append Reed-Solomon check words filling `total_bits`; the remainder that
+/// does not make a whole word is zero padding at the start
+fn add_check_words(bits: &[bool], total_bits: usize, word_size: usize) -> Vec<bool> {
+    let poly = match word_size {
+        4 => 0x13,
+        6 => 0x43,
+        8 => 0x12D,
+        10 => 0x409,
+        _ => 0x1069,
+    };
+    let field = GaloisField::new(poly, 1 << word_size);
+
+    let words: Vec<u16> = bits
+        .chunks(word_size)
+        .map(|chunk| chunk.iter().fold(0u16, |acc, &b| acc << 1 | b as u16))
+        .collect();
+    let total_words = total_bits / word_size;
+    let ecc = field.encode(&words, total_words - words.len());
+
+    let mut out = vec![false; total_bits % word_size];
+    for word in words.iter().chain(&ecc) {
+        push_bits(&mut out, *word as u32, word_size);
+    }
+    out
+}
+
+fn build_matrix(message: &[bool], mode_message: &[bool], compact: bool, layers: usize) -> BitMatrix {
+    let base_size = (if compact { 11 } else { 14 }) + layers * 4;
+
+    // Full symbols have a reference grid line every 16 modules from the centre
+    let (size, alignment) = if compact {
+        (base_size, (0..base_size).collect::<Vec<_>>())
+    } else {
+        let size = base_size + 1 + 2 * ((base_size / 2 - 1) / 15);
+        let orig_center = base_size / 2;
+        let center = size / 2;
+        let mut map = vec![0; base_size];
+        for i in 0..orig_center {
+            let offset = i + i / 15;
+            map[orig_center - i - 1] = center - offset - 1;
+            map[orig_center + i] = center + offset + 1;
+        }
+        (size, map)
+    };
+
+    let mut matrix = BitMatrix::new(size, size);
+    let bit = |i: usize| message.get(i).copied().unwrap_or(false);
+
+    // Layers spiral inwards, each side two modules thick
+    let mut row_offset = 0;
+    for i in 0..layers {
+        let row_size = (layers - i) * 4 + if compact { 9 } else { 12 };
+        for j in 0..row_size {
+            let column_offset = j * 2;
+            for k in 0..2 {
+                let far = base_size - 1 - i * 2;
+                if bit(row_offset + column_offset + k) {
+                    matrix.set(alignment[i * 2 + k], alignment[i * 2 + j], true);
+                }
+                if bit(row_offset + row_size * 2 + column_offset + k) {
+                    matrix.set(alignment[i * 2 + j], alignment[far - k], true);
+                }
+                if bit(row_offset + row_size * 4 + column_offset + k) {
+                    matrix.set(alignment[far - k], alignment[far - j], true);
+                }
+                if bit(row_offset + row_size * 6 + column_offset + k) {
+                    matrix.set(alignment[far - j], alignment[i * 2 + k], true);
+                }
+            }
+        }
+        row_offset += row_size * 8;
+    }
+
+    draw_mode_message(&mut matrix, compact, size, mode_message);
+
+    let center = size / 2;
+    if compact {
+        draw_bulls_eye(&mut matrix, center, 5);
+    } else {
+        draw_bulls_eye(&mut matrix, center, 7);
+        let mut i = 0;
+        let mut j = 0;
+        while i < base_size / 2 - 1 {
+            for k in (center & 1..size).step_by(2) {
+                matrix.set(center - j, k, true);
+                matrix.set(center + j, k, true);
+                matrix.set(k, center - j, true);
+                matrix.set(k, center + j, true);
+            }
+            i += 15;
+            j += 16;
+        }
+    }
+
+    matrix
+}
+
+fn draw_bulls_eye(matrix: &mut BitMatrix, center: usize, size: usize) {
+    for i in (0..size).step_by(2) {
+        for j in center - i..=center + i {
+            matrix.set(j, center - i, true);
+            matrix.set(j, center + i, true);
+            matrix.set(center - i, j, true);
+            matrix.set(center + i, j, true);
+        }
+    }
+    // Orientation marks
+    matrix.set(center - size, center - size, true);
+    matrix.set(center - size + 1, center - size, true);
+    matrix.set(center - size, center - size + 1, true);
+    matrix.set(center + size, center - size, true);
+    matrix.set(center + size, center - size + 1, true);
+    matrix.set(center + size, center + size - 1, true);
+}
+
+fn draw_mode_message(matrix: &mut BitMatrix, compact: bool, size: usize, mode_message: &[bool]) {
+    let center = size / 2;
+    if compact {
+        for i in 0..7 {
+            let offset = center - 3 + i;
+            if mode_message[i] {
+                matrix.set(offset, center - 5, true);
+            }
+            if mode_message[i + 7] {
+                matrix.set(center + 5, offset, true);
+            }
+            if mode_message[20 - i] {
+                matrix.set(offset, center + 5, true);
+            }
+            if mode_message[27 - i] {
+                matrix.set(center - 5, offset, true);
+            }
+        }
+    } else {
+        for i in 0..10 {
+            let offset = center - 5 + i + i / 5;
+            if mode_message[i] {
+                matrix.set(offset, center - 7, true);
+            }
+            if mode_message[i + 10] {
+                matrix.set(center + 7, offset, true);
+            }
+            if mode_message[29 - i] {
+                matrix.set(offset, center + 7, true);
+            }
+            if mode_message[39 - i] {
+                matrix.set(center - 7, offset, true);
+            }
+        }
+    }
+}
+
+/// Code of `c` in a character mode, if the mode can hold it
+fn char_code(mode: CharMode, c: u8) -> Option<u32> {
+    let code = match mode {
+        CharMode::Upper => match c {
+            b' ' => 1,
+            b'A'..=b'Z' => c - b'A' + 2,
+            _ => return None,
+        },
+        CharMode::Lower => match c {
+            b' ' => 1,
+            b'a'..=b'z' => c - b'a' + 2,
+            _ => return None,
+        },
+        CharMode::Mixed => match c {
+            b' ' => 1,
+            1..=13 => c + 1,
+            27..=31 => c - 12,
+            b'@' => 20,
+            b'\\' => 21,
+            b'^' => 22,
+            b'_' => 23,
+            b'`' => 24,
+            b'|' => 25,
+            b'~' => 26,
+            127 => 27,
+            _ => return None,
+        },
+        CharMode::Digit => match c {
+            b' ' => 1,
+            b'0'..=b'9' => c - b'0' + 2,
+            b',' => 12,
+            b'.' => 13,
+            _ => return None,
+        },
+        CharMode::Punct => match c {
+            b'\r' => 1,
+            b'!'..=b'/' => c - b'!' + 6,
+            b':'..=b'?' => c - b':' + 21,
+            b'[' => 27,
+            b']' => 28,
+            b'{' => 29,
+            b'}' => 30,
+            _ => return None,
+        },
+    };
+    Some(code as u32)
+}
+
+/// Mode a character is best latched into, or `None` if it needs binary shift
+fn preferred_mode(c: u8) -> Option<CharMode> {
+    [CharMode::Upper, CharMode::Lower, CharMode::Digit, CharMode::Punct, CharMode::Mixed]
+        .into_iter()
+        .find(|&m| char_code(m, c).is_some())
+}
+
+fn code_bits(mode: CharMode) -> usize {
+    if mode == CharMode::Digit {
+        4
+    } else {
+        5
+    }
+}
+
+/// Latch codes taking `from` to `to`, as (code, bit count) pairs
+fn latch(from: CharMode, to: CharMode) -> Vec<(u32, usize)> {
+    use CharMode::*;
+    match (from, to) {
+        _ if from == to => Vec::new(),
+        (Punct, _) => [vec![(31, 5)], latch(Upper, to)].concat(),
+        (Digit, _) => [vec![(14, 4)], latch(Upper, to)].concat(),
+        (Upper, Lower) => vec![(28, 5)],
+        (Upper, Mixed) | (Lower, Mixed) | (Mixed, Upper) => vec![(29, 5)],
+        (Upper, Digit) | (Lower, Digit) => vec![(30, 5)],
+        (Upper, Punct) | (Lower, Punct) => vec![(29, 5), (30, 5)],
+        (Lower, Upper) => vec![(30, 5), (14, 4)],
+        (Mixed, Lower) => vec![(28, 5)],
+        (Mixed, Punct) => vec![(30, 5)],
+        (Mixed, Digit) => vec![(29, 5), (30, 5)],
+        _ => unreachable!(),
+    }
+}
+
+fn encode_high_level(data: &[u8]) -> Vec<bool> {
+    let mut bits = Vec::new();
+    let mut mode = CharMode::Upper;
+    let mut i = 0;
+
+    while i < data.len() {
+        let c = data[i];
+        if let Some(code) = char_code(mode, c) {
+            push_bits(&mut bits, code, code_bits(mode));
+            i += 1;
+            continue;
+        }
+
+        let next_same = |target: CharMode| {
+            data.get(i + 1)
+                .is_some_and(|&n| char_code(mode, n).is_none() && char_code(target, n).is_some())
+        };
+
+        match preferred_mode(c) {
+            None => {
+                // Binary shift is only available from the upper, lower and mixed modes
+                if mode == CharMode::Digit || mode == CharMode::Punct {
+                    for (code, n) in latch(mode, CharMode::Upper) {
+                        push_bits(&mut bits, code, n);
+                    }
+                    mode = CharMode::Upper;
+                }
+                let end = (i..data.len())
+                    .find(|&j| preferred_mode(data[j]).is_some())
+                    .unwrap_or(data.len())
+                    .min(i + 2047 + 31);
+                let len = end - i;
+                push_bits(&mut bits, 31, 5);
+                if len <= 31 {
+                    push_bits(&mut bits, len as u32, 5);
+                } else {
+                    push_bits(&mut bits, 0, 5);
+                    push_bits(&mut bits, (len - 31) as u32, 11);
+                }
+                for &b in &data[i..end] {
+                    push_bits(&mut bits, b as u32, 8);
+                }
+                i = end;
+            }
+            Some(CharMode::Punct) if !next_same(CharMode::Punct) => {
+                // P/S is code 0 in every mode
+                push_bits(&mut bits, 0, code_bits(mode));
+                push_bits(&mut bits, char_code(CharMode::Punct, c).unwrap(), 5);
+                i += 1;
+            }
+            Some(CharMode::Upper)
+                if (mode == CharMode::Lower || mode == CharMode::Digit) && !next_same(CharMode::Upper) =>
+            {
+                let shift = if mode == CharMode::Lower { 28 } else { 15 };
+                push_bits(&mut bits, shift, code_bits(mode));
+                push_bits(&mut bits, char_code(CharMode::Upper, c).unwrap(), 5);
+                i += 1;
+            }
+            Some(target) => {
+                for (code, n) in latch(mode, target) {
+                    push_bits(&mut bits, code, n);
+                }
+                mode = target;
+            }
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bits written as 'X' (one) and '.' (zero), ignoring spaces
+    fn bits(s: &str) -> Vec<bool> {
+        s.chars().filter(|&c| c != ' ').map(|c| c == 'X').collect()
+    }
+
+    /// Codes of `(value, width)` concatenated into a bit string
+    fn codes(codes: &[(u32, usize)]) -> Vec<bool> {
+        let mut bits = Vec::new();
+        for &(value, width) in codes {
+            push_bits(&mut bits, value, width);
+        }
+        bits
+    }
+
+    fn rows(matrix: &BitMatrix) -> Vec<String> {
+        (0..matrix.height)
+            .map(|y| (0..matrix.width).map(|x| if matrix.get(x, y) { 'X' } else { '.' }).collect())
+            .collect()
+    }
+
+    #[test]
+    fn encodes_mode_messages() {
+        // Reference vectors from zxing's Aztec encoder tests
+        assert_eq!(mode_message(true, 2, 29), bits(".X .XXX.. ...X XX.. ..X. XX.. XX.X"));
+        assert_eq!(mode_message(true, 4, 64), bits("XX XXXXXX .X.. ...X ..XX .X.. XX.."));
+        assert_eq!(mode_message(false, 21, 660), bits("X.X.. .X.X..X..XX .XXX ..X. ..XX X..X .... .XXX"));
+        assert_eq!(mode_message(false, 32, 4096), bits("XXXXX XXXXXXXXXXX X.X. .... .XXX .X.. X..X .XXX"));
+    }
+
+    #[test]
+    fn stuffs_words_of_all_zeros_or_ones() {
+        let stuff = |word_size: usize, input: &str| stuff_bits(&bits(input), word_size);
+        assert_eq!(stuff(5, ".X.X. X.X.X .X.X."), bits(".X.X. X.X.X .X.X."));
+        assert_eq!(stuff(5, ".X.X. ..... .X.X"), bits(".X.X. ....X ..X.X"));
+        assert_eq!(stuff(6, ".X.X.. XXXXXX ...... ..X.XX"), bits(".X.X.. XXXXX. X..... ...X.X XXXXX."));
+        // A trailing partial word is padded with ones, then stuffed like any other
+        assert_eq!(stuff(4, "XXX"), bits("XXX."));
+    }
+
+    #[test]
+    fn latches_and_shifts_between_modes() {
+        // C, latch lower, "ode", space, latch digit, 2, shift upper D, shift punct !
+        let expected = codes(&[
+            (4, 5),
+            (28, 5), (16, 5), (5, 5), (6, 5), (1, 5),
+            (30, 5), (4, 4),
+            (15, 4), (5, 5),
+            (0, 4), (6, 5),
+        ]);
+        assert_eq!(encode_high_level(b"Code 2D!"), expected);
+
+        // Two punctuation marks latch through mixed mode; lower case then
+        // leaves punctuation through upper
+        let expected = codes(&[(29, 5), (30, 5), (27, 5), (28, 5), (31, 5), (28, 5), (2, 5)]);
+        assert_eq!(encode_high_level(b"[]a"), expected);
+
+        // Mixed characters, then back to upper
+        let expected = codes(&[(29, 5), (20, 5), (29, 5), (2, 5)]);
+        assert_eq!(encode_high_level(b"@A"), expected);
+    }
+
+    #[test]
+    fn binary_shifts_bytes_no_mode_holds() {
+        // From digit mode, latch to upper before the shift; UTF-8 'é' is C3 A9
+        let expected = codes(&[(30, 5), (3, 4), (14, 4), (31, 5), (2, 5), (0xC3, 8), (0xA9, 8)]);
+        assert_eq!(encode_high_level("1é".as_bytes()), expected);
+
+        // Runs over 31 bytes take an 11-bit length
+        let long = encode_high_level(&[0xFF; 40]);
+        assert_eq!(long[..21], codes(&[(31, 5), (0, 5), (9, 11)]));
+        assert_eq!(long.len(), 21 + 40 * 8);
+    }
+
+    #[test]
+    fn encodes_code_2d_compact() {
+        let expected = [
+            "..XXXXXXXXX...X",
+            "...XX...XXX.XX.",
+            "X.XX....X...XXX",
+            ".XXXXXXXXXXXX.X",
+            "XXXX.......XX.X",
+            "...X.XXXXX.XXX.",
+            "X..X.X...X.XX..",
+            "..XX.X.X.X.X...",
+            "..XX.X...X.X.XX",
+            ".X.X.XXXXX.X.X.",
+            "X..X.......X.X.",
+            "X..XXXXXXXXXXXX",
+            ".X...XX...X....",
+            "...XX...XX.XX..",
+            "X..XXX.X.XX....",
+        ];
+        assert_eq!(rows(&encode("Code 2D!", AztecMode::Auto).unwrap()), expected);
+        assert_eq!(rows(&encode("Code 2D!", AztecMode::Compact).unwrap()), expected);
+    }
+
+    #[test]
+    fn encodes_code_2d_full() {
+        let expected = [
+            ".....XX......XX..XX",
+            "..X.XXX..X...X..XXX",
+            "X.XX............X..",
+            ".XXXXXXXXXXXXXXXXX.",
+            "XX.X...........X...",
+            "..XX.XXXXXXXXX.X.X.",
+            "X.XX.X.......X.XXX.",
+            "...X.X.XXXXX.X.X.X.",
+            "..XX.X.X...X.X.X...",
+            ".X.X.X.X.X.X.X.X.X.",
+            ".X.X.X.X...X.X.XX.X",
+            "X.XX.X.XXXXX.X.XXX.",
+            "X..X.X.......X.X..X",
+            ".X.X.XXXXXXXXX.XXXX",
+            "X.XX...........X...",
+            "...XXXXXXXXXXXXXX.X",
+            "....X.....XX.XX....",
+            "XX...XX.XXX...XX...",
+            "XXX.X.XX......X.X..",
+        ];
+        assert_eq!(rows(&encode("Code 2D!", AztecMode::Full).unwrap()), expected);
+    }
+
+    #[test]
+    fn draws_reference_grid_in_full_symbols() {
+        // Five layers: 37 modules with grid lines 16 modules either side of the centre
+        let matrix = encode(&"A".repeat(120), AztecMode::Full).unwrap();
+        assert_eq!(matrix.width, 37);
+        for line in [2, 18, 34] {
+            for k in (0..37).step_by(2) {
+                assert!(matrix.get(line, k) && matrix.get(k, line), "line {} at {}", line, k);
+            }
+        }
+    }
+
+    #[test]
+    fn sizes_symbols_by_mode() {
+        let size = |text: &str, mode| encode(text, mode).map(|matrix| matrix.width);
+        assert_eq!(size("A", AztecMode::Auto), Ok(15));
+        assert_eq!(size("A", AztecMode::Full), Ok(19));
+        assert!(size(&"A".repeat(200), AztecMode::Compact).is_err());
+        assert!(encode("", AztecMode::Auto).is_err());
+    }
+}
@@ -0,0 +1,715 @@
+// Linear barcode encoders. Each encoder returns the symbol as a sequence of
+// modules (true = bar); quiet zones are reported separately.
+
+use std::ops::Range;
+
+/// Human-readable text centred on a span of modules
+pub struct HriText {
+    pub text: String,
+    /// Module span in symbol coordinates; may extend past either end of the
+    /// symbol for EAN/UPC digits printed outside the guard bars
+    pub start: i32,
+    pub end: i32,
+}
+
+pub struct Symbol {
+    pub modules: Vec<bool>,
+    pub hri: Vec<HriText>,
+    /// Module spans of EAN/UPC guard bars, which extend into the text line
+    pub guards: Vec<Range<usize>>,
+    /// Mandated light margin on each side, in modules
+    pub quiet_left: u32,
+    pub quiet_right: u32,
+    /// Smallest X-dimension (narrow module width) scanners reliably read
+    pub min_x_mm: f64,
+}
+
+/// Quiet zones and minimum X-dimension of a symbology
+struct PrintSpec {
+    quiet_left: u32,
+    quiet_right: u32,
+    min_x_mm: f64,
+}
+
+// General-purpose symbologies: 10X quiet zones, 7.5 mil minimum X-dimension
+const GENERAL_SPEC: PrintSpec = PrintSpec { quiet_left: 10, quiet_right: 10, min_x_mm: 0.191 };
+
+const ITF14_SPEC: PrintSpec = PrintSpec { quiet_left: 10, quiet_right: 10, min_x_mm: 0.495 };
+
+impl Symbol {
+    /// A symbol with the whole value printed under all of its modules
+    fn plain(modules: Vec<bool>, text: &str, spec: &PrintSpec) -> Self {
+        let end = modules.len() as i32;
+        Symbol {
+            modules,
+            hri: vec![HriText {
+                text: text.chars().filter(|c| !c.is_control()).collect(),
+                start: 0,
+                end,
+            }],
+            guards: Vec::new(),
+            quiet_left: spec.quiet_left,
+            quiet_right: spec.quiet_right,
+            min_x_mm: spec.min_x_mm,
+        }
+    }
+
+    /// A symbol using the split-digit layout of EAN/UPC
+    fn retail(modules: Vec<bool>, digits: &[u8], layout: &RetailLayout) -> Self {
+        let hri = layout
+            .groups
+            .iter()
+            .map(|&(first, last, start, end)| HriText {
+                text: digits[first..last].iter().map(|d| (b'0' + d) as char).collect(),
+                start,
+                end,
+            })
+            .collect();
+
+        Symbol {
+            modules,
+            hri,
+            guards: layout.guards.iter().map(|&(start, end)| start..end).collect(),
+            quiet_left: layout.spec.quiet_left,
+            quiet_right: layout.spec.quiet_right,
+            min_x_mm: layout.spec.min_x_mm,
+        }
+    }
+}
+
+/// Encode `text` in the given symbology
+pub fn encode(text: &str, format: &str) -> Result<Symbol, String> {
+    match format {
+        "code128" => Ok(Symbol::plain(encode_code128(text)?, text, &GENERAL_SPEC)),
+        "code39" => Ok(Symbol::plain(encode_code39(text)?, text, &GENERAL_SPEC)),
+        "code93" => Ok(Symbol::plain(encode_code93(text)?, text, &GENERAL_SPEC)),
+        "ean13" => {
+            let d = gs1_digits(text, 13, "EAN-13", gs1_check_digit)?;
+            Ok(Symbol::retail(encode_ean13(&d)?, &d, &EAN13_LAYOUT))
+        }
+        "ean8" => {
+            let d = gs1_digits(text, 8, "EAN-8", gs1_check_digit)?;
+            Ok(Symbol::retail(encode_ean8(&d)?, &d, &EAN8_LAYOUT))
+        }
+        "upca" => {
+            // UPC-A is EAN-13 with an implicit leading zero
+            let d = gs1_digits(text, 12, "UPC-A", gs1_check_digit)?;
+            let mut ean = vec![0];
+            ean.extend_from_slice(&d);
+            Ok(Symbol::retail(encode_ean13(&ean)?, &d, &UPCA_LAYOUT))
+        }
+        "upce" => {
+            let d = gs1_digits(text, 8, "UPC-E", upce_check_digit)?;
+            Ok(Symbol::retail(encode_upce(&d)?, &d, &UPCE_LAYOUT))
+        }
+        "itf14" => {
+            let d = gs1_digits(text, 14, "ITF-14", gs1_check_digit)?;
+            let digits: String = d.iter().map(|d| (b'0' + d) as char).collect();
+            Ok(Symbol::plain(encode_itf(&d)?, &digits, &ITF14_SPEC))
+        }
+        _ => Err(format!("Unsupported barcode format '{}'", format)),
+    }
+}
+
+/// Digits of a GS1 symbol that is `len` digits long including its check digit.
+/// A value one digit short gets the check digit appended; a full-length value
+/// must already carry the correct one.
+fn gs1_digits(text: &str, len: usize, name: &str, check_digit: fn(&[u8]) -> u8) -> Result<Vec<u8>, String> {
+    if !text.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("{} value '{}' must be numeric", name, text));
+    }
+
+    let mut d: Vec<u8> = text.bytes().map(|b| b - b'0').collect();
+    if d.len() == len - 1 {
+        d.push(check_digit(&d));
+    } else if d.len() == len {
+        let expected = check_digit(&d[..len - 1]);
+        if d[len - 1] != expected {
+            return Err(format!(
+                "{} value '{}' has check digit {}, expected {}",
+                name, text, d[len - 1], expected
+            ));
+        }
+    } else {
+        return Err(format!(
+            "{} requires {} or {} digits, got {} in '{}'",
+            name, len - 1, len, d.len(), text
+        ));
+    }
+
+    Ok(d)
+}
+
+/// Modulo-10 check digit with weights 3, 1, 3, ... from the rightmost data digit
+fn gs1_check_digit(data: &[u8]) -> u8 {
+    let sum: u32 = data
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| digit as u32 * if i % 2 == 0 { 3 } else { 1 })
+        .sum();
+    ((10 - sum % 10) % 10) as u8
+}
+
+/// UPC-E check digit is that of the equivalent UPC-A number.
+/// `data` is the number system followed by the six UPC-E digits.
+fn upce_check_digit(data: &[u8]) -> u8 {
+    gs1_check_digit(&upce_to_upca(data))
+}
+
+/// The eleven UPC-A digits, without check digit, a zero-suppressed UPC-E
+/// number expands to. `data` is the number system followed by the six UPC-E digits.
+fn upce_to_upca(data: &[u8]) -> Vec<u8> {
+    let (ns, e) = (data[0], &data[1..7]);
+    let manufacturer_product: [u8; 10] = match e[5] {
+        0..=2 => [e[0], e[1], e[5], 0, 0, 0, 0, e[2], e[3], e[4]],
+        3 => [e[0], e[1], e[2], 0, 0, 0, 0, 0, e[3], e[4]],
+        4 => [e[0], e[1], e[2], e[3], 0, 0, 0, 0, 0, e[4]],
+        _ => [e[0], e[1], e[2], e[3], e[4], 0, 0, 0, 0, e[5]],
+    };
+
+    let mut upca = vec![ns];
+    upca.extend_from_slice(&manufacturer_product);
+    upca
+}
+
+/// Append bars/spaces from a run-length pattern such as "212222", starting with a bar
+fn push_widths(modules: &mut Vec<bool>, widths: &str) {
+    for (i, w) in widths.bytes().enumerate() {
+        let bar = i % 2 == 0;
+        for _ in 0..(w - b'0') {
+            modules.push(bar);
+        }
+    }
+}
+
+/// Append modules from a bit pattern such as "0001101"
+fn push_bits(modules: &mut Vec<bool>, bits: &str) {
+    modules.extend(bits.bytes().map(|b| b == b'1'));
+}
+
+// ---------------------------------------------------------------------------
+// Code 128
+// ---------------------------------------------------------------------------
+
+const CODE128_PATTERNS: [&str; 107] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212", "221213",
+    "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221", "223211", "221132",
+    "221231", "213212", "223112", "312131", "311222", "321122", "321221", "312212", "322112", "322211",
+    "212123", "212321", "232121", "111323", "131123", "131321", "112313", "132113", "132311", "211313",
+    "231113", "231311", "112133", "112331", "132131", "113123", "113321", "133121", "313121", "211331",
+    "231131", "213113", "213311", "213131", "311123", "311321", "331121", "312113", "312311", "332111",
+    "314111", "221411", "431111", "111224", "111422", "121124", "121421", "141122", "141221", "112214",
+    "112412", "122114", "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111",
+    "111242", "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311", "113141",
+    "114131", "311141", "411131", "211412", "211214", "211232", "2331112",
+];
+
+const CODE128_SHIFT: usize = 98;
+const CODE128_STOP: usize = 106;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Code128Set {
+    A,
+    B,
+    C,
+}
+
+impl Code128Set {
+    const ALL: [Code128Set; 3] = [Code128Set::A, Code128Set::B, Code128Set::C];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn start_code(self) -> usize {
+        match self {
+            Code128Set::A => 103,
+            Code128Set::B => 104,
+            Code128Set::C => 105,
+        }
+    }
+
+    /// Codeword that switches to this set from another one
+    fn switch_code(self) -> usize {
+        match self {
+            Code128Set::A => 101,
+            Code128Set::B => 100,
+            Code128Set::C => 99,
+        }
+    }
+
+    /// Codeword for a single character, if the set can encode it
+    fn value(self, c: u8) -> Option<usize> {
+        match self {
+            Code128Set::A if c < 32 => Some(c as usize + 64),
+            Code128Set::A if c < 96 => Some(c as usize - 32),
+            Code128Set::B if (32..128).contains(&c) => Some(c as usize - 32),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Code128Step {
+    Start,
+    Switch(Code128Set),
+    Char,
+    Shifted,
+    Pair,
+}
+
+/// Encode with the fewest codewords, switching between subsets A, B and C as needed.
+fn encode_code128(text: &str) -> Result<Vec<bool>, String> {
+    let data = text.as_bytes();
+    if let Some(c) = text.chars().find(|c| !c.is_ascii()) {
+        return Err(format!("Code 128 cannot encode '{}'", c));
+    }
+
+    let n = data.len();
+    // cost[i][set]: fewest codewords to encode data[..i] ending in `set`,
+    // with the step that reached that state for backtracking
+    let mut cost = vec![[usize::MAX; 3]; n + 1];
+    let mut step = vec![[Code128Step::Start; 3]; n + 1];
+    cost[0] = [1, 1, 1];
+
+    for i in 0..=n {
+        // Switching subsets costs one codeword at the same position
+        for to in Code128Set::ALL {
+            for from in Code128Set::ALL {
+                if from != to && i > 0 && cost[i][from.index()] != usize::MAX
+                    && cost[i][from.index()] + 1 < cost[i][to.index()]
+                {
+                    cost[i][to.index()] = cost[i][from.index()] + 1;
+                    step[i][to.index()] = Code128Step::Switch(from);
+                }
+            }
+        }
+
+        if i == n {
+            break;
+        }
+
+        for set in Code128Set::ALL {
+            let c = cost[i][set.index()];
+            if c == usize::MAX {
+                continue;
+            }
+
+            let mut relax = |next: usize, added: usize, how: Code128Step| {
+                if c + added < cost[next][set.index()] {
+                    cost[next][set.index()] = c + added;
+                    step[next][set.index()] = how;
+                }
+            };
+
+            match set {
+                Code128Set::C => {
+                    if i + 1 < n && data[i].is_ascii_digit() && data[i + 1].is_ascii_digit() {
+                        relax(i + 2, 1, Code128Step::Pair);
+                    }
+                }
+                Code128Set::A | Code128Set::B => {
+                    let other = if set == Code128Set::A { Code128Set::B } else { Code128Set::A };
+                    if set.value(data[i]).is_some() {
+                        relax(i + 1, 1, Code128Step::Char);
+                    } else if other.value(data[i]).is_some() {
+                        relax(i + 1, 2, Code128Step::Shifted);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut set = Code128Set::ALL
+        .into_iter()
+        .min_by_key(|s| cost[n][s.index()])
+        .unwrap_or(Code128Set::B);
+
+    // Walk the steps back to the start, then emit codewords in order
+    let mut codewords = Vec::new();
+    let mut i = n;
+    loop {
+        match step[i][set.index()] {
+            Code128Step::Start => {
+                codewords.push(set.start_code());
+                break;
+            }
+            Code128Step::Switch(from) => {
+                codewords.push(set.switch_code());
+                set = from;
+            }
+            Code128Step::Char => {
+                codewords.push(set.value(data[i - 1]).unwrap_or(0));
+                i -= 1;
+            }
+            Code128Step::Shifted => {
+                let other = if set == Code128Set::A { Code128Set::B } else { Code128Set::A };
+                codewords.push(other.value(data[i - 1]).unwrap_or(0));
+                codewords.push(CODE128_SHIFT);
+                i -= 1;
+            }
+            Code128Step::Pair => {
+                codewords.push(((data[i - 2] - b'0') * 10 + (data[i - 1] - b'0')) as usize);
+                i -= 2;
+            }
+        }
+    }
+    codewords.reverse();
+
+    let checksum = codewords
+        .iter()
+        .enumerate()
+        .map(|(pos, &cw)| if pos == 0 { cw } else { pos * cw })
+        .sum::<usize>()
+        % 103;
+    codewords.push(checksum);
+    codewords.push(CODE128_STOP);
+
+    let mut modules = Vec::new();
+    for cw in codewords {
+        push_widths(&mut modules, CODE128_PATTERNS[cw]);
+    }
+    Ok(modules)
+}
+
+// ---------------------------------------------------------------------------
+// Code 39
+// ---------------------------------------------------------------------------
+
+const CODE39_CHARS: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ-. $/+%";
+
+// Narrow/wide pattern of each character: bar, space, bar, ... (5 bars, 4 spaces)
+const CODE39_PATTERNS: [&str; 43] = [
+    "nnnwwnwnn", "wnnwnnnnw", "nnwwnnnnw", "wnwwnnnnn", "nnnwwnnnw", "wnnwwnnnn", "nnwwwnnnn",
+    "nnnwnnwnw", "wnnwnnwnn", "nnwwnnwnn", "wnnnnwnnw", "nnwnnwnnw", "wnwnnwnnn", "nnnnwwnnw",
+    "wnnnwwnnn", "nnwnwwnnn", "nnnnnwwnw", "wnnnnwwnn", "nnwnnwwnn", "nnnnwwwnn", "wnnnnnnww",
+    "nnwnnnnww", "wnwnnnnwn", "nnnnwnnww", "wnnnwnnwn", "nnwnwnnwn", "nnnnnnwww", "wnnnnnwwn",
+    "nnwnnnwwn", "nnnnwnwwn", "wwnnnnnnw", "nwwnnnnnw", "wwwnnnnnn", "nwnnwnnnw", "wwnnwnnnn",
+    "nwwnwnnnn", "nwnnnnwnw", "wwnnnnwnn", "nwwnnnwnn", "nwnwnwnnn", "nwnwnnnwn", "nwnnnwnwn",
+    "nnnwnwnwn",
+];
+
+const CODE39_START_STOP: &str = "nwnnwnwnn";
+
+/// Wide elements are three modules wide
+const WIDE_RATIO: usize = 3;
+
+/// Append a narrow/wide pattern, starting with a bar
+fn push_narrow_wide(modules: &mut Vec<bool>, pattern: &str) {
+    for (i, element) in pattern.bytes().enumerate() {
+        let width = if element == b'w' { WIDE_RATIO } else { 1 };
+        for _ in 0..width {
+            modules.push(i % 2 == 0);
+        }
+    }
+}
+
+fn encode_code39(text: &str) -> Result<Vec<bool>, String> {
+    let mut modules = Vec::new();
+    push_narrow_wide(&mut modules, CODE39_START_STOP);
+
+    for c in text.chars() {
+        let index = CODE39_CHARS
+            .find(c)
+            .ok_or_else(|| format!("Code 39 cannot encode '{}'", c))?;
+        modules.push(false); // Inter-character gap
+        push_narrow_wide(&mut modules, CODE39_PATTERNS[index]);
+    }
+
+    modules.push(false);
+    push_narrow_wide(&mut modules, CODE39_START_STOP);
+    Ok(modules)
+}
+
+// ---------------------------------------------------------------------------
+// Code 93
+// ---------------------------------------------------------------------------
+
+const CODE93_CHARS: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ-. $/+%";
+
+const CODE93_PATTERNS: [&str; 47] = [
+    "131112", "111213", "111312", "111411", "121113", "121212", "121311", "111114", "131211", "141111",
+    "211113", "211212", "211311", "221112", "221211", "231111", "112113", "112212", "112311", "122112",
+    "132111", "111123", "111222", "111321", "121122", "131121", "212112", "212211", "211122", "211221",
+    "221121", "222111", "112122", "112221", "122121", "123111", "121131", "311112", "311211", "321111",
+    "112131", "113121", "211131", "121221", "312111", "311121", "122211",
+];
+
+const CODE93_START_STOP: &str = "111141";
+
+fn encode_code93(text: &str) -> Result<Vec<bool>, String> {
+    let mut values = text
+        .chars()
+        .map(|c| {
+            CODE93_CHARS
+                .find(c)
+                .ok_or_else(|| format!("Code 93 cannot encode '{}'", c))
+        })
+        .collect::<Result<Vec<usize>, String>>()?;
+
+    // Two modulo-47 check characters, weighted from the right
+    for max_weight in [20, 15] {
+        let sum: usize = values
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, &v)| (i % max_weight + 1) * v)
+            .sum();
+        values.push(sum % 47);
+    }
+
+    let mut modules = Vec::new();
+    push_widths(&mut modules, CODE93_START_STOP);
+    for v in values {
+        push_widths(&mut modules, CODE93_PATTERNS[v]);
+    }
+    push_widths(&mut modules, CODE93_START_STOP);
+    modules.push(true); // Termination bar
+    Ok(modules)
+}
+
+// ---------------------------------------------------------------------------
+// EAN / UPC
+// ---------------------------------------------------------------------------
+
+const EAN_L: [&str; 10] = [
+    "0001101", "0011001", "0010011", "0111101", "0100011", "0110001", "0101111", "0111011", "0110111", "0001011",
+];
+
+const EAN_G: [&str; 10] = [
+    "0100111", "0110011", "0011011", "0100001", "0011101", "0111001", "0000101", "0010001", "0001001", "0010111",
+];
+
+const EAN_R: [&str; 10] = [
+    "1110010", "1100110", "1101100", "1000010", "1011100", "1001110", "1010000", "1000100", "1001000", "1110100",
+];
+
+/// Digit groups (digit range, module span) and guard bar spans of an EAN/UPC symbol
+struct RetailLayout {
+    groups: &'static [(usize, usize, i32, i32)],
+    guards: &'static [(usize, usize)],
+    spec: PrintSpec,
+}
+
+// EAN/UPC at 80% magnification, the smallest GS1 allows
+const RETAIL_MIN_X_MM: f64 = 0.264;
+
+const EAN13_LAYOUT: RetailLayout = RetailLayout {
+    groups: &[(0, 1, -7, 0), (1, 7, 3, 45), (7, 13, 50, 92)],
+    guards: &[(0, 3), (45, 50), (92, 95)],
+    spec: PrintSpec { quiet_left: 11, quiet_right: 7, min_x_mm: RETAIL_MIN_X_MM },
+};
+
+const EAN8_LAYOUT: RetailLayout = RetailLayout {
+    groups: &[(0, 4, 3, 31), (4, 8, 36, 64)],
+    guards: &[(0, 3), (31, 36), (64, 67)],
+    spec: PrintSpec { quiet_left: 7, quiet_right: 7, min_x_mm: RETAIL_MIN_X_MM },
+};
+
+// The number system and check digits sit outside, and their bars are full height
+const UPCA_LAYOUT: RetailLayout = RetailLayout {
+    groups: &[(0, 1, -7, 0), (1, 6, 10, 45), (6, 11, 50, 85), (11, 12, 95, 102)],
+    guards: &[(0, 10), (45, 50), (85, 95)],
+    spec: PrintSpec { quiet_left: 9, quiet_right: 9, min_x_mm: RETAIL_MIN_X_MM },
+};
+
+const UPCE_LAYOUT: RetailLayout = RetailLayout {
+    groups: &[(0, 1, -7, 0), (1, 7, 3, 45), (7, 8, 51, 58)],
+    guards: &[(0, 3), (45, 51)],
+    spec: PrintSpec { quiet_left: 9, quiet_right: 7, min_x_mm: RETAIL_MIN_X_MM },
+};
+
+// L/G parity of the left half, selected by the first EAN-13 digit
+const EAN13_PARITY: [&str; 10] = [
+    "LLLLLL", "LLGLGG", "LLGGLG", "LLGGGL", "LGLLGG", "LGGLLG", "LGGGLL", "LGLGLG", "LGLGGL", "LGGLGL",
+];
+
+// Odd (L) / even (G) parity of UPC-E digits for number system 0, selected by the check digit
+const UPCE_PARITY: [&str; 10] = [
+    "GGGLLL", "GGLGLL", "GGLLGL", "GGLLLG", "GLGGLL", "GLLGGL", "GLLLGG", "GLGLGL", "GLGLLG", "GLLGLG",
+];
+
+fn push_parity_digit(modules: &mut Vec<bool>, digit: u8, parity: u8) {
+    let table = if parity == b'G' { &EAN_G } else { &EAN_L };
+    push_bits(modules, table[digit as usize]);
+}
+
+fn encode_ean13(d: &[u8]) -> Result<Vec<bool>, String> {
+    let mut modules = Vec::new();
+    push_bits(&mut modules, "101");
+    for (i, parity) in EAN13_PARITY[d[0] as usize].bytes().enumerate() {
+        push_parity_digit(&mut modules, d[i + 1], parity);
+    }
+    push_bits(&mut modules, "01010");
+    for &digit in &d[7..13] {
+        push_bits(&mut modules, EAN_R[digit as usize]);
+    }
+    push_bits(&mut modules, "101");
+    Ok(modules)
+}
+
+fn encode_ean8(d: &[u8]) -> Result<Vec<bool>, String> {
+    let mut modules = Vec::new();
+    push_bits(&mut modules, "101");
+    for &digit in &d[..4] {
+        push_bits(&mut modules, EAN_L[digit as usize]);
+    }
+    push_bits(&mut modules, "01010");
+    for &digit in &d[4..] {
+        push_bits(&mut modules, EAN_R[digit as usize]);
+    }
+    push_bits(&mut modules, "101");
+    Ok(modules)
+}
+
+/// `d` is number system, six data digits and check digit
+fn encode_upce(d: &[u8]) -> Result<Vec<bool>, String> {
+    if d[0] > 1 {
+        return Err(format!("UPC-E number system must be 0 or 1, got {}", d[0]));
+    }
+
+    let mut modules = Vec::new();
+    push_bits(&mut modules, "101");
+    for (i, parity) in UPCE_PARITY[d[7] as usize].bytes().enumerate() {
+        // Number system 1 uses the inverted parity pattern
+        let parity = match (d[0], parity) {
+            (1, b'G') => b'L',
+            (1, _) => b'G',
+            (_, p) => p,
+        };
+        push_parity_digit(&mut modules, d[i + 1], parity);
+    }
+    push_bits(&mut modules, "010101");
+    Ok(modules)
+}
+
+// ---------------------------------------------------------------------------
+// Interleaved 2 of 5 (ITF-14)
+// ---------------------------------------------------------------------------
+
+const ITF_PATTERNS: [&str; 10] = [
+    "nnwwn", "wnnnw", "nwnnw", "wwnnn", "nnwnw", "wnwnn", "nwwnn", "nnnww", "wnnwn", "nwnwn",
+];
+
+fn encode_itf(d: &[u8]) -> Result<Vec<bool>, String> {
+    let mut modules = Vec::new();
+    push_narrow_wide(&mut modules, "nnnn");
+
+    // Each digit pair interleaves the first digit's bars with the second digit's spaces
+    for pair in d.chunks(2) {
+        let bars = ITF_PATTERNS[pair[0] as usize].as_bytes();
+        let spaces = ITF_PATTERNS[pair[1] as usize].as_bytes();
+        let mut interleaved = String::with_capacity(10);
+        for k in 0..5 {
+            interleaved.push(bars[k] as char);
+            interleaved.push(spaces[k] as char);
+        }
+        push_narrow_wide(&mut modules, &interleaved);
+    }
+
+    push_narrow_wide(&mut modules, "wnn");
+    Ok(modules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(modules: &[bool]) -> String {
+        modules.iter().map(|&bar| if bar { '1' } else { '0' }).collect()
+    }
+
+    /// Code 128 symbol characters, read back from their bar/space widths.
+    /// Characters are 11 modules wide and the stop pattern 13.
+    fn code128_codewords(modules: &[bool]) -> Vec<usize> {
+        let (characters, stop) = modules.split_at(modules.len() - 13);
+        characters
+            .chunks(11)
+            .chain([stop])
+            .map(|chunk| {
+                let widths: String = chunk
+                    .chunk_by(|a, b| a == b)
+                    .map(|run| char::from(b'0' + run.len() as u8))
+                    .collect();
+                CODE128_PATTERNS.iter().position(|pattern| *pattern == widths).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn encodes_ean13() {
+        let symbol = encode("4006381333931", "ean13").unwrap();
+        // Parity LGLLGG for the leading 4, then right-hand codes
+        let expected = [
+            "101",
+            "0001101", "0100111", "0101111", "0111101", "0001001", "0110011",
+            "01010",
+            "1000010", "1000010", "1000010", "1110100", "1000010", "1100110",
+            "101",
+        ];
+        assert_eq!(bits(&symbol.modules), expected.concat());
+        let hri: Vec<&str> = symbol.hri.iter().map(|h| h.text.as_str()).collect();
+        assert_eq!(hri, ["4", "006381", "333931"]);
+    }
+
+    #[test]
+    fn appends_missing_check_digit() {
+        let short = encode("400638133393", "ean13").unwrap();
+        let full = encode("4006381333931", "ean13").unwrap();
+        assert_eq!(short.modules, full.modules);
+    }
+
+    #[test]
+    fn expands_upce_to_upca() {
+        // 0 425261 is UPC-A 0 42100 00526, check digit 4
+        assert_eq!(upce_to_upca(&[0, 4, 2, 5, 2, 6, 1]), [0, 4, 2, 1, 0, 0, 0, 0, 5, 2, 6]);
+        assert_eq!(upce_to_upca(&[0, 1, 2, 3, 4, 5, 3]), [0, 1, 2, 3, 0, 0, 0, 0, 0, 4, 5]);
+        assert_eq!(upce_to_upca(&[0, 1, 2, 3, 4, 5, 4]), [0, 1, 2, 3, 4, 0, 0, 0, 0, 0, 5]);
+        assert_eq!(upce_to_upca(&[0, 1, 2, 3, 4, 5, 9]), [0, 1, 2, 3, 4, 5, 0, 0, 0, 0, 9]);
+        assert_eq!(upce_check_digit(&[0, 4, 2, 5, 2, 6, 1]), 4);
+        assert!(encode("04252614", "upce").is_ok());
+    }
+
+    #[test]
+    fn switches_code128_to_set_c_after_odd_digit() {
+        let modules = encode_code128("ab1234567").unwrap();
+        // Start B, a, b, 1, Code C, 23, 45, 67, checksum, stop
+        assert_eq!(code128_codewords(&modules), [104, 65, 66, 17, 99, 23, 45, 67, 57, CODE128_STOP]);
+    }
+
+    #[test]
+    fn rejects_wrong_check_digit() {
+        let error = encode("4006381333932", "ean13").err().unwrap();
+        assert_eq!(error, "EAN-13 value '4006381333932' has check digit 2, expected 1");
+        assert!(encode("04252615", "upce").is_err());
+    }
+
+    #[test]
+    fn computes_gs1_check_digits() {
+        assert_eq!(gs1_check_digit(&[9, 6, 3, 8, 5, 0, 7]), 4);
+        assert_eq!(gs1_check_digit(&[0, 3, 6, 0, 0, 0, 2, 9, 1, 4, 5]), 2);
+        assert_eq!(gs1_check_digit(&[1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0]), 2);
+        assert!(encode("96385074", "ean8").is_ok());
+        assert!(encode("036000291452", "upca").is_ok());
+        assert!(encode("10012345678902", "itf14").is_ok());
+        assert!(encode("10012345678903", "itf14").is_err());
+    }
+
+    #[test]
+    fn reports_quiet_zones_and_minimum_x_dimension() {
+        let spec = |value: &str, format: &str| {
+            let symbol = encode(value, format).unwrap();
+            (symbol.quiet_left, symbol.quiet_right, symbol.min_x_mm)
+        };
+        // EAN/UPC quiet zones are asymmetric and allow 80% magnification
+        assert_eq!(spec("4006381333931", "ean13"), (11, 7, 0.264));
+        assert_eq!(spec("96385074", "ean8"), (7, 7, 0.264));
+        assert_eq!(spec("036000291452", "upca"), (9, 9, 0.264));
+        assert_eq!(spec("04252614", "upce"), (9, 7, 0.264));
+        assert_eq!(spec("ABC-123", "code128"), (10, 10, 0.191));
+        assert_eq!(spec("ABC-123", "code39"), (10, 10, 0.191));
+        assert_eq!(spec("10012345678902", "itf14"), (10, 10, 0.495));
+    }
+}
@@ -0,0 +1,24 @@
+/// A grid of dark (true) and light modules produced by a 2D symbology
+pub struct BitMatrix {
+    pub width: usize,
+    pub height: usize,
+    modules: Vec<bool>,
+}
+
+impl BitMatrix {
+    pub fn new(width: usize, height: usize) -> Self {
+        BitMatrix {
+            width,
+            height,
+            modules: vec![false; width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.width + x] = dark;
+    }
+}
@@ -0,0 +1,417 @@
+// DataMatrix ECC200 encoder (ASCII encodation).
+
+use crate::bit_matrix::BitMatrix;
+use crate::reed_solomon::GaloisField;
+
+struct SymbolSize {
+    rows: usize,
+    cols: usize,
+    /// Data region size, excluding finder and timing patterns
+    region_rows: usize,
+    region_cols: usize,
+    data_codewords: usize,
+    ecc_codewords: usize,
+    /// Number of interleaved Reed-Solomon blocks
+    blocks: usize,
+}
+
+const fn size(
+    rows: usize,
+    cols: usize,
+    region_rows: usize,
+    region_cols: usize,
+    data_codewords: usize,
+    ecc_codewords: usize,
+    blocks: usize,
+) -> SymbolSize {
+    SymbolSize { rows, cols, region_rows, region_cols, data_codewords, ecc_codewords, blocks }
+}
+
+const SQUARE_SIZES: [SymbolSize; 24] = [
+    size(10, 10, 8, 8, 3, 5, 1),
+    size(12, 12, 10, 10, 5, 7, 1),
+    size(14, 14, 12, 12, 8, 10, 1),
+    size(16, 16, 14, 14, 12, 12, 1),
+    size(18, 18, 16, 16, 18, 14, 1),
+    size(20, 20, 18, 18, 22, 18, 1),
+    size(22, 22, 20, 20, 30, 20, 1),
+    size(24, 24, 22, 22, 36, 24, 1),
+    size(26, 26, 24, 24, 44, 28, 1),
+    size(32, 32, 14, 14, 62, 36, 1),
+    size(36, 36, 16, 16, 86, 42, 1),
+    size(40, 40, 18, 18, 114, 48, 1),
+    size(44, 44, 20, 20, 144, 56, 1),
+    size(48, 48, 22, 22, 174, 68, 1),
+    size(52, 52, 24, 24, 204, 84, 2),
+    size(64, 64, 14, 14, 280, 112, 2),
+    size(72, 72, 16, 16, 368, 144, 4),
+    size(80, 80, 18, 18, 456, 192, 4),
+    size(88, 88, 20, 20, 576, 224, 4),
+    size(96, 96, 22, 22, 696, 272, 4),
+    size(104, 104, 24, 24, 816, 336, 6),
+    size(120, 120, 18, 18, 1050, 408, 6),
+    size(132, 132, 20, 20, 1304, 496, 8),
+    size(144, 144, 22, 22, 1558, 620, 10),
+];
+
+const RECTANGULAR_SIZES: [SymbolSize; 6] = [
+    size(8, 18, 6, 16, 5, 7, 1),
+    size(8, 32, 6, 14, 10, 11, 1),
+    size(12, 26, 10, 24, 16, 14, 1),
+    size(12, 36, 10, 16, 22, 18, 1),
+    size(16, 36, 14, 16, 32, 24, 1),
+    size(16, 48, 14, 22, 49, 28, 1),
+];
+
+const PAD: u8 = 129;
+const UPPER_SHIFT: u8 = 235;
+
+/// Encode `text` into the smallest square (or rectangular) ECC200 symbol that fits
+pub fn encode(text: &str, rectangular: bool) -> Result<BitMatrix, String> {
+    let mut codewords = encode_ascii(text.as_bytes());
+
+    let sizes: &[SymbolSize] = if rectangular { &RECTANGULAR_SIZES } else { &SQUARE_SIZES };
+    let symbol = sizes
+        .iter()
+        .find(|s| s.data_codewords >= codewords.len())
+        .ok_or_else(|| format!("DataMatrix: {} codewords exceed the largest symbol", codewords.len()))?;
+
+    pad(&mut codewords, symbol.data_codewords);
+    let all = add_error_correction(&codewords, symbol);
+    Ok(place_symbol(&all, symbol))
+}
+
+/// Fill the symbol's remaining data capacity. The first pad is 129, later
+/// pads are randomized by position.
+fn pad(codewords: &mut Vec<u8>, capacity: usize) {
+    if codewords.len() < capacity {
+        codewords.push(PAD);
+    }
+    while codewords.len() < capacity {
+        let position = codewords.len() + 1;
+        let mut pad = PAD as usize + (149 * position) % 253 + 1;
+        if pad > 254 {
+            pad -= 254;
+        }
+        codewords.push(pad as u8);
+    }
+}
+
+fn encode_ascii(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let c = data[i];
+        if c.is_ascii_digit() && i + 1 < data.len() && data[i + 1].is_ascii_digit() {
+            // Digit pairs pack into a single codeword
+            out.push(130 + (c - b'0') * 10 + (data[i + 1] - b'0'));
+            i += 2;
+            continue;
+        }
+        if c >= 128 {
+            out.push(UPPER_SHIFT);
+            out.push(c - 127);
+        } else {
+            out.push(c + 1);
+        }
+        i += 1;
+    }
+    out
+}
+
+/// Append error correction, interleaving data and ECC across the symbol's blocks
+fn add_error_correction(data: &[u8], symbol: &SymbolSize) -> Vec<u8> {
+    let field = GaloisField::new(0x12D, 256);
+    let ecc_per_block = symbol.ecc_codewords / symbol.blocks;
+
+    let mut out = data.to_vec();
+    out.resize(data.len() + symbol.ecc_codewords, 0);
+
+    for block in 0..symbol.blocks {
+        let block_data: Vec<u16> = data.iter().skip(block).step_by(symbol.blocks).map(|&d| d as u16).collect();
+        let ecc = field.encode(&block_data, ecc_per_block);
+        for (j, e) in ecc.into_iter().enumerate() {
+            out[data.len() + j * symbol.blocks + block] = e as u8;
+        }
+    }
+    out
+}
+
+/// Lay codewords out in the ECC200 "utah" pattern, then add finder and timing patterns
+fn place_symbol(codewords: &[u8], symbol: &SymbolSize) -> BitMatrix {
+    let regions_v = symbol.rows / (symbol.region_rows + 2);
+    let regions_h = symbol.cols / (symbol.region_cols + 2);
+    let nrow = regions_v * symbol.region_rows;
+    let ncol = regions_h * symbol.region_cols;
+
+    let mut placement = Placement {
+        nrow: nrow as i32,
+        ncol: ncol as i32,
+        bits: vec![None; nrow * ncol],
+        codewords,
+    };
+    placement.run();
+
+    let mut matrix = BitMatrix::new(symbol.cols, symbol.rows);
+    for r in 0..nrow {
+        for c in 0..ncol {
+            let y = r + 1 + 2 * (r / symbol.region_rows);
+            let x = c + 1 + 2 * (c / symbol.region_cols);
+            matrix.set(x, y, placement.bits[r * ncol + c].unwrap_or(false));
+        }
+    }
+
+    // Each region has a solid L on its left and bottom edges and alternating timing on top and right
+    for ry in 0..regions_v {
+        let top = ry * (symbol.region_rows + 2);
+        let bottom = top + symbol.region_rows + 1;
+        for x in 0..symbol.cols {
+            matrix.set(x, top, x % 2 == 0);
+            matrix.set(x, bottom, true);
+        }
+    }
+    for rx in 0..regions_h {
+        let left = rx * (symbol.region_cols + 2);
+        let right = left + symbol.region_cols + 1;
+        for y in 0..symbol.rows {
+            matrix.set(left, y, true);
+            matrix.set(right, y, y % 2 == 1);
+        }
+    }
+
+    matrix
+}
+
+/// ECC200 module placement (ISO/IEC 16022 Annex F)
+struct Placement<'a> {
+    nrow: i32,
+    ncol: i32,
+    bits: Vec<Option<bool>>,
+    codewords: &'a [u8],
+}
+
+impl Placement<'_> {
+    fn run(&mut self) {
+        let (nrow, ncol) = (self.nrow, self.ncol);
+        let mut chr = 0;
+        let mut row = 4;
+        let mut col = 0;
+
+        loop {
+            if row == nrow && col == 0 {
+                self.corner(chr, &[(nrow - 1, 0), (nrow - 1, 1), (nrow - 1, 2), (0, ncol - 2), (0, ncol - 1), (1, ncol - 1), (2, ncol - 1), (3, ncol - 1)]);
+                chr += 1;
+            }
+            if row == nrow - 2 && col == 0 && ncol % 4 != 0 {
+                self.corner(chr, &[(nrow - 3, 0), (nrow - 2, 0), (nrow - 1, 0), (0, ncol - 4), (0, ncol - 3), (0, ncol - 2), (0, ncol - 1), (1, ncol - 1)]);
+                chr += 1;
+            }
+            if row == nrow - 2 && col == 0 && ncol % 8 == 4 {
+                self.corner(chr, &[(nrow - 3, 0), (nrow - 2, 0), (nrow - 1, 0), (0, ncol - 2), (0, ncol - 1), (1, ncol - 1), (2, ncol - 1), (3, ncol - 1)]);
+                chr += 1;
+            }
+            if row == nrow + 4 && col == 2 && ncol % 8 == 0 {
+                self.corner(chr, &[(nrow - 1, 0), (nrow - 1, ncol - 1), (0, ncol - 3), (0, ncol - 2), (0, ncol - 1), (1, ncol - 3), (1, ncol - 2), (1, ncol - 1)]);
+                chr += 1;
+            }
+
+            // Sweep up and to the right
+            loop {
+                if row < nrow && col >= 0 && self.bits[(row * ncol + col) as usize].is_none() {
+                    self.utah(row, col, chr);
+                    chr += 1;
+                }
+                row -= 2;
+                col += 2;
+                if row < 0 || col >= ncol {
+                    break;
+                }
+            }
+            row += 1;
+            col += 3;
+
+            // Sweep down and to the left
+            loop {
+                if row >= 0 && col < ncol && self.bits[(row * ncol + col) as usize].is_none() {
+                    self.utah(row, col, chr);
+                    chr += 1;
+                }
+                row += 2;
+                col -= 2;
+                if row >= nrow || col < 0 {
+                    break;
+                }
+            }
+            row += 3;
+            col += 1;
+
+            if row >= nrow && col >= ncol {
+                break;
+            }
+        }
+
+        // Unfilled bottom-right corner gets a fixed pattern
+        let last = (nrow * ncol - 1) as usize;
+        if self.bits[last].is_none() {
+            self.bits[last] = Some(true);
+            self.bits[last - ncol as usize - 1] = Some(true);
+        }
+    }
+
+    fn module(&mut self, mut row: i32, mut col: i32, chr: usize, bit: u8) {
+        if row < 0 {
+            row += self.nrow;
+            col += 4 - ((self.nrow + 4) % 8);
+        }
+        if col < 0 {
+            col += self.ncol;
+            row += 4 - ((self.ncol + 4) % 8);
+        }
+        let value = self.codewords.get(chr).copied().unwrap_or(0);
+        self.bits[(row * self.ncol + col) as usize] = Some(value & (0x80 >> bit) != 0);
+    }
+
+    /// Standard 8-module codeword shape with its bottom-right module at (row, col)
+    fn utah(&mut self, row: i32, col: i32, chr: usize) {
+        let shape = [
+            (row - 2, col - 2), (row - 2, col - 1),
+            (row - 1, col - 2), (row - 1, col - 1), (row - 1, col),
+            (row, col - 2), (row, col - 1), (row, col),
+        ];
+        self.corner(chr, &shape);
+    }
+
+    fn corner(&mut self, chr: usize, modules: &[(i32, i32); 8]) {
+        for (bit, &(row, col)) in modules.iter().enumerate() {
+            self.module(row, col, chr, bit as u8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(matrix: &BitMatrix) -> Vec<String> {
+        (0..matrix.height)
+            .map(|y| (0..matrix.width).map(|x| if matrix.get(x, y) { 'X' } else { '.' }).collect())
+            .collect()
+    }
+
+    #[test]
+    fn encodes_123456() {
+        // ISO/IEC 16022 example: digit pairs, then five check codewords
+        let codewords = encode_ascii(b"123456");
+        assert_eq!(codewords, [142, 164, 186]);
+        assert_eq!(add_error_correction(&codewords, &SQUARE_SIZES[0]), [142, 164, 186, 114, 25, 5, 88, 102]);
+
+        let expected = [
+            "X.X.X.X.X.",
+            "XX..X.XX.X",
+            "XX.....X..",
+            "XX...XXX.X",
+            "XX....X...",
+            "X.....XXXX",
+            "XXX.XX....",
+            "XXXX.XX..X",
+            "X..XXX.X..",
+            "XXXXXXXXXX",
+        ];
+        assert_eq!(rows(&encode("123456", false).unwrap()), expected);
+    }
+
+    #[test]
+    fn encodes_hello_world() {
+        let mut codewords = encode_ascii(b"Hello World");
+        assert_eq!(codewords, [73, 102, 109, 109, 112, 33, 88, 112, 115, 109, 101]);
+        pad(&mut codewords, 12);
+        assert_eq!(codewords[11], PAD);
+
+        let expected = [
+            "X.X.X.X.X.X.X.X.",
+            "X.XX.XX.X.XX...X",
+            "XX...XX.XX......",
+            "XXX.XX.X..XX...X",
+            "XX........XXXXX.",
+            "XXX...X..X....XX",
+            "XX..XX.....X....",
+            "XXX.XX.X..X....X",
+            "X..X.XXXXXXX....",
+            "X.XX.X..XXX.XX.X",
+            "XXX.X..X...XXX..",
+            "X.XX.XXX....XX.X",
+            "X..X.XXX.X.XXX..",
+            "XXXX..X.X....X.X",
+            "XXXXXX.X.XXX..X.",
+            "XXXXXXXXXXXXXXXX",
+        ];
+        assert_eq!(rows(&encode("Hello World", false).unwrap()), expected);
+    }
+
+    #[test]
+    fn randomizes_pads_after_the_first() {
+        let mut codewords = encode_ascii(b"A");
+        pad(&mut codewords, 5);
+        // 129 + (149 * 3 % 253 + 1) wraps past 254
+        assert_eq!(codewords, [66, 129, 70, 220, 115]);
+    }
+
+    #[test]
+    fn shifts_bytes_above_ascii() {
+        // UTF-8 'é' is C3 A9
+        assert_eq!(encode_ascii("é".as_bytes()), [UPPER_SHIFT, 68, UPPER_SHIFT, 42]);
+        assert_eq!(encode_ascii(b"1a23"), [50, 98, 153]);
+    }
+
+    #[test]
+    fn picks_the_smallest_symbol() {
+        let size = |text: &str, rectangular| {
+            let matrix = encode(text, rectangular).unwrap();
+            (matrix.width, matrix.height)
+        };
+        assert_eq!(size("123456", false), (10, 10));
+        assert_eq!(size("123456", true), (18, 8));
+        assert_eq!(size("Hello, wristband 0001", true), (36, 12));
+        assert_eq!(size(&"9".repeat(3116), false), (144, 144));
+        assert!(encode(&"9".repeat(3118), false).is_err());
+    }
+
+    #[test]
+    fn places_every_module_of_every_size() {
+        // The utah shapes and the four corner patterns must tile the mapping
+        // matrix exactly; only the fixed 2x2 corner may be left over
+        for symbol in SQUARE_SIZES.iter().chain(&RECTANGULAR_SIZES) {
+            let nrow = symbol.rows / (symbol.region_rows + 2) * symbol.region_rows;
+            let ncol = symbol.cols / (symbol.region_cols + 2) * symbol.region_cols;
+            let codewords = symbol.data_codewords + symbol.ecc_codewords;
+            let spare = nrow * ncol - 8 * codewords;
+            assert!(spare == 0 || spare == 4, "{}x{}", symbol.rows, symbol.cols);
+
+            // Every codeword is 0xFF, so each module placed twice would leave another unset
+            let all = vec![0xFF; codewords];
+            let mut placement = Placement { nrow: nrow as i32, ncol: ncol as i32, bits: vec![None; nrow * ncol], codewords: &all };
+            placement.run();
+            let unset = placement.bits.iter().filter(|bit| bit.is_none()).count();
+            let dark = placement.bits.iter().filter(|bit| **bit == Some(true)).count();
+            assert_eq!((unset, dark), (spare / 2, 8 * codewords + spare / 2), "{}x{}", symbol.rows, symbol.cols);
+        }
+    }
+
+    #[test]
+    fn interleaves_144x144_blocks() {
+        // 1558 data codewords in ten blocks: the first eight hold 156, the last two 155
+        let symbol = &SQUARE_SIZES[23];
+        let data: Vec<u8> = (0..symbol.data_codewords).map(|i| (i * 7 % 256) as u8).collect();
+        let all = add_error_correction(&data, symbol);
+        assert_eq!(all.len(), 1558 + 620);
+
+        let field = GaloisField::new(0x12D, 256);
+        for block in 0..10 {
+            let block_data: Vec<u16> = all[..1558].iter().skip(block).step_by(10).map(|&c| c as u16).collect();
+            let block_ecc: Vec<u16> = all[1558..].iter().skip(block).step_by(10).map(|&c| c as u16).collect();
+            assert_eq!(block_data.len(), if block < 8 { 156 } else { 155 });
+            assert_eq!(block_ecc.len(), 62);
+            assert!(field.is_codeword(&[block_data, block_ecc].concat(), 62), "block {}", block);
+        }
+    }
+}
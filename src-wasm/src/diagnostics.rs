@@ -0,0 +1,38 @@
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// A non-fatal issue found while rendering, reported back to the caller as JSON
+#[derive(Serialize, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Stable identifier of the kind of issue, e.g. "barcode_x_dimension"
+    pub code: &'static str,
+    #[serde(rename = "stampId")]
+    pub stamp_id: String,
+    /// Record the issue applies to; `None` when it holds for every record
+    #[serde(rename = "recordIndex")]
+    pub record_index: Option<usize>,
+    pub message: String,
+}
+
+/// Diagnostics of a render job. Record-independent issues are reported once
+/// per stamp for the whole job, however many tickets raise them.
+#[derive(Default)]
+pub struct Diagnostics {
+    list: Vec<Diagnostic>,
+    /// Code and stamp of every record-independent issue reported so far
+    reported: HashSet<(&'static str, String)>,
+}
+
+impl Diagnostics {
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        if diagnostic.record_index.is_none() && !self.reported.insert((diagnostic.code, diagnostic.stamp_id.clone())) {
+            return;
+        }
+        self.list.push(diagnostic);
+    }
+
+    /// Diagnostics pushed since the last call
+    pub fn take(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.list)
+    }
+}
@@ -0,0 +1,171 @@
+// Drawing targets for tickets: the pixels of an image, or vector operations
+// written out as PDF content.
+
+use image::{Rgba, RgbaImage};
+use std::borrow::Cow;
+
+use crate::mask::Mask;
+use crate::text_layout::{LineLayout, PositionedGlyph, TextFont};
+use crate::ticket_renderer::{composite_image, fill_rect};
+
+/// Affine transform `[a, b, c, d, e, f]` mapping (x, y) to
+/// (a x + c y + e, b x + d y + f), as a PDF `cm` operator does
+pub type Transform = [f32; 6];
+
+pub fn translation(x: f32, y: f32) -> Transform {
+    [1.0, 0.0, 0.0, 1.0, x, y]
+}
+
+/// Clockwise rotation by `degrees` around `pivot`, in y-down pixel space
+pub fn rotation(pivot: (f32, f32), degrees: f32) -> Transform {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (x, y) = pivot;
+    [cos, sin, -sin, cos, x - x * cos + y * sin, y - x * sin - y * cos]
+}
+
+/// How glyph outlines are painted
+#[derive(Clone, Copy, PartialEq)]
+pub enum Paint {
+    Fill(Rgba<u8>),
+    /// Outline reaching `width` pixels outside the glyphs
+    Stroke(Rgba<u8>, f32),
+}
+
+/// A glyph by its id in the font, with its origin on the baseline
+pub struct Glyph {
+    pub id: u16,
+    pub x: f32,
+    pub y: f32,
+}
+
+/// Vector drawing operation, in the pixels of the ticket (y pointing down)
+pub enum DrawOp<'f> {
+    Rect { x: f32, y: f32, width: f32, height: f32, color: Rgba<u8> },
+    /// Image stretched over a box
+    Image { image: Cow<'f, RgbaImage>, x: f32, y: f32, width: f32, height: f32 },
+    /// Glyphs of one font file, `size` pixels per em
+    Text { font: &'f [u8], size: f32, paint: Paint, glyphs: Vec<Glyph> },
+    /// Start a group drawn through `transform`, clipped to an `[x, y, width,
+    /// height]` box in the transformed space; closed by `End`
+    Begin { transform: Transform, clip: Option<[f32; 4]> },
+    End,
+}
+
+/// Operations drawing one page, in order
+#[derive(Default)]
+pub struct Drawing<'f> {
+    pub ops: Vec<DrawOp<'f>>,
+}
+
+impl<'f> Drawing<'f> {
+    /// Add the glyphs of a shaped line starting at `x`, with the baseline at
+    /// `baseline_y`. `style` gives the font and paint of each glyph.
+    pub fn push_line<'s>(
+        &mut self,
+        line: &LineLayout,
+        x: f32,
+        baseline_y: f32,
+        style: impl Fn(&PositionedGlyph) -> (&'s TextFont<'f>, Paint),
+    ) where
+        'f: 's,
+    {
+        for positioned in &line.glyphs {
+            let (font, paint) = style(positioned);
+            let size = font_size(font, positioned);
+            let glyph = Glyph { id: positioned.id.0, x: x + positioned.x, y: baseline_y + positioned.y };
+            match self.ops.last_mut() {
+                Some(DrawOp::Text { font: data, size: s, paint: p, glyphs })
+                    if std::ptr::eq(*data, font.data) && *s == size && *p == paint =>
+                {
+                    glyphs.push(glyph);
+                }
+                _ => self.ops.push(DrawOp::Text { font: font.data, size, paint, glyphs: vec![glyph] }),
+            }
+        }
+    }
+}
+
+/// Pixels per em of a glyph, the font size a PDF sets it at
+fn font_size(font: &TextFont, glyph: &PositionedGlyph) -> f32 {
+    use ab_glyph::{Font, ScaleFont};
+    let scaled = font.glyphs.as_scaled(glyph.scale);
+    scaled.h_scale_factor() * font.glyphs.units_per_em().unwrap_or(1000.0)
+}
+
+/// Where stamps are drawn
+pub enum Canvas<'c, 'f> {
+    Raster(&'c mut RgbaImage),
+    Vector(&'c mut Drawing<'f>),
+}
+
+impl<'f> Canvas<'_, 'f> {
+    /// Blend `color` over a box; on images, over the pixels whose centres fall inside it
+    pub fn fill_rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: Rgba<u8>) {
+        match self {
+            Canvas::Raster(img) => fill_rect(img, x, y, x + width, y + height, color),
+            Canvas::Vector(drawing) => drawing.ops.push(DrawOp::Rect { x, y, width, height, color }),
+        }
+    }
+
+    /// Blend `color` through a coverage mask moved by (dx, dy); vector
+    /// drawings embed it as an image
+    pub fn fill_mask(&mut self, mask: &Mask, dx: i32, dy: i32, color: Rgba<u8>) {
+        match self {
+            Canvas::Raster(img) => mask.fill(img, dx, dy, color),
+            Canvas::Vector(drawing) => {
+                if mask.width == 0 || mask.height == 0 {
+                    return;
+                }
+                let mut image = RgbaImage::new(mask.width as u32, mask.height as u32);
+                mask.fill(&mut image, -mask.x, -mask.y, color);
+                drawing.ops.push(DrawOp::Image {
+                    image: Cow::Owned(image),
+                    x: (mask.x + dx) as f32,
+                    y: (mask.y + dy) as f32,
+                    width: mask.width as f32,
+                    height: mask.height as f32,
+                });
+            }
+        }
+    }
+
+    /// Draw `image` at its pixel size with its top-left corner at (x, y)
+    pub fn draw_image(&mut self, image: Cow<'f, RgbaImage>, x: u32, y: u32) {
+        match self {
+            Canvas::Raster(img) => composite_image(img, &image, x, y),
+            Canvas::Vector(drawing) => {
+                let (width, height) = (image.width() as f32, image.height() as f32);
+                drawing.ops.push(DrawOp::Image { image, x: x as f32, y: y as f32, width, height });
+            }
+        }
+    }
+
+    /// Fill a shaped line starting at `x`, with the baseline at `baseline_y`.
+    /// Fonts set to a variable instance other than the default can't be
+    /// embedded as drawn, so their glyphs are rasterized on vector drawings too.
+    pub fn draw_glyphs(&mut self, fonts: &[TextFont<'f>], line: &LineLayout, x: f32, baseline_y: f32, color: Rgba<u8>) {
+        if let Canvas::Vector(drawing) = self {
+            if fonts.iter().all(TextFont::is_default_instance) {
+                drawing.push_line(line, x, baseline_y, |glyph| (&fonts[glyph.font], Paint::Fill(color)));
+                return;
+            }
+        }
+
+        let size = line.glyphs.iter().map(|glyph| glyph.scale.y).fold(0.0, f32::max);
+        let mask_x = (x - size).floor() as i32;
+        let mask_y = (baseline_y - 2.0 * size).floor() as i32;
+        let mut mask = Mask::new(mask_x, mask_y, (line.width + 2.0 * size).ceil() as usize + 1, (3.0 * size).ceil() as usize + 1);
+        for glyph in &line.glyphs {
+            mask.add_glyph(&fonts[glyph.font], glyph, x, baseline_y);
+        }
+        self.fill_mask(&mask, 0, 0, color);
+    }
+
+    /// Mean colour under a box of an image canvas; vector drawings have no pixels to sample
+    pub fn average_color(&self, x: u32, y: u32, width: u32, height: u32) -> Option<Rgba<u8>> {
+        match self {
+            Canvas::Raster(img) => Some(crate::ticket_renderer::average_color(img, x, y, width, height)),
+            Canvas::Vector(_) => None,
+        }
+    }
+}
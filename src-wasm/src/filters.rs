@@ -0,0 +1,301 @@
+// Template filters: `{{value | name:arg:arg}}`.
+
+#[derive(Clone, Debug)]
+pub enum Filter {
+    /// Left-pad to `width` characters with `fill` (default '0')
+    Pad { width: usize, fill: char },
+    Upper,
+    Lower,
+    Trim,
+    /// Number with two decimals (none for JPY), thousands separators and the
+    /// currency's symbol, or its code when the symbol isn't known
+    Currency(String),
+    /// Reformat an ISO date (`YYYY-MM-DD`, optionally with `THH:MM[:SS]`)
+    /// using strftime-style fields
+    Format(String),
+    /// Characters `start..end`; negative indices count from the end
+    Slice { start: i64, end: Option<i64> },
+    /// At most `length` characters, ending in `suffix` (default '…') when cut
+    Truncate { length: usize, suffix: String },
+    /// `fallback` in place of an empty (or all-whitespace) value
+    Default(String),
+}
+
+impl Filter {
+    /// The fewest and most arguments filter `name` takes, if it exists
+    pub fn arity(name: &str) -> Option<(usize, usize)> {
+        Some(match name {
+            "upper" | "lower" | "trim" => (0, 0),
+            "currency" | "format" | "default" => (1, 1),
+            "pad" | "slice" | "truncate" => (1, 2),
+            _ => return None,
+        })
+    }
+
+    pub fn parse(name: &str, args: &[String]) -> Result<Self, String> {
+        let (min, max) = Self::arity(name).ok_or_else(|| format!("Unknown filter '{}'", name))?;
+        if args.len() < min || args.len() > max {
+            return Err(format!("Filter '{}' takes {} argument(s), got {}", name, arity_text(min, max), args.len()));
+        }
+        let number = |arg: &str| arg.parse::<i64>().map_err(|_| format!("Filter '{}': '{}' is not a whole number", name, arg));
+        let count = |arg: &str| arg.parse::<usize>().map_err(|_| format!("Filter '{}': '{}' is not a count", name, arg));
+
+        Ok(match name {
+            "pad" => {
+                let fill = match args.get(1) {
+                    Some(fill) if fill.chars().count() == 1 => fill.chars().next().unwrap_or('0'),
+                    Some(fill) => return Err(format!("Filter 'pad': fill '{}' must be one character", fill)),
+                    None => '0',
+                };
+                Filter::Pad { width: count(&args[0])?, fill }
+            }
+            "upper" => Filter::Upper,
+            "lower" => Filter::Lower,
+            "trim" => Filter::Trim,
+            "currency" => Filter::Currency(args[0].to_uppercase()),
+            "format" => Filter::Format(args[0].clone()),
+            "slice" => Filter::Slice { start: number(&args[0])?, end: args.get(1).map(|end| number(end)).transpose()? },
+            "truncate" => {
+                Filter::Truncate { length: count(&args[0])?, suffix: args.get(1).cloned().unwrap_or_else(|| "…".to_string()) }
+            }
+            _ => Filter::Default(args[0].clone()),
+        })
+    }
+
+    pub fn apply(&self, value: &str) -> String {
+        match self {
+            Filter::Pad { width, fill } => {
+                let missing = width.saturating_sub(value.chars().count());
+                std::iter::repeat_n(*fill, missing).chain(value.chars()).collect()
+            }
+            Filter::Upper => value.to_uppercase(),
+            Filter::Lower => value.to_lowercase(),
+            Filter::Trim => value.trim().to_string(),
+            Filter::Currency(code) => currency(value, code),
+            Filter::Format(pattern) => format_date(value, pattern),
+            Filter::Slice { start, end } => {
+                let chars: Vec<char> = value.chars().collect();
+                let index = |i: i64| if i < 0 { (chars.len() as i64 + i).max(0) as usize } else { (i as usize).min(chars.len()) };
+                let (start, end) = (index(*start), end.map_or(chars.len(), index));
+                if start < end { chars[start..end].iter().collect() } else { String::new() }
+            }
+            Filter::Truncate { length, suffix } => {
+                if value.chars().count() <= *length {
+                    return value.to_string();
+                }
+                let keep = length.saturating_sub(suffix.chars().count());
+                value.chars().take(keep).chain(suffix.chars()).take(*length).collect()
+            }
+            Filter::Default(fallback) => {
+                if value.trim().is_empty() { fallback.clone() } else { value.to_string() }
+            }
+        }
+    }
+}
+
+fn arity_text(min: usize, max: usize) -> String {
+    if min == max { min.to_string() } else { format!("{} to {}", min, max) }
+}
+
+/// Values that aren't numbers are left as they are
+fn currency(value: &str, code: &str) -> String {
+    let Some(amount) = value.trim().parse::<f64>().ok().filter(|a| a.is_finite()) else {
+        return value.to_string();
+    };
+    let (symbol, decimals) = match code {
+        "USD" => ("$", 2),
+        "EUR" => ("€", 2),
+        "GBP" => ("£", 2),
+        "JPY" => ("¥", 0),
+        "INR" => ("₹", 2),
+        _ => ("", 2),
+    };
+
+    // Round half up, like the preview's Math.round, rather than to even
+    let digits = format!("{:0>width$.0}", (amount.abs() * 10f64.powi(decimals as i32)).round(), width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    let mut grouped = String::new();
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    if !fraction.is_empty() {
+        grouped = format!("{}.{}", grouped, fraction);
+    }
+
+    let sign = if amount < 0.0 && digits.chars().any(|c| c.is_ascii_digit() && c != '0') { "-" } else { "" };
+    if symbol.is_empty() {
+        format!("{}{} {}", sign, code, grouped)
+    } else {
+        format!("{}{}{}", sign, symbol, grouped)
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+const WEEKDAYS: [&str; 7] = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+
+/// strftime subset: %Y %y %m %d %e %b %B %a %A %H %M %S %%. Values that
+/// aren't ISO dates are left as they are.
+fn format_date(value: &str, pattern: &str) -> String {
+    let Some((year, month, day, hour, minute, second)) = parse_iso_date(value.trim()) else {
+        return value.to_string();
+    };
+    let weekday = weekday(year, month, day);
+
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&year.to_string()),
+            Some('y') => out.push_str(&format!("{:02}", year.rem_euclid(100))),
+            Some('m') => out.push_str(&format!("{:02}", month)),
+            Some('d') => out.push_str(&format!("{:02}", day)),
+            Some('e') => out.push_str(&day.to_string()),
+            Some('b') => out.push_str(&MONTHS[month as usize - 1][..3]),
+            Some('B') => out.push_str(MONTHS[month as usize - 1]),
+            Some('a') => out.push_str(&WEEKDAYS[weekday][..3]),
+            Some('A') => out.push_str(WEEKDAYS[weekday]),
+            Some('H') => out.push_str(&format!("{:02}", hour)),
+            Some('M') => out.push_str(&format!("{:02}", minute)),
+            Some('S') => out.push_str(&format!("{:02}", second)),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+fn parse_iso_date(value: &str) -> Option<(i64, u32, u32, u32, u32, u32)> {
+    let (date, time) = match value.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+
+    // A field of exactly `len` ASCII digits
+    let field = |part: Option<&str>, len: usize| -> Option<u32> {
+        part.filter(|p| p.len() == len && p.bytes().all(|b| b.is_ascii_digit()))?.parse().ok()
+    };
+
+    let mut parts = date.split('-');
+    let year = field(parts.next(), 4)? as i64;
+    let month = field(parts.next(), 2)?;
+    let day = field(parts.next(), 2)?;
+    if parts.next().is_some() || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let (mut hour, mut minute, mut second) = (0, 0, 0);
+    if let Some(time) = time {
+        let mut parts = time.split(':');
+        hour = field(parts.next(), 2)?;
+        minute = field(parts.next(), 2)?;
+        if let Some(s) = parts.next() {
+            second = field(s.get(..2), 2)?;
+        }
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+    }
+    Some((year, month, day, hour, minute, second))
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Day of the week, 0 = Monday
+fn weekday(year: i64, month: u32, day: u32) -> usize {
+    // Days since 1970-01-01 (a Thursday), from Howard Hinnant's days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    (days + 3).rem_euclid(7) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(name: &str, args: &[&str], value: &str) -> String {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Filter::parse(name, &args).unwrap().apply(value)
+    }
+
+    fn error(name: &str, args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Filter::parse(name, &args).unwrap_err()
+    }
+
+    #[test]
+    fn rounds_currency_half_up() {
+        assert_eq!(apply("currency", &["EUR"], "0.125"), "€0.13");
+        // 1.005 is just under in binary, so rounds down here as in the preview
+        assert_eq!(apply("currency", &["USD"], "1.005"), "$1.00");
+        assert_eq!(apply("currency", &["USD"], "999.999"), "$1,000.00");
+        assert_eq!(apply("currency", &["jpy"], "0.5"), "¥1");
+        assert_eq!(apply("currency", &["INR"], " 1e6 "), "₹1,000,000.00");
+        // No negative zero once rounded
+        assert_eq!(apply("currency", &["USD"], "-0.004"), "$0.00");
+        assert_eq!(apply("currency", &["SEK"], "12"), "SEK 12.00");
+        assert_eq!(apply("currency", &["USD"], "inf"), "inf");
+    }
+
+    #[test]
+    fn formats_dates() {
+        assert_eq!(apply("format", &["%A %e %B %Y"], "2024-02-29"), "Thursday 29 February 2024");
+        assert_eq!(apply("format", &["%a %d/%m/%y"], "1900-01-01"), "Mon 01/01/00");
+        assert_eq!(apply("format", &["%H.%M.%S"], "2025-03-11 07:08:09"), "07.08.09");
+        assert_eq!(apply("format", &["%H:%M:%S"], "2025-03-11T07:08:09.250Z"), "07:08:09");
+        // Unknown fields and a trailing '%' are kept
+        assert_eq!(apply("format", &["%Q %"], "2025-03-11"), "%Q %");
+        for invalid in ["2023-02-29", "2025-13-01", "2025-3-11", "2025-03-11T24:00", "tomorrow"] {
+            assert_eq!(apply("format", &["%Y"], invalid), invalid);
+        }
+    }
+
+    #[test]
+    fn counts_characters_not_bytes() {
+        assert_eq!(apply("pad", &["3", "·"], "é"), "··é");
+        assert_eq!(apply("slice", &["1", "3"], "żółw"), "ół");
+        assert_eq!(apply("slice", &["-2"], "żółw"), "łw");
+        assert_eq!(apply("slice", &["-9", "9"], "żółw"), "żółw");
+        assert_eq!(apply("truncate", &["5"], "Zoë Ångström"), "Zoë …");
+        assert_eq!(apply("truncate", &["12"], "Zoë Ångström"), "Zoë Ångström");
+        // A suffix longer than the length is cut too
+        assert_eq!(apply("truncate", &["2", "..."], "abcdef"), "..");
+        assert_eq!(apply("upper", &[], "straße"), "STRASSE");
+    }
+
+    #[test]
+    fn rejects_unknown_filters_and_bad_arguments() {
+        assert_eq!(error("shout", &[]), "Unknown filter 'shout'");
+        assert_eq!(error("pad", &[]), "Filter 'pad' takes 1 to 2 argument(s), got 0");
+        assert_eq!(error("upper", &["1"]), "Filter 'upper' takes 0 argument(s), got 1");
+        assert_eq!(error("pad", &["six"]), "Filter 'pad': 'six' is not a count");
+        assert_eq!(error("truncate", &["-1"]), "Filter 'truncate': '-1' is not a count");
+        assert_eq!(error("slice", &["0", "a"]), "Filter 'slice': 'a' is not a whole number");
+        assert_eq!(error("pad", &["6", "ab"]), "Filter 'pad': fill 'ab' must be one character");
+    }
+}
@@ -20,7 +20,7 @@ pub async fn load_font(font_name: &str, url: &str) -> Result<Vec<u8>, JsValue> {
     }
 
     // Fetch font from URL
-    let mut opts = RequestInit::new();
+    let opts = RequestInit::new();
     opts.set_method("GET");
     opts.set_mode(RequestMode::Cors);
 
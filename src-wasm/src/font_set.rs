@@ -0,0 +1,240 @@
+// Fonts registered by family, weight and style, with CSS-style face matching.
+
+use ab_glyph::{FontRef, VariableFont};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FontStyle {
+    Normal,
+    Italic,
+    Oblique,
+}
+
+impl FontStyle {
+    pub fn parse(value: Option<&str>) -> Result<Self, String> {
+        match value {
+            None | Some("normal") => Ok(FontStyle::Normal),
+            Some("italic") => Ok(FontStyle::Italic),
+            Some("oblique") => Ok(FontStyle::Oblique),
+            Some(other) => Err(format!("Unknown font style '{}'", other)),
+        }
+    }
+
+    /// Order in which styles are tried for this requested style
+    fn preference(self, face: FontStyle) -> usize {
+        let order = match self {
+            FontStyle::Normal => [FontStyle::Normal, FontStyle::Oblique, FontStyle::Italic],
+            FontStyle::Italic => [FontStyle::Italic, FontStyle::Oblique, FontStyle::Normal],
+            FontStyle::Oblique => [FontStyle::Oblique, FontStyle::Italic, FontStyle::Normal],
+        };
+        order.iter().position(|&s| s == face).unwrap_or(order.len())
+    }
+}
+
+pub const NORMAL_WEIGHT: f32 = 400.0;
+
+/// A registered face; variable fonts with a `wght` axis cover a weight range
+pub struct FontFace {
+    /// Font file, shared with the styles that use it
+    pub data: Arc<[u8]>,
+    pub style: FontStyle,
+    pub weight: (f32, f32),
+}
+
+#[derive(Default)]
+pub struct FontSet {
+    families: HashMap<String, Vec<FontFace>>,
+}
+
+impl FontSet {
+    /// Register fonts keyed `family`, `family:weight`, `family:style` or
+    /// `family:weight:style`, e.g. "Roboto:700:italic". A key with no weight
+    /// takes the range of the font's `wght` axis, or 400 for static fonts.
+    pub fn new(fonts: HashMap<String, Vec<u8>>) -> Result<Self, String> {
+        let mut set = FontSet::default();
+        for (key, data) in fonts {
+            let mut parts = key.split(':');
+            let family = parts.next().unwrap_or_default().to_string();
+            let mut weight = None;
+            let mut style = FontStyle::Normal;
+            for part in parts {
+                match part.parse::<f32>() {
+                    Ok(w) => weight = Some(w),
+                    Err(_) => style = FontStyle::parse(Some(part)).map_err(|e| format!("Font '{}': {}", key, e))?,
+                }
+            }
+
+            let font = FontRef::try_from_slice(&data).map_err(|e| format!("Failed to load font '{}': {}", key, e))?;
+            let axis = font.variations().into_iter().find(|axis| &axis.tag == b"wght");
+            let weight = match (weight, axis) {
+                (Some(w), _) => (w, w),
+                (None, Some(axis)) => (axis.min_value, axis.max_value),
+                (None, None) => (NORMAL_WEIGHT, NORMAL_WEIGHT),
+            };
+
+            set.families.entry(family).or_default().push(FontFace { data: data.into(), style, weight });
+        }
+        Ok(set)
+    }
+
+    /// Alphabetically first family, used when a stamp names none
+    pub fn first_family(&self) -> Option<&str> {
+        self.families.keys().min().map(String::as_str)
+    }
+
+    /// Closest face of `family` to the requested weight and style: style is
+    /// matched first, then weight as in CSS font matching
+    pub fn select(&self, family: &str, weight: f32, style: FontStyle) -> Option<&FontFace> {
+        self.families.get(family)?.iter().min_by(|a, b| {
+            let key = |face: &FontFace| {
+                let (tier, distance) = weight_preference(weight, face.weight);
+                (style.preference(face.style), tier, distance)
+            };
+            key(a).partial_cmp(&key(b)).unwrap_or(std::cmp::Ordering::Equal)
+        })
+    }
+}
+
+/// Rank of a face's weight range for a requested weight (lower is better).
+/// Between 400 and 500 slightly heavier faces up to 500 win, then lighter,
+/// then heavier ones; below 400 lighter faces win, above 500 heavier ones.
+fn weight_preference(desired: f32, (low, high): (f32, f32)) -> (u8, f32) {
+    if low <= desired && desired <= high {
+        return (0, 0.0);
+    }
+    let lighter = high < desired;
+    let distance = if lighter { desired - high } else { low - desired };
+    let tier = if (400.0..=500.0).contains(&desired) {
+        match lighter {
+            false if low <= 500.0 => 1,
+            true => 2,
+            false => 3,
+        }
+    } else if (desired < 400.0) == lighter {
+        1
+    } else {
+        2
+    };
+    (tier, distance)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const ROBOTO: &[u8] = include_bytes!("../../static/fonts/Roboto-Regular.ttf");
+
+    /// Big-endian 16-bit words
+    fn words(values: &[i32]) -> Vec<u8> {
+        values.iter().flat_map(|&value| (value as u16).to_be_bytes()).collect()
+    }
+
+    /// A variable TrueType font with `wght` (100-900), `wdth` (75-100) and
+    /// `slnt` (-15-0) axes and one glyph: 'I', a stem from x = 100 to 200
+    /// and 700 units high. The heaviest weight moves its right edge 100
+    /// units right, the narrowest width scales it by 3/4 towards x = 0, and
+    /// the full slant moves its top 188 units right.
+    pub(crate) fn variable_font() -> Vec<u8> {
+        let glyph = [words(&[1, 100, 0, 200, 700, 3, 0]), vec![1; 4], words(&[100, 100, 0, -100]), words(&[0, 0, 700, 0])].concat();
+        let axes: Vec<u8> = [(b"wght", 100, 400, 900), (b"wdth", 75, 100, 100), (b"slnt", -15, 0, 0)]
+            .iter()
+            .flat_map(|(tag, min, default, max)| [tag.to_vec(), words(&[*min, 0, *default, 0, *max, 0, 0, 256])].concat())
+            .collect();
+        // Every tuple moves the x of all points and leaves the four phantom points
+        let tuples = [([16384, 0, 0], [0, 100, 100, 0]), ([0, -16384, 0], [-25, -50, -50, -25]), ([0, 0, -16384], [0, 0, 188, 188])];
+        let mut variations = words(&[0x8000 | tuples.len() as i32, 4 + 10 * tuples.len() as i32]);
+        for (peak, _) in &tuples {
+            variations.extend(words(&[18, 0x8000]));
+            variations.extend(words(peak));
+        }
+        // All points share the tuples; x deltas are words, y deltas zero
+        variations.push(0);
+        for (_, deltas) in &tuples {
+            variations.push(0x47);
+            variations.extend(words(deltas));
+            variations.extend(words(&[0; 4]));
+            variations.push(0x87);
+        }
+        variations.resize(variations.len().next_multiple_of(2), 0);
+        let gvar = [words(&[1, 0, 3, 0, 0, 26, 2, 0, 0, 26, 0, 0, variations.len() as i32 / 2]), variations].concat();
+
+        let tables: [(&[u8; 4], Vec<u8>); 9] = [
+            // Unicode 'I' to glyph 1, as a trimmed table
+            (b"cmap", words(&[0, 1, 0, 3, 0, 12, 6, 12, 0, 'I' as i32, 1, 1])),
+            (b"fvar", [words(&[1, 0, 16, 2, 3, 20, 0, 16]), axes].concat()),
+            (b"glyf", glyph.clone()),
+            (b"gvar", gvar),
+            (b"head", words(&[1, 0, 1, 0, 0, 0, 0x5F0F, 0x3CF5, 0, 1000, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1000, 1000, 0, 8, 2, 0, 0])),
+            (b"hhea", words(&[1, 0, 800, -200, 0, 500, 0, 0, 500, 1, 0, 0, 0, 0, 0, 0, 0, 2])),
+            (b"hmtx", words(&[500, 0, 400, 100])),
+            (b"loca", words(&[0, 0, glyph.len() as i32 / 2])),
+            (b"maxp", words(&[0, 0x5000, 2])),
+        ];
+        let mut font = words(&[1, 0, tables.len() as i32, 0, 0, 0]);
+        let mut offset = font.len() + 16 * tables.len();
+        for (tag, data) in &tables {
+            font.extend_from_slice(*tag);
+            font.extend(words(&[0, 0, 0, offset as i32, 0, data.len() as i32]));
+            offset += data.len().next_multiple_of(4);
+        }
+        for (_, data) in tables {
+            let padded = font.len() + data.len().next_multiple_of(4);
+            font.extend(data);
+            font.resize(padded, 0);
+        }
+        font
+    }
+
+    fn faces(keys: &[&str]) -> FontSet {
+        FontSet::new(keys.iter().map(|key| (key.to_string(), ROBOTO.to_vec())).collect()).unwrap()
+    }
+
+    /// Weight and style of the face picked for a request
+    fn pick(set: &FontSet, weight: f32, style: FontStyle) -> (f32, FontStyle) {
+        let face = set.select("Roboto", weight, style).unwrap();
+        (face.weight.0, face.style)
+    }
+
+    #[test]
+    fn picks_the_exact_face() {
+        let set = faces(&["Roboto", "Roboto:700", "Roboto:700:italic"]);
+        assert_eq!(pick(&set, 400.0, FontStyle::Normal), (400.0, FontStyle::Normal));
+        assert_eq!(pick(&set, 700.0, FontStyle::Normal), (700.0, FontStyle::Normal));
+        assert_eq!(pick(&set, 700.0, FontStyle::Italic), (700.0, FontStyle::Italic));
+        assert!(set.select("Lora", 400.0, FontStyle::Normal).is_none());
+    }
+
+    #[test]
+    fn falls_back_across_weights_as_css_does() {
+        let normal = |keys: &[&str], weight: f32| pick(&faces(keys), weight, FontStyle::Normal).0;
+        // Below 400, lighter faces first, the closest one first
+        assert_eq!(normal(&["Roboto:100", "Roboto:200", "Roboto:500"], 300.0), 200.0);
+        assert_eq!(normal(&["Roboto:500", "Roboto:600"], 300.0), 500.0);
+        // Above 500, heavier faces first
+        assert_eq!(normal(&["Roboto:300", "Roboto:800", "Roboto:900"], 600.0), 800.0);
+        assert_eq!(normal(&["Roboto:300", "Roboto:500"], 600.0), 500.0);
+        // From 400 to 500, heavier faces up to 500, then lighter, then heavier ones
+        assert_eq!(normal(&["Roboto:300", "Roboto:500", "Roboto:600"], 400.0), 500.0);
+        assert_eq!(normal(&["Roboto:300", "Roboto:600"], 400.0), 300.0);
+        assert_eq!(normal(&["Roboto:600", "Roboto:700"], 450.0), 600.0);
+    }
+
+    #[test]
+    fn matches_style_before_weight() {
+        let italic = |keys: &[&str]| pick(&faces(keys), 400.0, FontStyle::Italic);
+        assert_eq!(italic(&["Roboto", "Roboto:700:italic"]), (700.0, FontStyle::Italic));
+        // Italic falls back to oblique, then to normal
+        assert_eq!(italic(&["Roboto", "Roboto:oblique"]), (400.0, FontStyle::Oblique));
+        assert_eq!(italic(&["Roboto", "Roboto:700"]), (400.0, FontStyle::Normal));
+    }
+
+    #[test]
+    fn variable_faces_cover_their_weight_axis() {
+        let fonts = HashMap::from([("Roboto".to_string(), variable_font()), ("Roboto:950".to_string(), ROBOTO.to_vec())]);
+        let set = FontSet::new(fonts).unwrap();
+        assert_eq!(pick(&set, 650.0, FontStyle::Normal), (100.0, FontStyle::Normal));
+        // Beyond the axis, an exact static face wins
+        assert_eq!(pick(&set, 950.0, FontStyle::Normal), (950.0, FontStyle::Normal));
+    }
+}
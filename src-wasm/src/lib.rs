@@ -1,17 +1,37 @@
 mod ticket_renderer;
 mod font_loader;
+mod font_set;
+mod text_layout;
+mod markup;
+mod template;
+mod filters;
+mod mask;
+mod sheet;
+mod pdf;
+mod barcode;
+mod reed_solomon;
+mod bit_matrix;
+mod datamatrix;
+mod aztec;
+mod diagnostics;
+mod drawing;
 
 use image::{ImageBuffer, RgbaImage, Rgba};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::cell::RefCell;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::future_to_promise;
 use ticket_renderer::{TicketRenderer, TemplateData, Stamp};
+use sheet::{Numbering, SheetLayout, draw_page, render_page, BUILTIN_VARIABLES};
+use pdf::PdfWriter;
+use diagnostics::Diagnostic;
+use template::DataSource;
 
 // Thread-local storage for the last rendered image data
 thread_local! {
-    static LAST_RENDER: RefCell<Vec<u8>> = RefCell::new(Vec::new());
+    static LAST_RENDER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    static LAST_DIAGNOSTICS: RefCell<Vec<Diagnostic>> = const { RefCell::new(Vec::new()) };
 }
 
 /// Get access to the WASM memory buffer
@@ -40,8 +60,19 @@ pub struct RenderConfig {
     pub template_width: u32,
     pub template_height: u32,
     pub stamps: Vec<Stamp>,
+    /// Names, types and CSV columns of the project's data sources, for
+    /// `{{source.field}}` and checking placeholders
+    #[serde(default)]
+    pub data_sources: Vec<DataSource>,
     pub records: Vec<HashMap<String, String>>,
     pub dpi: f64,
+    /// Fail the job, rather than warn, when a placeholder matches no record field
+    #[serde(default)]
+    pub strict_variables: bool,
+    /// Place of `records` in a larger job, for built-in variables; by default
+    /// they are the whole job on this sheet layout
+    #[serde(default)]
+    pub numbering: Option<Numbering>,
 }
 
 /// Renders a complete sheet with tickets generated entirely in WASM.
@@ -52,19 +83,61 @@ pub struct RenderConfig {
 #[wasm_bindgen]
 pub fn render_sheet(config_json: String, template_data: Vec<u8>, fonts_json: String) -> js_sys::Promise {
     future_to_promise(async move {
-        match render_sheet_impl(&config_json, &template_data, &fonts_json).await {
-            Ok(bytes) => {
-                let len = bytes.len();
-                LAST_RENDER.with(|cell| {
-                    *cell.borrow_mut() = bytes;
-                });
-                Ok(JsValue::from(len as u32))
-            }
-            Err(e) => Err(e)
-        }
+        let bytes = render_sheet_impl(&config_json, &template_data, &fonts_json).await?;
+        Ok(JsValue::from(store_render(bytes)))
     })
 }
 
+/// A render job prepared once, with its fonts loaded and templates compiled,
+/// whose sheets can then be rendered one at a time.
+#[wasm_bindgen]
+pub struct SheetJob {
+    job: RenderJob,
+}
+
+/// Parses `config_json`, loads its fonts and compiles its templates for
+/// rendering sheets with SheetJob.render_page().
+///
+/// Returns a Promise that resolves to the SheetJob.
+#[wasm_bindgen]
+pub fn prepare_sheet_job(config_json: String, template_data: Vec<u8>, fonts_json: String) -> js_sys::Promise {
+    future_to_promise(async move {
+        let fonts_map = load_fonts(&fonts_json).await?;
+        let job = prepare_job(&config_json, &template_data, fonts_map)
+            .map_err(|e| JsValue::from_str(&e))?;
+        Ok(JsValue::from(SheetJob { job }))
+    })
+}
+
+#[wasm_bindgen]
+impl SheetJob {
+    /// Number of sheets needed to print every record of the job
+    pub fn page_count(&self) -> u32 {
+        sheet::page_count(self.job.records.len(), self.job.layout.tickets_per_page()) as u32
+    }
+
+    /// Renders sheet `page_index` (zero-based), paginating the records in grid order.
+    ///
+    /// Returns the length of the rendered data.
+    /// Call get_render_data_ptr() to get a pointer to the data for zero-copy access.
+    pub fn render_page(&self, page_index: u32) -> Result<u32, JsValue> {
+        let sheet_img = render_page(&self.job.layout, &self.job.renderer, &self.job.records, &self.job.numbering, page_index as usize)
+            .map_err(|e| JsValue::from_str(&e))?;
+        store_diagnostics(&self.job.renderer);
+        Ok(store_render(sheet_img.into_raw()))
+    }
+}
+
+/// Number of sheets needed to print every record of the render config.
+#[wasm_bindgen]
+pub fn get_page_count(config_json: &str) -> Result<u32, JsValue> {
+    let request: RenderConfig = serde_json::from_str(config_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid config JSON: {}", e)))?;
+
+    let tickets_per_page = request.sheet_config.rows * request.sheet_config.cols;
+    Ok(sheet::page_count(request.records.len(), tickets_per_page) as u32)
+}
+
 /// Get a pointer to the last rendered image data (zero-copy access)
 #[wasm_bindgen]
 pub fn get_render_data_ptr() -> *const u8 {
@@ -73,6 +146,15 @@ pub fn get_render_data_ptr() -> *const u8 {
     })
 }
 
+/// Warnings raised by the last render, as a JSON array of
+/// `{ code, stampId, recordIndex, message }` objects
+#[wasm_bindgen]
+pub fn get_render_diagnostics() -> String {
+    LAST_DIAGNOSTICS.with(|cell| {
+        serde_json::to_string(&*cell.borrow()).unwrap_or_else(|_| "[]".to_string())
+    })
+}
+
 /// Get the length of the last rendered image data
 #[wasm_bindgen]
 pub fn get_render_data_len() -> usize {
@@ -81,141 +163,123 @@ pub fn get_render_data_len() -> usize {
     })
 }
 
-async fn render_sheet_impl(config_json: &str, template_data: &[u8], fonts_json: &str) -> Result<Vec<u8>, JsValue> {
-    let t_start = js_sys::Date::now();
+/// Renders the sheet described by `config_json` into a print-ready PDF whose page
+/// size matches `paper_width_mm` x `paper_height_mm` exactly. Text is set in the
+/// embedded fonts and barcode and matrix modules are vector rectangles; the ticket
+/// artwork, QR logos and text shadows are embedded as images. Positions are laid
+/// out at the config's `dpi`, as on the preview.
+///
+/// Returns a Promise that resolves to the length of the PDF.
+/// Call get_render_data_ptr() to get a pointer to the bytes for zero-copy access.
+#[wasm_bindgen]
+pub fn render_pdf(config_json: String, template_data: Vec<u8>, fonts_json: String) -> js_sys::Promise {
+    future_to_promise(async move {
+        let bytes = render_pdf_impl(&config_json, &template_data, &fonts_json).await?;
+        Ok(JsValue::from(store_render(bytes)))
+    })
+}
 
-    let request: RenderConfig = serde_json::from_str(config_json)
-        .map_err(|e| JsValue::from_str(&format!("Invalid config JSON: {}", e)))?;
+/// A parsed render request with its renderer ready to draw tickets
+struct RenderJob {
+    config: SheetConfig,
+    layout: SheetLayout,
+    renderer: TicketRenderer,
+    records: Vec<HashMap<String, String>>,
+    numbering: Numbering,
+}
 
-    // Parse fonts JSON (font name -> URL)
+/// Fetch every font listed in `fonts_json` (font name -> URL)
+async fn load_fonts(fonts_json: &str) -> Result<HashMap<String, Vec<u8>>, JsValue> {
     let fonts_urls: HashMap<String, String> = serde_json::from_str(fonts_json)
         .map_err(|e| JsValue::from_str(&format!("Invalid fonts JSON: {}", e)))?;
 
-    // Fetch all fonts
-    let t1 = js_sys::Date::now();
     let mut fonts_map: HashMap<String, Vec<u8>> = HashMap::new();
     for (font_name, font_url) in fonts_urls {
         let font_data = font_loader::load_font(&font_name, &font_url).await?;
         fonts_map.insert(font_name, font_data);
     }
-    web_sys::console::log_1(&JsValue::from_str(&format!("[WASM PERF] Font loading: {:.1}ms", js_sys::Date::now() - t1)));
-
-    let config = &request.sheet_config;
-    let dpi = request.dpi;
-    let mm_per_inch = 25.4;
-    let pixels_per_mm = dpi / mm_per_inch;
-
-    // Calculate page dimensions (must match TypeScript's Math.round())
-    let page_width_px = (config.paper_width_mm * pixels_per_mm).round() as u32;
-    let page_height_px = (config.paper_height_mm * pixels_per_mm).round() as u32;
-
-    // Create white background
-    let mut sheet_img: RgbaImage = ImageBuffer::new(page_width_px, page_height_px);
-    for pixel in sheet_img.pixels_mut() {
-        *pixel = Rgba([255, 255, 255, 255]);
-    }
 
-    // Calculate ticket dimensions
-    let tickets_per_page = config.rows * config.cols;
-
-    // Calculate ticket size in mm
-    let ticket_width_mm = if config.cols > 0 {
-        (config.paper_width_mm
-            - config.margin_left_mm
-            - config.margin_right_mm
-            - (config.cols - 1) as f64 * config.spacing_x_mm)
-        / config.cols as f64
-    } else {
-        0.0
-    };
-
-    let ticket_height_mm = if config.rows > 0 {
-        (config.paper_height_mm
-            - config.margin_top_mm
-            - config.margin_bottom_mm
-            - (config.rows - 1) as f64 * config.spacing_y_mm)
-        / config.rows as f64
-    } else {
-        0.0
-    };
+    Ok(fonts_map)
+}
 
-    if ticket_width_mm <= 0.0 || ticket_height_mm <= 0.0 {
-        return Err(JsValue::from_str("Invalid ticket dimensions"));
-    }
+fn prepare_job(config_json: &str, template_data: &[u8], fonts_map: HashMap<String, Vec<u8>>) -> Result<RenderJob, String> {
+    let request: RenderConfig = serde_json::from_str(config_json)
+        .map_err(|e| format!("Invalid config JSON: {}", e))?;
 
-    // Calculate target ticket dimensions in pixels (must match TypeScript's Math.round())
-    let target_ticket_width_px = (ticket_width_mm * pixels_per_mm).round() as u32;
-    let target_ticket_height_px = (ticket_height_mm * pixels_per_mm).round() as u32;
+    let layout = SheetLayout::new(&request.sheet_config, request.template_width, request.template_height, request.dpi)?;
 
-    // Calculate uniform scale factor for template
+    // Validate template data length
     let template_width = request.template_width;
     let template_height = request.template_height;
-
-    let scale_x = target_ticket_width_px as f32 / template_width as f32;
-    let scale_y = target_ticket_height_px as f32 / template_height as f32;
-    let scale = scale_x.min(scale_y); // Uniform scaling - CRITICAL
-
-    // Calculate actual scaled template dimensions (maintaining aspect ratio)
-    let scaled_template_width = (template_width as f32 * scale).round() as u32;
-    let scaled_template_height = (template_height as f32 * scale).round() as u32;
-
-    // Validate template data length
     let expected_len = (template_width * template_height * 4) as usize;
     if template_data.len() != expected_len {
-        return Err(JsValue::from_str(&format!(
+        return Err(format!(
             "Invalid template data length. Expected {} bytes ({}x{}x4), got {}",
             expected_len, template_width, template_height, template_data.len()
-        )));
+        ));
     }
 
-    // Create template data structure
     let template = TemplateData {
         width: template_width,
         height: template_height,
         data: template_data.to_vec(),
     };
 
-    // Create ticket renderer with all fonts
-    let t2 = js_sys::Date::now();
-    let renderer = TicketRenderer::new(template, request.stamps, fonts_map)
-        .map_err(|e| JsValue::from_str(&e))?;
-    web_sys::console::log_1(&JsValue::from_str(&format!("[WASM PERF] Create renderer: {:.1}ms", js_sys::Date::now() - t2)));
-
-    // Convert margins and spacing to pixels (must match TypeScript's Math.round())
-    let margin_left_px = (config.margin_left_mm * pixels_per_mm).round() as u32;
-    let margin_top_px = (config.margin_top_mm * pixels_per_mm).round() as u32;
-    let spacing_x_px = (config.spacing_x_mm * pixels_per_mm).round() as u32;
-    let spacing_y_px = (config.spacing_y_mm * pixels_per_mm).round() as u32;
-
-    // Render tickets on the first page
-    let records_to_render = request.records.len().min(tickets_per_page);
+    // Placeholders may name a record field, a data source or CSV column (so
+    // jobs without records are checked too), or a built-in variable
+    let mut keys: HashSet<String> = request.records.iter().flat_map(|record| record.keys().cloned()).collect();
+    for source in &request.data_sources {
+        keys.insert(source.name.clone());
+        keys.extend(source.columns.iter().cloned());
+    }
+    keys.extend(BUILTIN_VARIABLES.map(String::from));
+
+    let renderer = TicketRenderer::new(template, request.stamps, request.data_sources, fonts_map, request.dpi)?;
+    renderer.check_record_keys(&keys, request.strict_variables)?;
+
+    let numbering = request.numbering.unwrap_or_else(|| Numbering::new(&layout, request.records.len()));
+    Ok(RenderJob {
+        config: request.sheet_config,
+        layout,
+        renderer,
+        records: request.records,
+        numbering,
+    })
+}
 
-    let t3 = js_sys::Date::now();
-    for i in 0..records_to_render {
-        let record = &request.records[i];
+/// Keep `bytes` for get_render_data_ptr() and return their length
+fn store_render(bytes: Vec<u8>) -> u32 {
+    let len = bytes.len();
+    LAST_RENDER.with(|cell| {
+        *cell.borrow_mut() = bytes;
+    });
+    len as u32
+}
 
-        // Calculate grid position
-        let row = i / config.cols;
-        let col = i % config.cols;
+fn store_diagnostics(renderer: &TicketRenderer) {
+    let diagnostics = renderer.take_diagnostics();
+    LAST_DIAGNOSTICS.with(|cell| {
+        *cell.borrow_mut() = diagnostics;
+    });
+}
 
-        // Calculate position on sheet (top-left of cell)
-        let cell_x_px = margin_left_px + col as u32 * (target_ticket_width_px + spacing_x_px);
-        let cell_y_px = margin_top_px + row as u32 * (target_ticket_height_px + spacing_y_px);
+async fn render_sheet_impl(config_json: &str, template_data: &[u8], fonts_json: &str) -> Result<Vec<u8>, JsValue> {
+    let t_start = js_sys::Date::now();
 
-        // Render ticket with uniformly scaled dimensions
-        let ticket_img = renderer.render(record, scaled_template_width, scaled_template_height)
-            .map_err(|e| JsValue::from_str(&format!("Failed to render ticket {}: {}", i, e)))?;
+    let t1 = js_sys::Date::now();
+    let fonts_map = load_fonts(fonts_json).await?;
+    web_sys::console::log_1(&JsValue::from_str(&format!("[WASM PERF] Font loading: {:.1}ms", js_sys::Date::now() - t1)));
 
-        // Center the scaled template within the cell
-        let offset_x = (target_ticket_width_px.saturating_sub(scaled_template_width)) / 2;
-        let offset_y = (target_ticket_height_px.saturating_sub(scaled_template_height)) / 2;
-        let final_x = cell_x_px + offset_x;
-        let final_y = cell_y_px + offset_y;
+    let t2 = js_sys::Date::now();
+    let job = prepare_job(config_json, template_data, fonts_map)
+        .map_err(|e| JsValue::from_str(&e))?;
+    web_sys::console::log_1(&JsValue::from_str(&format!("[WASM PERF] Create renderer: {:.1}ms", js_sys::Date::now() - t2)));
 
-        // Composite onto sheet
-        composite_image(&mut sheet_img, &ticket_img, final_x, final_y);
-    }
-    web_sys::console::log_1(&JsValue::from_str(&format!("[WASM PERF] Render {} tickets: {:.1}ms", records_to_render, js_sys::Date::now() - t3)));
+    let t3 = js_sys::Date::now();
+    let sheet_img = render_page(&job.layout, &job.renderer, &job.records, &job.numbering, 0)
+        .map_err(|e| JsValue::from_str(&e))?;
+    web_sys::console::log_1(&JsValue::from_str(&format!("[WASM PERF] Render sheet: {:.1}ms", js_sys::Date::now() - t3)));
+    store_diagnostics(&job.renderer);
 
     let t4 = js_sys::Date::now();
     let result = sheet_img.into_raw();
@@ -226,7 +290,32 @@ async fn render_sheet_impl(config_json: &str, template_data: &[u8], fonts_json:
     Ok(result)
 }
 
+async fn render_pdf_impl(config_json: &str, template_data: &[u8], fonts_json: &str) -> Result<Vec<u8>, JsValue> {
+    let t_start = js_sys::Date::now();
+
+    let fonts_map = load_fonts(fonts_json).await?;
+    let job = prepare_job(config_json, template_data, fonts_map)
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    // Always emit at least one (blank) page so the PDF is valid
+    let page_count = sheet::page_count(job.records.len(), job.layout.tickets_per_page()).max(1);
+
+    let mut pdf = PdfWriter::new();
+    for page_index in 0..page_count {
+        let drawing = draw_page(&job.layout, &job.renderer, &job.records, &job.numbering, page_index)
+            .map_err(|e| JsValue::from_str(&e))?;
+        pdf.add_page(&drawing, job.config.paper_width_mm, job.config.paper_height_mm, job.layout.dpi);
+    }
+    let result = pdf.finish().map_err(|e| JsValue::from_str(&e))?;
+    store_diagnostics(&job.renderer);
+
+    web_sys::console::log_1(&JsValue::from_str(&format!("[WASM PERF] PDF ({} pages, {} bytes): {:.1}ms", page_count, result.len(), js_sys::Date::now() - t_start)));
+
+    Ok(result)
+}
+
 /// Legacy function for compositing pre-rendered tickets (kept for compatibility).
+/// Renders sheet `page_index` (default 0) of the tickets in `image_data`.
 /// Use render_sheet for better performance.
 #[wasm_bindgen]
 pub fn generate_preview_png(
@@ -234,6 +323,7 @@ pub fn generate_preview_png(
     image_data: &[u8],
     ticket_width_px: u32,
     ticket_height_px: u32,
+    page_index: Option<u32>,
 ) -> Result<Vec<u8>, JsValue> {
     let config: SheetConfig = serde_json::from_str(config_json)
         .map_err(|e| JsValue::from_str(&format!("Invalid config JSON: {}", e)))?;
@@ -275,12 +365,22 @@ pub fn generate_preview_png(
     let spacing_x_px = (config.spacing_x_mm * pixels_per_mm) as u32;
     let spacing_y_px = (config.spacing_y_mm * pixels_per_mm) as u32;
 
-    // Only render the first page
-    let tickets_to_render = total_tickets.min(tickets_per_page);
+    let page_count = sheet::page_count(total_tickets, tickets_per_page);
+    let page_index = page_index.unwrap_or(0) as usize;
+    if page_index >= page_count {
+        return Err(JsValue::from_str(&format!(
+            "Page {} out of range ({} pages)",
+            page_index, page_count
+        )));
+    }
+
+    let first_ticket = page_index * tickets_per_page;
+    let tickets_to_render = (total_tickets - first_ticket).min(tickets_per_page);
 
-    for i in 0..tickets_to_render {
-        let row = i / config.cols;
-        let col = i % config.cols;
+    for slot in 0..tickets_to_render {
+        let i = first_ticket + slot;
+        let row = slot / config.cols;
+        let col = slot % config.cols;
 
         // Calculate position using actual ticket pixel dimensions and spacing
         let x_px = margin_left_px + col as u32 * (ticket_width_px + spacing_x_px);
@@ -332,34 +432,54 @@ pub fn generate_preview_png(
     Ok(png_bytes)
 }
 
-/// Helper function to composite images with alpha blending
-fn composite_image(base: &mut RgbaImage, overlay: &RgbaImage, x: u32, y: u32) {
-    let (base_w, base_h) = base.dimensions();
-    let (overlay_w, overlay_h) = overlay.dimensions();
-
-    for oy in 0..overlay_h {
-        for ox in 0..overlay_w {
-            let bx = x + ox;
-            let by = y + oy;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FONT: &[u8] = include_bytes!("../../static/fonts/Roboto-Regular.ttf");
+
+    /// Prepare a one-ticket sheet job with a text stamp showing `template`
+    fn prepare(template: &str, data_sources: &str, records: &str, strict: bool) -> Result<RenderJob, String> {
+        let config = format!(
+            r##"{{
+                "sheet_config": {{
+                    "paper_width_mm": 50, "paper_height_mm": 20, "rows": 1, "cols": 1,
+                    "margin_top_mm": 0, "margin_right_mm": 0, "margin_bottom_mm": 0, "margin_left_mm": 0,
+                    "spacing_x_mm": 0, "spacing_y_mm": 0
+                }},
+                "template_width": 4, "template_height": 2,
+                "stamps": [{{"type":"text","id":"t","x":0,"y":0,"width":4,"height":2,"template":{},"fontFamily":"Roboto","fontSize":1,"color":"#000","alignment":"left"}}],
+                "data_sources": {}, "records": {}, "dpi": 96, "strict_variables": {}
+            }}"##,
+            serde_json::to_string(template).unwrap(),
+            data_sources,
+            records,
+            strict,
+        );
+        let fonts = HashMap::from([("Roboto".to_string(), FONT.to_vec())]);
+        prepare_job(&config, &[255; 4 * 2 * 4], fonts)
+    }
 
-            if bx >= base_w || by >= base_h {
-                continue;
-            }
+    const GUESTS: &str = r#"[{"name":"guests","type":"csv","columns":["name"]},{"name":"number","type":"sequential"}]"#;
 
-            let pixel = overlay.get_pixel(ox, oy);
-            let bg = base.get_pixel(bx, by);
+    #[test]
+    fn strict_jobs_fail_on_placeholders_nothing_provides() {
+        // Columns, source names and built-ins count even before there are records
+        let error = prepare("{{name}} {{number}} {{seat}} {{_index}} {{guests.row}}", GUESTS, "[]", true).err();
+        assert_eq!(error.as_deref(), Some("Unknown template variables in stamp 't': {{seat}}, {{guests.row}}"));
 
-            let alpha = pixel[3] as f32 / 255.0;
-            let inv_alpha = 1.0 - alpha;
+        assert!(prepare("{{name}} {{seat}}", GUESTS, r#"[{"seat":"12"}]"#, true).is_ok());
+        assert!(prepare("{{seat}}", "[]", "[]", true).is_err());
+    }
 
-            let blended = Rgba([
-                (pixel[0] as f32 * alpha + bg[0] as f32 * inv_alpha) as u8,
-                (pixel[1] as f32 * alpha + bg[1] as f32 * inv_alpha) as u8,
-                (pixel[2] as f32 * alpha + bg[2] as f32 * inv_alpha) as u8,
-                255,
-            ]);
+    #[test]
+    fn lenient_jobs_report_unknown_placeholders() {
+        let job = prepare("{{name}} {{seat}}", GUESTS, "[]", false).unwrap();
+        let diagnostics = job.renderer.take_diagnostics();
+        let reported: Vec<(&str, &str)> = diagnostics.iter().map(|d| (d.code, d.message.as_str())).collect();
+        assert_eq!(reported, [("unknown_variable", "No data field for {{seat}}")]);
 
-            base.put_pixel(bx, by, blended);
-        }
+        let job = prepare("{{name}}", GUESTS, "[]", false).unwrap();
+        assert!(job.renderer.take_diagnostics().is_empty());
     }
 }
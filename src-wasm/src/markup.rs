@@ -0,0 +1,272 @@
+// Inline markup for text stamps: **bold**, *italic* and bracketed spans such
+// as [Row C]{color=#c00 size=120% weight=600 style=italic}. A backslash
+// escapes the next character; {{placeholders}} are passed through untouched.
+
+use crate::font_set::FontStyle;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SpanSize {
+    /// Template pixels, like `fontSize`
+    Pixels(f32),
+    /// Percentage of the surrounding text's size
+    Percent(f32),
+}
+
+/// Formatting applied to a span; unset fields inherit from the stamp
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct SpanFormat {
+    pub weight: Option<f32>,
+    pub style: Option<FontStyle>,
+    pub color: Option<String>,
+    pub size: Option<SpanSize>,
+}
+
+impl SpanFormat {
+    /// `inner` nested inside `self`: inner fields win, relative sizes compound
+    fn nest(&self, inner: &SpanFormat) -> SpanFormat {
+        let size = match (self.size, inner.size) {
+            (Some(SpanSize::Pixels(px)), Some(SpanSize::Percent(p))) => Some(SpanSize::Pixels(px * p / 100.0)),
+            (Some(SpanSize::Percent(q)), Some(SpanSize::Percent(p))) => Some(SpanSize::Percent(q * p / 100.0)),
+            (outer, inner) => inner.or(outer),
+        };
+        SpanFormat {
+            weight: inner.weight.or(self.weight),
+            style: inner.style.or(self.style),
+            color: inner.color.clone().or_else(|| self.color.clone()),
+            size,
+        }
+    }
+}
+
+/// Template text, still containing placeholders, with its formatting
+pub struct Span {
+    pub text: String,
+    pub format: SpanFormat,
+}
+
+enum Node {
+    Text(String),
+    Styled(SpanFormat, Vec<Node>),
+}
+
+/// Split a template into formatted spans, merging neighbours that share a format
+pub fn parse(template: &str) -> Result<Vec<Span>, String> {
+    let chars: Vec<char> = template.chars().collect();
+    let mut parser = Parser { chars: &chars, pos: 0 };
+    let nodes = parser.nodes(None)?;
+
+    let mut spans = Vec::new();
+    flatten(&nodes, &SpanFormat::default(), &mut spans);
+    Ok(spans)
+}
+
+fn flatten(nodes: &[Node], format: &SpanFormat, spans: &mut Vec<Span>) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => match spans.last_mut() {
+                Some(last) if last.format == *format => last.text.push_str(text),
+                _ => spans.push(Span { text: text.clone(), format: format.clone() }),
+            },
+            Node::Styled(inner, children) => flatten(children, &format.nest(inner), spans),
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: &'a [char],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn starts_with(&self, token: &str) -> bool {
+        token.chars().enumerate().all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    /// Parse nodes until `closing` (consumed) or the end of the template
+    fn nodes(&mut self, closing: Option<&str>) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+        let flush = |text: &mut String, nodes: &mut Vec<Node>| {
+            if !text.is_empty() {
+                nodes.push(Node::Text(std::mem::take(text)));
+            }
+        };
+
+        while self.pos < self.chars.len() {
+            if let Some(closing) = closing {
+                // "**" inside *italic* opens bold rather than closing the italic
+                if self.starts_with(closing) && !(closing == "*" && self.starts_with("**")) {
+                    self.pos += closing.chars().count();
+                    flush(&mut text, &mut nodes);
+                    return Ok(nodes);
+                }
+            }
+
+            let c = self.chars[self.pos];
+            if c == '\\' && self.pos + 1 < self.chars.len() {
+                text.push(self.chars[self.pos + 1]);
+                self.pos += 2;
+            } else if self.starts_with("{{") {
+                // Placeholders are resolved later, so keep them intact
+                while self.pos < self.chars.len() && !self.starts_with("}}") {
+                    text.push(self.chars[self.pos]);
+                    self.pos += 1;
+                }
+                text.push_str("}}");
+                self.pos = (self.pos + 2).min(self.chars.len());
+            } else if c == '*' {
+                let (marker, format) = if self.starts_with("**") {
+                    ("**", SpanFormat { weight: Some(700.0), ..Default::default() })
+                } else {
+                    ("*", SpanFormat { style: Some(FontStyle::Italic), ..Default::default() })
+                };
+                flush(&mut text, &mut nodes);
+                self.pos += marker.len();
+                let children = self.nodes(Some(marker))?;
+                nodes.push(Node::Styled(format, children));
+            } else if c == '[' {
+                flush(&mut text, &mut nodes);
+                self.pos += 1;
+                nodes.extend(self.bracket()?);
+            } else {
+                text.push(c);
+                self.pos += 1;
+            }
+        }
+
+        match closing {
+            Some(closing) => Err(format!("Unclosed '{}' in markup", closing)),
+            None => {
+                flush(&mut text, &mut nodes);
+                Ok(nodes)
+            }
+        }
+    }
+
+    /// A `[...]{attributes}` span, after its opening bracket. Brackets without
+    /// attributes are kept as literal text.
+    fn bracket(&mut self) -> Result<Vec<Node>, String> {
+        let start = self.pos;
+        let children = match self.nodes(Some("]")) {
+            Ok(children) => children,
+            Err(_) => {
+                // No closing bracket: the rest is plain text after a literal '['
+                self.pos = start;
+                let mut nodes = vec![Node::Text("[".to_string())];
+                nodes.extend(self.nodes(None)?);
+                return Ok(nodes);
+            }
+        };
+
+        if !self.starts_with("{") || self.starts_with("{{") {
+            let mut nodes = vec![Node::Text("[".to_string())];
+            nodes.extend(children);
+            nodes.push(Node::Text("]".to_string()));
+            return Ok(nodes);
+        }
+
+        let close = self.chars[self.pos..].iter().position(|&c| c == '}')
+            .ok_or("Unclosed '{' after ']' in markup")?;
+        let attributes: String = self.chars[self.pos + 1..self.pos + close].iter().collect();
+        self.pos += close + 1;
+        Ok(vec![Node::Styled(parse_attributes(&attributes)?, children)])
+    }
+}
+
+fn parse_attributes(attributes: &str) -> Result<SpanFormat, String> {
+    let mut format = SpanFormat::default();
+    for attribute in attributes.split_whitespace() {
+        let (key, value) = attribute.split_once('=')
+            .ok_or_else(|| format!("Markup attribute '{}' has no value", attribute))?;
+        let number = |v: &str| v.parse::<f32>().map_err(|_| format!("Invalid {} '{}' in markup", key, value));
+        match key {
+            "color" => format.color = Some(value.to_string()),
+            "size" => {
+                format.size = Some(match value.strip_suffix('%') {
+                    Some(percent) => SpanSize::Percent(number(percent)?),
+                    None => SpanSize::Pixels(number(value)?),
+                })
+            }
+            "weight" => {
+                format.weight = Some(match value {
+                    "normal" => 400.0,
+                    "bold" => 700.0,
+                    _ => number(value)?,
+                })
+            }
+            "style" => format.style = Some(FontStyle::parse(Some(value))?),
+            _ => return Err(format!("Unknown markup attribute '{}'", key)),
+        }
+    }
+    Ok(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(template: &str) -> Vec<(String, SpanFormat)> {
+        parse(template).unwrap().into_iter().map(|span| (span.text, span.format)).collect()
+    }
+
+    fn plain(text: &str) -> (String, SpanFormat) {
+        (text.to_string(), SpanFormat::default())
+    }
+
+    fn bold() -> SpanFormat {
+        SpanFormat { weight: Some(700.0), ..Default::default() }
+    }
+
+    fn italic() -> SpanFormat {
+        SpanFormat { style: Some(FontStyle::Italic), ..Default::default() }
+    }
+
+    fn size(size: SpanSize) -> SpanFormat {
+        SpanFormat { size: Some(size), ..Default::default() }
+    }
+
+    #[test]
+    fn nests_formats() {
+        assert_eq!(spans("Hi **there** you"), [plain("Hi "), ("there".into(), bold()), plain(" you")]);
+        // "**" inside *italic* opens bold rather than closing the italic
+        let bold_italic = SpanFormat { weight: Some(700.0), style: Some(FontStyle::Italic), ..Default::default() };
+        assert_eq!(spans("*a **b** c*"), [("a ".into(), italic()), ("b".into(), bold_italic), (" c".into(), italic())]);
+        assert_eq!(
+            spans("[Row **C**]{color=#c00 weight=600}"),
+            [
+                ("Row ".into(), SpanFormat { color: Some("#c00".into()), weight: Some(600.0), ..Default::default() }),
+                ("C".into(), SpanFormat { color: Some("#c00".into()), weight: Some(700.0), ..Default::default() }),
+            ]
+        );
+        // Relative sizes compound
+        assert_eq!(
+            spans("[a [b]{size=50%}]{size=20}"),
+            [("a ".into(), size(SpanSize::Pixels(20.0))), ("b".into(), size(SpanSize::Pixels(10.0)))]
+        );
+        assert_eq!(spans("[[b]{size=50%}]{size=50%}"), [("b".into(), size(SpanSize::Percent(25.0)))]);
+    }
+
+    #[test]
+    fn escapes_and_placeholders_stay_text() {
+        assert_eq!(spans(r"\*not italic\* \[x]{size=2} a\\b"), [plain(r"*not italic* [x]{size=2} a\b")]);
+        assert_eq!(spans("*{{a*b}}*"), [("{{a*b}}".into(), italic())]);
+        assert_eq!(spans("[x]{{n}}"), [plain("[x]{{n}}")]);
+    }
+
+    #[test]
+    fn keeps_brackets_without_attributes() {
+        assert_eq!(spans("[12] and [13"), [plain("[12] and [13")]);
+        assert_eq!(spans("Seat [12 **VIP**"), [plain("Seat [12 "), ("VIP".into(), bold())]);
+    }
+
+    #[test]
+    fn rejects_unclosed_markers_and_bad_attributes() {
+        let error = |template: &str| parse(template).err().unwrap();
+        assert_eq!(error("**bold"), "Unclosed '**' in markup");
+        assert_eq!(error("*a **b* c"), "Unclosed '*' in markup");
+        assert_eq!(error("[x]{color=red"), "Unclosed '{' after ']' in markup");
+        assert_eq!(error("[x]{size=big}"), "Invalid size 'big' in markup");
+        assert_eq!(error("[x]{font=Lora}"), "Unknown markup attribute 'font'");
+        assert_eq!(error("[x]{bold}"), "Markup attribute 'bold' has no value");
+    }
+}
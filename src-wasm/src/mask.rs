@@ -0,0 +1,162 @@
+// Coverage masks for drawing text with outlines and shadows.
+
+use ab_glyph::{point, Font};
+use image::{Rgba, RgbaImage};
+
+use crate::text_layout::{PositionedGlyph, TextFont};
+use crate::ticket_renderer::blend_pixel;
+
+/// Per-pixel coverage (0..1) over a region of the image starting at (x, y)
+#[derive(Clone)]
+pub struct Mask {
+    pub x: i32,
+    pub y: i32,
+    pub width: usize,
+    pub height: usize,
+    data: Vec<f32>,
+}
+
+impl Mask {
+    pub fn new(x: i32, y: i32, width: usize, height: usize) -> Self {
+        Mask { x, y, width, height, data: vec![0.0; width * height] }
+    }
+
+    fn get(&self, x: i32, y: i32) -> f32 {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return 0.0;
+        }
+        self.data[y as usize * self.width + x as usize]
+    }
+
+    /// Add the coverage of a glyph from a line starting at `x`, with the baseline at `baseline_y`
+    pub fn add_glyph(&mut self, font: &TextFont, positioned: &PositionedGlyph, x: f32, baseline_y: f32) {
+        let glyph = positioned.id.with_scale_and_position(positioned.scale, point(x + positioned.x, baseline_y + positioned.y));
+        let Some(outlined) = font.glyphs.outline_glyph(glyph) else {
+            return;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|gx, gy, coverage| {
+            let px = bounds.min.x as i32 + gx as i32 - self.x;
+            let py = bounds.min.y as i32 + gy as i32 - self.y;
+            if px >= 0 && py >= 0 && (px as usize) < self.width && (py as usize) < self.height {
+                let cell = &mut self.data[py as usize * self.width + px as usize];
+                *cell = (*cell + coverage).min(1.0);
+            }
+        });
+    }
+
+    /// Add the coverage of a mask over the same region
+    pub fn add(&mut self, other: &Mask) {
+        for (cell, coverage) in self.data.iter_mut().zip(&other.data) {
+            *cell = (*cell + coverage).min(1.0);
+        }
+    }
+
+    /// Grow the covered shape by `radius` pixels, anti-aliased at the new edge
+    pub fn dilate(&self, radius: f32) -> Mask {
+        let reach = radius.ceil() as i32 + 1;
+        let mut out = Mask::new(self.x, self.y, self.width, self.height);
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                let mut value: f32 = 0.0;
+                for dy in -reach..=reach {
+                    for dx in -reach..=reach {
+                        let coverage = self.get(x + dx, y + dy);
+                        if coverage > value {
+                            let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                            value = value.max(coverage * (radius + 1.0 - distance).clamp(0.0, 1.0));
+                        }
+                    }
+                }
+                out.data[y as usize * self.width + x as usize] = value;
+            }
+        }
+        out
+    }
+
+    /// Gaussian blur with the given standard deviation
+    pub fn blur(&self, sigma: f32) -> Mask {
+        if sigma <= 0.0 {
+            return self.clone();
+        }
+        let reach = (sigma * 3.0).ceil() as i32;
+        let kernel: Vec<f32> = (-reach..=reach).map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp()).collect();
+        let total: f32 = kernel.iter().sum();
+
+        let pass = |source: &Mask, horizontal: bool| {
+            let mut out = Mask::new(source.x, source.y, source.width, source.height);
+            for y in 0..source.height as i32 {
+                for x in 0..source.width as i32 {
+                    let sum: f32 = kernel
+                        .iter()
+                        .zip(-reach..=reach)
+                        .map(|(k, i)| k * if horizontal { source.get(x + i, y) } else { source.get(x, y + i) })
+                        .sum();
+                    out.data[y as usize * source.width + x as usize] = sum / total;
+                }
+            }
+            out
+        };
+        pass(&pass(self, true), false)
+    }
+
+    /// Blend `color` onto `img` through the mask, moved by (dx, dy)
+    pub fn fill(&self, img: &mut RgbaImage, dx: i32, dy: i32, color: Rgba<u8>) {
+        let (img_w, img_h) = img.dimensions();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let coverage = self.data[y * self.width + x];
+                if coverage <= 0.0 {
+                    continue;
+                }
+                let px = self.x + x as i32 + dx;
+                let py = self.y + y as i32 + dy;
+                if px >= 0 && py >= 0 && (px as u32) < img_w && (py as u32) < img_h {
+                    let alpha = (coverage * color[3] as f32).round() as u8;
+                    blend_pixel(img, px as u32, py as u32, Rgba([color[0], color[1], color[2], alpha]));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 11 x 11 mask with only its centre covered
+    fn dot() -> Mask {
+        let mut mask = Mask::new(0, 0, 11, 11);
+        mask.data[5 * 11 + 5] = 1.0;
+        mask
+    }
+
+    #[test]
+    fn dilates_to_a_disc_with_a_soft_edge() {
+        let grown = dot().dilate(2.0);
+        assert_eq!(grown.get(5, 5), 1.0);
+        assert_eq!(grown.get(7, 5), 1.0);
+        assert_eq!(grown.get(6, 6), 1.0);
+        // √5 and √8 pixels out, past the radius
+        assert!((grown.get(7, 6) - (3.0 - 5f32.sqrt())).abs() < 1e-6);
+        assert!((grown.get(7, 7) - (3.0 - 8f32.sqrt())).abs() < 1e-6);
+        assert_eq!(grown.get(8, 5), 0.0);
+        // Radius 0 leaves the shape as it was
+        assert_eq!(dot().dilate(0.0).data, dot().data);
+    }
+
+    #[test]
+    fn blurs_symmetrically_keeping_total_coverage() {
+        let blurred = dot().blur(1.0);
+        let total: f32 = blurred.data.iter().sum();
+        assert!((total - 1.0).abs() < 1e-5, "{}", total);
+        // The centre keeps the square of the kernel's middle weight
+        let middle = 1.0 / (-3..=3).map(|i: i32| (-(i * i) as f32 / 2.0).exp()).sum::<f32>();
+        assert!((blurred.get(5, 5) - middle * middle).abs() < 1e-6);
+        for (x, y) in [(4, 5), (6, 5), (5, 4), (5, 6)] {
+            assert_eq!(blurred.get(x, y), blurred.get(6, 5));
+        }
+        assert!(blurred.get(6, 5) < blurred.get(5, 5) && blurred.get(6, 6) < blurred.get(6, 5));
+        assert_eq!(dot().blur(0.0).data, dot().data);
+    }
+}
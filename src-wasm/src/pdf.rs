@@ -0,0 +1,535 @@
+use image::RgbaImage;
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use rustybuzz::ttf_parser::{self, GlyphId};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+
+use crate::drawing::{DrawOp, Drawing, Paint};
+
+const POINTS_PER_MM: f64 = 72.0 / 25.4;
+
+const CATALOG_ID: usize = 1;
+const PAGES_ID: usize = 2;
+
+/// A font file embedded once for the whole document
+struct EmbeddedFont<'f> {
+    data: &'f [u8],
+    /// Id of the Type0 font object pages refer to
+    id: usize,
+    glyphs: BTreeSet<u16>,
+}
+
+/// Minimal PDF 1.4 writer: pages are vector drawings sized to the paper in mm,
+/// with text set in embedded fonts and images kept at their own resolution.
+/// Pages are written as they are added; fonts are completed by `finish` once
+/// every glyph they need is known.
+pub struct PdfWriter<'f> {
+    buf: Vec<u8>,
+    /// Byte offset of each object, indexed by object id - 1
+    offsets: Vec<usize>,
+    page_ids: Vec<usize>,
+    fonts: Vec<EmbeddedFont<'f>>,
+    /// Borrowed images, such as ticket artwork, shared by every page they appear on
+    images: Vec<(*const RgbaImage, usize)>,
+}
+
+/// Colour and opacity currently set in a content stream
+#[derive(Clone, Copy, PartialEq)]
+struct PaintState {
+    fill: Option<[u8; 3]>,
+    stroke: Option<[u8; 3]>,
+    alpha: u8,
+}
+
+/// Names of the objects a page's content uses
+#[derive(Default)]
+struct PageResources {
+    fonts: BTreeMap<usize, usize>,
+    images: BTreeMap<usize, usize>,
+    alphas: BTreeSet<u8>,
+}
+
+impl<'f> PdfWriter<'f> {
+    pub fn new() -> Self {
+        let mut buf = Vec::new();
+        // Binary comment marks the file as 8-bit for transfer tools
+        buf.extend_from_slice(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n");
+
+        PdfWriter {
+            buf,
+            // Catalog and page tree are written last but reserve the first ids
+            offsets: vec![0, 0],
+            page_ids: Vec::new(),
+            fonts: Vec::new(),
+            images: Vec::new(),
+        }
+    }
+
+    /// Append a page of `width_mm` x `height_mm` showing `drawing`, whose
+    /// coordinates are pixels at `dpi` from the top-left corner of the page.
+    pub fn add_page(&mut self, drawing: &Drawing<'f>, width_mm: f64, height_mm: f64, dpi: f64) {
+        let width_pt = width_mm * POINTS_PER_MM;
+        let height_pt = height_mm * POINTS_PER_MM;
+
+        let mut resources = PageResources::default();
+        // Pixels to points, with y pointing down from the top of the page
+        let mut content = format!("q {:.6} 0 0 {:.6} 0 {:.4} cm\n", 72.0 / dpi, -72.0 / dpi, height_pt);
+        let mut state = PaintState { fill: None, stroke: None, alpha: 255 };
+        let mut saved = Vec::new();
+
+        for op in &drawing.ops {
+            match op {
+                DrawOp::Rect { x, y, width, height, color } => {
+                    set_paint(&mut content, &mut state, &mut resources, Paint::Fill(*color));
+                    let _ = writeln!(content, "{} {} {} {} re f", num(*x), num(*y), num(*width), num(*height));
+                }
+                DrawOp::Image { image, x, y, width, height } => {
+                    let id = self.image_id(image, matches!(image, Cow::Borrowed(_)));
+                    let name = resources.images.len();
+                    let name = *resources.images.entry(id).or_insert(name);
+                    // Image space is a unit square with the first row at the top
+                    let _ = writeln!(
+                        content,
+                        "q {} 0 0 {} {} {} cm /Im{} Do Q",
+                        num(*width),
+                        num(-height),
+                        num(*x),
+                        num(y + height),
+                        name
+                    );
+                }
+                DrawOp::Text { font, size, paint, glyphs } => {
+                    let index = self.font_index(font);
+                    self.fonts[index].glyphs.extend(glyphs.iter().map(|glyph| glyph.id));
+                    let name = resources.fonts.len();
+                    let name = *resources.fonts.entry(self.fonts[index].id).or_insert(name);
+
+                    set_paint(&mut content, &mut state, &mut resources, *paint);
+                    let _ = write!(content, "BT /F{} {} Tf ", name, num(*size));
+                    match paint {
+                        // PDF strokes are centred on the outline; the fill drawn
+                        // after it covers the inner half
+                        Paint::Stroke(_, width) => {
+                            let _ = write!(content, "1 Tr {} w 1 j ", num(2.0 * width));
+                        }
+                        Paint::Fill(_) => content.push_str("0 Tr "),
+                    }
+                    for glyph in glyphs {
+                        // Flip glyphs upright again in the y-down page space
+                        let _ = write!(content, "1 0 0 -1 {} {} Tm <{:04X}> Tj ", num(glyph.x), num(glyph.y), glyph.id);
+                    }
+                    content.push_str("ET\n");
+                }
+                DrawOp::Begin { transform, clip } => {
+                    saved.push(state);
+                    let [a, b, c, d, e, f] = transform.map(num);
+                    let _ = writeln!(content, "q {} {} {} {} {} {} cm", a, b, c, d, e, f);
+                    if let Some([x, y, width, height]) = clip {
+                        let _ = writeln!(content, "{} {} {} {} re W n", num(*x), num(*y), num(*width), num(*height));
+                    }
+                }
+                DrawOp::End => {
+                    content.push_str("Q\n");
+                    state = saved.pop().unwrap_or(state);
+                }
+            }
+        }
+        content.push_str("Q\n");
+
+        let content_id = self.reserve_id();
+        let page_id = self.reserve_id();
+        self.write_stream(content_id, "/Filter /FlateDecode", &compress_to_vec_zlib(content.as_bytes(), 6));
+
+        let mut dict = String::new();
+        if !resources.fonts.is_empty() {
+            dict.push_str("/Font <<");
+            for (id, name) in &resources.fonts {
+                let _ = write!(dict, " /F{} {} 0 R", name, id);
+            }
+            dict.push_str(" >> ");
+        }
+        if !resources.images.is_empty() {
+            dict.push_str("/XObject <<");
+            for (id, name) in &resources.images {
+                let _ = write!(dict, " /Im{} {} 0 R", name, id);
+            }
+            dict.push_str(" >> ");
+        }
+        if !resources.alphas.is_empty() {
+            dict.push_str("/ExtGState <<");
+            for alpha in &resources.alphas {
+                let _ = write!(dict, " /GS{} << /ca {} /CA {} >>", alpha, num(*alpha as f32 / 255.0), num(*alpha as f32 / 255.0));
+            }
+            dict.push_str(" >> ");
+        }
+
+        self.write_object(
+            page_id,
+            &format!(
+                "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {:.4} {:.4}] /Resources << {}>> /Contents {} 0 R >>",
+                PAGES_ID, width_pt, height_pt, dict, content_id
+            ),
+        );
+
+        self.page_ids.push(page_id);
+    }
+
+    /// Write the embedded fonts, page tree, catalog, cross-reference table and
+    /// trailer. Fails when a font pages refer to can't be embedded.
+    pub fn finish(mut self) -> Result<Vec<u8>, String> {
+        for font in std::mem::take(&mut self.fonts) {
+            self.write_font(&font)?;
+        }
+
+        let mut kids = String::new();
+        for id in &self.page_ids {
+            let _ = write!(kids, "{} 0 R ", id);
+        }
+
+        self.write_object(
+            PAGES_ID,
+            &format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.trim_end(), self.page_ids.len()),
+        );
+        self.write_object(
+            CATALOG_ID,
+            &format!("<< /Type /Catalog /Pages {} 0 R >>", PAGES_ID),
+        );
+
+        let xref_offset = self.buf.len();
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+        for offset in &self.offsets {
+            let _ = writeln!(xref, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(
+            xref,
+            "trailer\n<< /Size {} /Root {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1,
+            CATALOG_ID,
+            xref_offset
+        );
+        self.buf.extend_from_slice(xref.as_bytes());
+
+        Ok(self.buf)
+    }
+
+    /// Object id of `image`, writing it now unless it is `shared` and was
+    /// already written. Alpha goes in a soft mask when the image isn't opaque.
+    fn image_id(&mut self, image: &RgbaImage, shared: bool) -> usize {
+        let key = shared.then_some(image as *const RgbaImage);
+        if let Some((_, id)) = self.images.iter().find(|(ptr, _)| Some(*ptr) == key) {
+            return *id;
+        }
+
+        let image_id = self.reserve_id();
+        let mut dict = format!(
+            "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /FlateDecode",
+            image.width(),
+            image.height()
+        );
+        if image.pixels().any(|p| p[3] < 255) {
+            let alpha: Vec<u8> = image.pixels().map(|p| p[3]).collect();
+            let mask_id = self.reserve_id();
+            self.write_stream(
+                mask_id,
+                &format!(
+                    "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceGray /BitsPerComponent 8 /Filter /FlateDecode",
+                    image.width(),
+                    image.height()
+                ),
+                &compress_to_vec_zlib(&alpha, 6),
+            );
+            let _ = write!(dict, " /SMask {} 0 R", mask_id);
+        }
+        let rgb: Vec<u8> = image.pixels().flat_map(|p| [p[0], p[1], p[2]]).collect();
+        self.write_stream(image_id, &dict, &compress_to_vec_zlib(&rgb, 6));
+
+        if let Some(key) = key {
+            self.images.push((key, image_id));
+        }
+        image_id
+    }
+
+    /// Index of the embedded font for a font file, reserving its object id on first use
+    fn font_index(&mut self, data: &'f [u8]) -> usize {
+        if let Some(index) = self.fonts.iter().position(|font| std::ptr::eq(font.data, data)) {
+            return index;
+        }
+        let id = self.reserve_id();
+        self.fonts.push(EmbeddedFont { data, id, glyphs: BTreeSet::new() });
+        self.fonts.len() - 1
+    }
+
+    /// Write a font file as a Type0 font with Identity-H encoding, so content
+    /// streams show glyph ids directly, and a ToUnicode map for copying text.
+    /// The whole file is embedded; `glyphs` only sizes the width and Unicode tables.
+    fn write_font(&mut self, font: &EmbeddedFont) -> Result<(), String> {
+        let face = ttf_parser::Face::parse(font.data, 0).map_err(|e| format!("Failed to embed font: {}", e))?;
+        let units = face.units_per_em() as f32;
+        let scale = |value: f32| (value * 1000.0 / units).round() as i32;
+
+        let name: String = face
+            .names()
+            .into_iter()
+            .filter(|name| name.name_id == ttf_parser::name_id::POST_SCRIPT_NAME && name.is_unicode())
+            .find_map(|name| name.to_string())
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect();
+        let name = if name.is_empty() { format!("Font{}", font.id) } else { name };
+
+        // CFF outlines are embedded as OpenType, TrueType outlines as they are
+        let cff = font.data.starts_with(b"OTTO");
+        let file_id = self.reserve_id();
+        let descriptor_id = self.reserve_id();
+        let cid_font_id = self.reserve_id();
+        let to_unicode_id = self.reserve_id();
+
+        let file_dict = if cff {
+            "/Subtype /OpenType /Filter /FlateDecode".to_string()
+        } else {
+            format!("/Length1 {} /Filter /FlateDecode", font.data.len())
+        };
+        self.write_stream(file_id, &file_dict, &compress_to_vec_zlib(font.data, 6));
+
+        let bbox = face.global_bounding_box();
+        let italic_angle = face.italic_angle();
+        // Symbolic, plus italic when the font slants
+        let flags = if italic_angle != 0.0 { 4 | 64 } else { 4 };
+        self.write_object(
+            descriptor_id,
+            &format!(
+                "<< /Type /FontDescriptor /FontName /{} /Flags {} /FontBBox [{} {} {} {}] /ItalicAngle {} /Ascent {} /Descent {} /CapHeight {} /StemV 80 /{} {} 0 R >>",
+                name,
+                flags,
+                scale(bbox.x_min as f32),
+                scale(bbox.y_min as f32),
+                scale(bbox.x_max as f32),
+                scale(bbox.y_max as f32),
+                num(italic_angle),
+                scale(face.ascender() as f32),
+                scale(face.descender() as f32),
+                scale(face.capital_height().unwrap_or(face.ascender()) as f32),
+                if cff { "FontFile3" } else { "FontFile2" },
+                file_id,
+            ),
+        );
+
+        let mut widths = String::new();
+        for &glyph in &font.glyphs {
+            let advance = face.glyph_hor_advance(GlyphId(glyph)).unwrap_or(0);
+            let _ = write!(widths, "{} [{}] ", glyph, scale(advance as f32));
+        }
+        self.write_object(
+            cid_font_id,
+            &format!(
+                "<< /Type /Font /Subtype /{} /BaseFont /{} /CIDSystemInfo << /Registry (Adobe) /Ordering (Identity) /Supplement 0 >> /FontDescriptor {} 0 R /DW 0 /W [{}]{} >>",
+                if cff { "CIDFontType0" } else { "CIDFontType2" },
+                name,
+                descriptor_id,
+                widths.trim_end(),
+                if cff { "" } else { " /CIDToGIDMap /Identity" },
+            ),
+        );
+
+        self.write_stream(to_unicode_id, "", to_unicode(&face, &font.glyphs).as_bytes());
+
+        self.write_object(
+            font.id,
+            &format!(
+                "<< /Type /Font /Subtype /Type0 /BaseFont /{} /Encoding /Identity-H /DescendantFonts [{} 0 R] /ToUnicode {} 0 R >>",
+                name, cid_font_id, to_unicode_id
+            ),
+        );
+        Ok(())
+    }
+
+    fn reserve_id(&mut self) -> usize {
+        self.offsets.push(0);
+        self.offsets.len()
+    }
+
+    fn write_object(&mut self, id: usize, body: &str) {
+        self.offsets[id - 1] = self.buf.len();
+        self.buf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", id, body).as_bytes());
+    }
+
+    fn write_stream(&mut self, id: usize, dict_entries: &str, data: &[u8]) {
+        let dict = if dict_entries.is_empty() {
+            format!("/Length {}", data.len())
+        } else {
+            format!("{} /Length {}", dict_entries, data.len())
+        };
+
+        self.offsets[id - 1] = self.buf.len();
+        self.buf.extend_from_slice(format!("{} 0 obj\n<< {} >>\nstream\n", id, dict).as_bytes());
+        self.buf.extend_from_slice(data);
+        self.buf.extend_from_slice(b"\nendstream\nendobj\n");
+    }
+}
+
+/// Set the colour and opacity of `paint`, writing only what changed
+fn set_paint(content: &mut String, state: &mut PaintState, resources: &mut PageResources, paint: Paint) {
+    let (color, stroke) = match paint {
+        Paint::Fill(color) => (color, false),
+        Paint::Stroke(color, _) => (color, true),
+    };
+    let rgb = [color[0], color[1], color[2]];
+    let [r, g, b] = rgb.map(|c| num(c as f32 / 255.0));
+    if stroke && state.stroke != Some(rgb) {
+        let _ = writeln!(content, "{} {} {} RG", r, g, b);
+        state.stroke = Some(rgb);
+    } else if !stroke && state.fill != Some(rgb) {
+        let _ = writeln!(content, "{} {} {} rg", r, g, b);
+        state.fill = Some(rgb);
+    }
+    if state.alpha != color[3] {
+        let _ = writeln!(content, "/GS{} gs", color[3]);
+        resources.alphas.insert(color[3]);
+        state.alpha = color[3];
+    }
+}
+
+/// CMap from glyph ids to the characters that map to them in the font's cmap
+fn to_unicode(face: &ttf_parser::Face, glyphs: &BTreeSet<u16>) -> String {
+    let mut chars: BTreeMap<u16, char> = BTreeMap::new();
+    for subtable in face.tables().cmap.iter().flat_map(|cmap| cmap.subtables).filter(|subtable| subtable.is_unicode()) {
+        subtable.codepoints(|codepoint| {
+            let (Some(c), Some(glyph)) = (char::from_u32(codepoint), subtable.glyph_index(codepoint)) else {
+                return;
+            };
+            if glyphs.contains(&glyph.0) {
+                chars.entry(glyph.0).or_insert(c);
+            }
+        });
+    }
+
+    let mut cmap = String::from(
+        "/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+         /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+         /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n\
+         1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n",
+    );
+    let entries: Vec<(u16, char)> = chars.into_iter().collect();
+    // At most 100 entries per block
+    for block in entries.chunks(100) {
+        let _ = writeln!(cmap, "{} beginbfchar", block.len());
+        for (glyph, c) in block {
+            let utf16: String = c.encode_utf16(&mut [0; 2]).iter().map(|unit| format!("{:04X}", unit)).collect();
+            let _ = writeln!(cmap, "<{:04X}> <{}>", glyph, utf16);
+        }
+        cmap.push_str("endbfchar\n");
+    }
+    cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n");
+    cmap
+}
+
+/// Number for a content stream: at most three decimals, without trailing zeros
+fn num(value: f32) -> String {
+    let text = format!("{:.3}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    match text {
+        "-0" | "" => "0".to_string(),
+        _ => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drawing::Glyph;
+    use image::Rgba;
+    use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+    const FONT: &[u8] = include_bytes!("../../static/fonts/Roboto-Regular.ttf");
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack.windows(needle.len()).position(|window| window == needle)
+    }
+
+    fn count(haystack: &[u8], needle: &str) -> usize {
+        haystack.windows(needle.len()).filter(|window| *window == needle.as_bytes()).count()
+    }
+
+    /// Decompressed data of every Flate stream in the file
+    fn streams(pdf: &[u8]) -> Vec<Vec<u8>> {
+        let mut streams = Vec::new();
+        let mut rest = pdf;
+        while let Some(start) = find(rest, b" >>\nstream\n") {
+            let dict = String::from_utf8_lossy(&rest[..start]);
+            let length: usize = dict.rsplit("/Length ").next().unwrap().trim().parse().unwrap();
+            let data = &rest[start + 11..start + 11 + length];
+            if let Ok(data) = decompress_to_vec_zlib(data) {
+                streams.push(data);
+            }
+            rest = &rest[start + 11 + length..];
+        }
+        streams
+    }
+
+    #[test]
+    fn writes_vector_pages() {
+        let artwork = RgbaImage::from_pixel(4, 2, Rgba([200, 100, 0, 128]));
+        let glyph = ttf_parser::Face::parse(FONT, 0).unwrap().glyph_index('A').unwrap().0;
+        let mut drawing = Drawing::default();
+        drawing.ops.push(DrawOp::Image { image: Cow::Borrowed(&artwork), x: 0.0, y: 0.0, width: 400.0, height: 200.0 });
+        drawing.ops.push(DrawOp::Rect { x: 10.0, y: 20.0, width: 3.0, height: 40.0, color: Rgba([0, 0, 0, 255]) });
+        drawing.ops.push(DrawOp::Text {
+            font: FONT,
+            size: 24.0,
+            paint: Paint::Fill(Rgba([0, 0, 255, 255])),
+            glyphs: vec![Glyph { id: glyph, x: 50.0, y: 80.0 }],
+        });
+
+        let mut pdf = PdfWriter::new();
+        pdf.add_page(&drawing, 210.0, 297.0, 300.0);
+        pdf.add_page(&drawing, 210.0, 297.0, 300.0);
+        let pdf = pdf.finish().unwrap();
+
+        // startxref points at the table, and every entry at its object
+        let tail = std::str::from_utf8(&pdf[pdf.len() - 40..]).unwrap();
+        let xref_offset: usize = tail.rsplit("startxref\n").next().unwrap().lines().next().unwrap().parse().unwrap();
+        let xref = std::str::from_utf8(&pdf[xref_offset..]).unwrap();
+        assert!(xref.starts_with("xref\n0 "));
+        let size: usize = xref.lines().nth(1).unwrap().split(' ').nth(1).unwrap().parse().unwrap();
+        assert!(xref.contains(&format!("/Size {}", size)));
+        for (id, entry) in xref.lines().skip(3).take(size - 1).enumerate() {
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(pdf[offset..].starts_with(format!("{} 0 obj\n", id + 1).as_bytes()), "object {}", id + 1);
+        }
+
+        assert_eq!(count(&pdf, "/Count 2"), 1);
+        // A4 in points
+        assert_eq!(count(&pdf, "/MediaBox [0 0 595.2756 841.8898]"), 2);
+
+        // The artwork and its alpha are embedded once for both pages, as is the font
+        assert_eq!(count(&pdf, "/Subtype /Image"), 2);
+        assert_eq!(count(&pdf, "/SMask"), 1);
+        assert_eq!(count(&pdf, "/Subtype /Type0 /BaseFont /Roboto-Regular /Encoding /Identity-H"), 1);
+        assert_eq!(count(&pdf, "/FontFile2"), 1);
+
+        let streams = streams(&pdf);
+        let content = streams.iter().map(|s| String::from_utf8_lossy(s)).find(|s| s.contains(" cm\n")).unwrap();
+        assert!(content.starts_with("q 0.240000 0 0 -0.240000 0 841.8898 cm\n"));
+        assert!(content.contains("0 0 0 rg\n10 20 3 40 re f\n"));
+        assert!(content.contains(&format!("BT /F0 24 Tf 0 Tr 1 0 0 -1 50 80 Tm <{:04X}> Tj ET", glyph)));
+        assert!(content.contains("q 400 0 0 -200 0 200 cm /Im0 Do Q"));
+        assert_eq!(count(&pdf, &format!("<{:04X}> <0041>", glyph)), 1);
+    }
+
+    #[test]
+    fn fails_rather_than_leave_a_font_unwritten() {
+        let mut drawing = Drawing::default();
+        drawing.ops.push(DrawOp::Text {
+            font: b"not a font",
+            size: 12.0,
+            paint: Paint::Fill(Rgba([0, 0, 0, 255])),
+            glyphs: vec![Glyph { id: 1, x: 0.0, y: 0.0 }],
+        });
+
+        let mut pdf = PdfWriter::new();
+        pdf.add_page(&drawing, 210.0, 297.0, 300.0);
+        assert!(pdf.finish().unwrap_err().starts_with("Failed to embed font: "));
+    }
+}
@@ -0,0 +1,109 @@
+// Reed-Solomon error correction over GF(2^m), as used by DataMatrix and Aztec.
+
+pub struct GaloisField {
+    exp: Vec<u16>,
+    log: Vec<u16>,
+    size: usize,
+}
+
+impl GaloisField {
+    /// Field of `size` elements generated by the primitive polynomial `poly`
+    pub fn new(poly: u32, size: usize) -> Self {
+        let mut exp = vec![0u16; size];
+        let mut log = vec![0u16; size];
+        let mut x: u32 = 1;
+        for (i, e) in exp.iter_mut().enumerate() {
+            *e = x as u16;
+            x <<= 1;
+            if x as usize >= size {
+                x ^= poly;
+                x &= size as u32 - 1;
+            }
+            if i < size - 1 {
+                log[*e as usize] = i as u16;
+            }
+        }
+        GaloisField { exp, log, size }
+    }
+
+    fn mul(&self, a: u16, b: u16) -> u16 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        let l = (self.log[a as usize] as usize + self.log[b as usize] as usize) % (self.size - 1);
+        self.exp[l]
+    }
+
+    /// Generator polynomial with roots α^1 .. α^ec_len, highest degree first
+    fn generator(&self, ec_len: usize) -> Vec<u16> {
+        let mut g = vec![1u16];
+        for i in 1..=ec_len {
+            let root = self.exp[i % (self.size - 1)];
+            let mut next = vec![0u16; g.len() + 1];
+            for (j, &c) in g.iter().enumerate() {
+                next[j] ^= c;
+                next[j + 1] ^= self.mul(c, root);
+            }
+            g = next;
+        }
+        g
+    }
+
+    /// Error correction codewords for `data`
+    pub fn encode(&self, data: &[u16], ec_len: usize) -> Vec<u16> {
+        let g = self.generator(ec_len);
+        let mut ecc = vec![0u16; ec_len];
+        for &d in data {
+            let factor = d ^ ecc[0];
+            ecc.rotate_left(1);
+            ecc[ec_len - 1] = 0;
+            for (e, &coef) in ecc.iter_mut().zip(&g[1..]) {
+                *e ^= self.mul(coef, factor);
+            }
+        }
+        ecc
+    }
+
+    /// Whether `codeword` (data then check words) has the generator's roots
+    /// α^1 .. α^ec_len, as every valid codeword does
+    #[cfg(test)]
+    pub fn is_codeword(&self, codeword: &[u16], ec_len: usize) -> bool {
+        (1..=ec_len).all(|i| {
+            let root = self.exp[i % (self.size - 1)];
+            codeword.iter().fold(0, |acc, &c| self.mul(acc, root) ^ c) == 0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_datamatrix_reference_check_words() {
+        // ISO/IEC 16022 example: "123456" in a 10x10 symbol
+        let field = GaloisField::new(0x12D, 256);
+        assert_eq!(field.encode(&[142, 164, 186], 5), [114, 25, 5, 88, 102]);
+    }
+
+    #[test]
+    fn matches_aztec_mode_message_check_words() {
+        // Compact mode messages for 2 layers of 29 words and 4 layers of 64 words
+        let field = GaloisField::new(0x13, 16);
+        assert_eq!(field.encode(&[0b0101, 0b1100], 5), [1, 12, 2, 12, 13]);
+        assert_eq!(field.encode(&[0b1111, 0b1111], 5), [4, 1, 3, 4, 12]);
+    }
+
+    #[test]
+    fn check_words_complete_a_codeword() {
+        for (poly, size) in [(0x13, 16), (0x43, 64), (0x12D, 256), (0x409, 1024), (0x1069, 4096)] {
+            let field = GaloisField::new(poly, size);
+            let data: Vec<u16> = (0..40).map(|i| ((i * 37 + 11) % size) as u16).collect();
+            let mut codeword = data.clone();
+            codeword.extend(field.encode(&data, 12));
+            assert!(field.is_codeword(&codeword, 12), "GF({})", size);
+            codeword[3] ^= 1;
+            assert!(!field.is_codeword(&codeword, 12), "GF({})", size);
+        }
+    }
+}
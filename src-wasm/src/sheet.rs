@@ -0,0 +1,255 @@
+use image::{ImageBuffer, Rgba, RgbaImage};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::ops::Range;
+use crate::SheetConfig;
+use crate::drawing::{self, DrawOp, Drawing};
+use crate::ticket_renderer::{composite_image, TicketRenderer};
+
+/// Pixel geometry of a sheet, computed once per render job and shared by every page.
+pub struct SheetLayout {
+    /// Device resolution the pixel sizes are computed at
+    pub dpi: f64,
+    pub page_width_px: u32,
+    pub page_height_px: u32,
+    pub rows: usize,
+    pub cols: usize,
+    cell_width_px: u32,
+    cell_height_px: u32,
+    /// Template size after uniform scaling into a cell
+    pub ticket_width_px: u32,
+    pub ticket_height_px: u32,
+    margin_left_px: u32,
+    margin_top_px: u32,
+    spacing_x_px: u32,
+    spacing_y_px: u32,
+}
+
+impl SheetLayout {
+    pub fn new(config: &SheetConfig, template_width: u32, template_height: u32, dpi: f64) -> Result<Self, String> {
+        let mm_per_inch = 25.4;
+        let pixels_per_mm = dpi / mm_per_inch;
+
+        // Calculate ticket size in mm
+        let ticket_width_mm = if config.cols > 0 {
+            (config.paper_width_mm
+                - config.margin_left_mm
+                - config.margin_right_mm
+                - (config.cols - 1) as f64 * config.spacing_x_mm)
+            / config.cols as f64
+        } else {
+            0.0
+        };
+
+        let ticket_height_mm = if config.rows > 0 {
+            (config.paper_height_mm
+                - config.margin_top_mm
+                - config.margin_bottom_mm
+                - (config.rows - 1) as f64 * config.spacing_y_mm)
+            / config.rows as f64
+        } else {
+            0.0
+        };
+
+        if ticket_width_mm <= 0.0 || ticket_height_mm <= 0.0 {
+            return Err("Invalid ticket dimensions".to_string());
+        }
+
+        if template_width == 0 || template_height == 0 {
+            return Err("Invalid template dimensions (zero)".to_string());
+        }
+
+        // Pixel sizes must match TypeScript's Math.round()
+        let cell_width_px = (ticket_width_mm * pixels_per_mm).round() as u32;
+        let cell_height_px = (ticket_height_mm * pixels_per_mm).round() as u32;
+
+        let scale_x = cell_width_px as f32 / template_width as f32;
+        let scale_y = cell_height_px as f32 / template_height as f32;
+        let scale = scale_x.min(scale_y); // Uniform scaling - CRITICAL
+
+        Ok(SheetLayout {
+            dpi,
+            page_width_px: (config.paper_width_mm * pixels_per_mm).round() as u32,
+            page_height_px: (config.paper_height_mm * pixels_per_mm).round() as u32,
+            rows: config.rows,
+            cols: config.cols,
+            cell_width_px,
+            cell_height_px,
+            ticket_width_px: (template_width as f32 * scale).round() as u32,
+            ticket_height_px: (template_height as f32 * scale).round() as u32,
+            margin_left_px: (config.margin_left_mm * pixels_per_mm).round() as u32,
+            margin_top_px: (config.margin_top_mm * pixels_per_mm).round() as u32,
+            spacing_x_px: (config.spacing_x_mm * pixels_per_mm).round() as u32,
+            spacing_y_px: (config.spacing_y_mm * pixels_per_mm).round() as u32,
+        })
+    }
+
+    pub fn tickets_per_page(&self) -> usize {
+        self.rows * self.cols
+    }
+
+    /// Top-left corner of the ticket in grid slot `slot`, centred within its cell
+    fn ticket_origin(&self, slot: usize) -> (u32, u32) {
+        let row = slot / self.cols;
+        let col = slot % self.cols;
+
+        let cell_x = self.margin_left_px + col as u32 * (self.cell_width_px + self.spacing_x_px);
+        let cell_y = self.margin_top_px + row as u32 * (self.cell_height_px + self.spacing_y_px);
+
+        let offset_x = self.cell_width_px.saturating_sub(self.ticket_width_px) / 2;
+        let offset_y = self.cell_height_px.saturating_sub(self.ticket_height_px) / 2;
+
+        (cell_x + offset_x, cell_y + offset_y)
+    }
+}
+
+/// Number of sheets needed to lay out `record_count` tickets
+pub fn page_count(record_count: usize, tickets_per_page: usize) -> usize {
+    if tickets_per_page == 0 {
+        return 0;
+    }
+    record_count.div_ceil(tickets_per_page)
+}
+
+/// Variables the sheet renderer adds to every record, all counted from 1
+pub const BUILTIN_VARIABLES: [&str; 6] = ["_index", "_page", "_page_count", "_row", "_col", "_total"];
+
+/// Where the records of a job sit among all of its tickets, for the built-in
+/// variables: the index of the first record, the number of tickets and the
+/// sheet grid. A preview rendering tickets one at a time sets these to the
+/// ticket's place in the whole job.
+#[derive(Deserialize, Clone, Copy)]
+pub struct Numbering {
+    pub first_index: usize,
+    pub total: usize,
+    pub rows: usize,
+    pub cols: usize,
+}
+
+impl Numbering {
+    /// Numbering of all `record_count` tickets of a job laid out on `layout`
+    pub fn new(layout: &SheetLayout, record_count: usize) -> Self {
+        Numbering { first_index: 0, total: record_count, rows: layout.rows, cols: layout.cols }
+    }
+}
+
+/// `record` plus the built-in variables for the job's record `index`. A
+/// record's own fields take precedence, so existing data keeps its meaning.
+fn with_builtins(record: &HashMap<String, String>, index: usize, numbering: &Numbering) -> HashMap<String, String> {
+    let index = numbering.first_index + index;
+    let cols = numbering.cols.max(1);
+    let tickets_per_page = (numbering.rows * cols).max(1);
+    let slot = index % tickets_per_page;
+    let values = [
+        index + 1,
+        index / tickets_per_page + 1,
+        page_count(numbering.total, tickets_per_page),
+        slot / cols + 1,
+        slot % cols + 1,
+        numbering.total,
+    ];
+
+    let mut record = record.clone();
+    for (name, value) in BUILTIN_VARIABLES.iter().zip(values) {
+        record.entry(name.to_string()).or_insert_with(|| value.to_string());
+    }
+    record
+}
+
+/// Indices of the records on sheet `page_index`. Page 0 is always valid so an
+/// empty job still yields a blank sheet.
+fn page_range(layout: &SheetLayout, record_count: usize, page_index: usize) -> Result<Range<usize>, String> {
+    let tickets_per_page = layout.tickets_per_page();
+    let page_count = page_count(record_count, tickets_per_page);
+    if page_index > 0 && page_index >= page_count {
+        return Err(format!("Page {} out of range ({} pages)", page_index, page_count));
+    }
+
+    let first = (page_index * tickets_per_page).min(record_count);
+    Ok(first..(first + tickets_per_page).min(record_count))
+}
+
+/// Render sheet `page_index` of `records`, laid out in grid order.
+pub fn render_page(
+    layout: &SheetLayout,
+    renderer: &TicketRenderer,
+    records: &[HashMap<String, String>],
+    numbering: &Numbering,
+    page_index: usize,
+) -> Result<RgbaImage, String> {
+    let page = page_range(layout, records.len(), page_index)?;
+
+    // Create white background
+    let mut sheet_img: RgbaImage = ImageBuffer::from_pixel(
+        layout.page_width_px,
+        layout.page_height_px,
+        Rgba([255, 255, 255, 255]),
+    );
+
+    for (slot, index) in page.enumerate() {
+        let record = with_builtins(&records[index], index, numbering);
+        let ticket_img = renderer.render(&record, index, layout.ticket_width_px, layout.ticket_height_px)
+            .map_err(|e| format!("Failed to render ticket {}: {}", index, e))?;
+
+        let (x, y) = layout.ticket_origin(slot);
+        composite_image(&mut sheet_img, &ticket_img, x, y);
+    }
+
+    Ok(sheet_img)
+}
+
+/// Draw sheet `page_index` of `records` as vector operations in page pixels,
+/// each ticket clipped to its own box as a rendered ticket would be.
+pub fn draw_page<'f>(
+    layout: &SheetLayout,
+    renderer: &'f TicketRenderer,
+    records: &[HashMap<String, String>],
+    numbering: &Numbering,
+    page_index: usize,
+) -> Result<Drawing<'f>, String> {
+    let mut drawing = Drawing::default();
+    for (slot, index) in page_range(layout, records.len(), page_index)?.enumerate() {
+        let record = with_builtins(&records[index], index, numbering);
+        let (x, y) = layout.ticket_origin(slot);
+        drawing.ops.push(DrawOp::Begin {
+            transform: drawing::translation(x as f32, y as f32),
+            clip: Some([0.0, 0.0, layout.ticket_width_px as f32, layout.ticket_height_px as f32]),
+        });
+        renderer.draw(&mut drawing, &record, index, layout.ticket_width_px, layout.ticket_height_px)
+            .map_err(|e| format!("Failed to render ticket {}: {}", index, e))?;
+        drawing.ops.push(DrawOp::End);
+    }
+
+    Ok(drawing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builtins(record: &HashMap<String, String>) -> Vec<(&str, &str)> {
+        BUILTIN_VARIABLES.iter().map(|name| (*name, record[*name].as_str())).collect()
+    }
+
+    #[test]
+    fn record_fields_win_over_builtins() {
+        let numbering = Numbering { first_index: 0, total: 7, rows: 2, cols: 3 };
+        let record = HashMap::from([("_index".to_string(), "A-17".to_string())]);
+        let record = with_builtins(&record, 4, &numbering);
+        assert_eq!(
+            builtins(&record),
+            [("_index", "A-17"), ("_page", "1"), ("_page_count", "2"), ("_row", "2"), ("_col", "2"), ("_total", "7")]
+        );
+    }
+
+    #[test]
+    fn numbers_tickets_by_their_place_in_the_job() {
+        // Ticket 8 of 10 rendered on its own, as the preview does
+        let numbering = Numbering { first_index: 7, total: 10, rows: 2, cols: 3 };
+        let record = with_builtins(&HashMap::new(), 0, &numbering);
+        assert_eq!(
+            builtins(&record),
+            [("_index", "8"), ("_page", "2"), ("_page_count", "2"), ("_row", "1"), ("_col", "2"), ("_total", "10")]
+        );
+    }
+}
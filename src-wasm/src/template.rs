@@ -0,0 +1,419 @@
+// Stamp template placeholders, matching resolveTemplate in src/lib/engine/template.ts.
+// Both must agree with the shared test vectors in src/lib/engine/template-vectors.json.
+
+use crate::filters::Filter;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+/// The parts of a project data source templates refer to
+#[derive(Deserialize, Clone)]
+pub struct DataSource {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String, // "csv" | "sequential" | "random"
+    /// Column names of a CSV source
+    #[serde(default)]
+    pub columns: Vec<String>,
+}
+
+/// A stamp template compiled once per job, then evaluated for each record.
+///
+/// Each `{{expression}}` in the source is replaced by its value:
+/// - `{{name}}` is the record value `name` (a scalar source or CSV column)
+/// - `{{source.field}}` is CSV column `field`, if `source` is a CSV source
+/// - `{{.field}}` is CSV column `field`, if there is exactly one CSV source
+/// - `{{"text"}}` is literal text
+/// - `{{value | filter:arg | ...}}` passes the value through filters
+/// - `{{a == b}}` and `{{a != b}}` are "true" or empty
+/// - `{{condition ? then : else}}` picks a branch by whether `condition` is
+///   truthy: anything but empty, "0", "false" or "no"
+///
+/// Parentheses group, e.g. `{{vip ? (seat | pad:3) : "GA"}}`; without them a
+/// filter in the `then` branch takes arguments up to its maximum. Whitespace
+/// around names is ignored; anything unresolved is empty. A source or column
+/// name that isn't a valid expression, like `{{Price (EUR)}}`, is looked up
+/// as written.
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+enum Part {
+    Text(String),
+    Expression(Expression),
+}
+
+impl Template {
+    /// Parse `template`, resolving names against `sources`. Fails on the first
+    /// invalid expression.
+    pub fn compile(template: &str, sources: &[DataSource]) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find("{{") {
+            text.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            // Expressions are one or more characters other than '}'
+            let end = after.find('}').filter(|&end| end > 0 && after[end..].starts_with("}}"));
+            match end {
+                Some(end) => {
+                    let source = &after[..end];
+                    let expression = match Expression::parse(source, sources) {
+                        Ok(expression) => expression,
+                        Err(e) => Expression::literal_name(source.trim(), sources)
+                            .ok_or_else(|| format!("In '{{{{{}}}}}': {}", source.trim(), e))?,
+                    };
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Expression(expression));
+                    rest = &after[end + 2..];
+                }
+                None => {
+                    text.push('{');
+                    rest = &rest[start + 1..];
+                }
+            }
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts })
+    }
+
+    pub fn render(&self, record: &HashMap<String, String>) -> String {
+        let mut result = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => result.push_str(text),
+                Part::Expression(expression) => result.push_str(&expression.evaluate(record)),
+            }
+        }
+        result
+    }
+
+    /// Names, as written, that resolve to no key in `keys`
+    pub fn unknown_variables(&self, keys: &HashSet<String>) -> Vec<&str> {
+        let mut unknown = Vec::new();
+        for part in &self.parts {
+            if let Part::Expression(expression) = part {
+                expression.unknown_variables(keys, &mut unknown);
+            }
+        }
+        unknown
+    }
+}
+
+enum Expression {
+    /// A name as written, and the record key it resolves to, if any
+    Variable(String, Option<String>),
+    Literal(String),
+    Filtered(Box<Expression>, Vec<Filter>),
+    /// Equality (`true`) or inequality (`false`) of two values
+    Compare(Box<Expression>, bool, Box<Expression>),
+    Conditional(Box<Expression>, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    fn parse(source: &str, sources: &[DataSource]) -> Result<Self, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens: &tokens, sources, pos: 0, in_branch: false };
+        let expression = parser.conditional()?;
+        match parser.next() {
+            None => Ok(expression),
+            Some(token) => Err(format!("Unexpected {}", token)),
+        }
+    }
+
+    /// `name` as a variable, as written, when it names a source or column
+    fn literal_name(name: &str, sources: &[DataSource]) -> Option<Self> {
+        let field = name.split_once('.').map_or(name, |(_, field)| field);
+        let known = sources.iter().any(|s| s.name == field || s.columns.iter().any(|column| column == field));
+        known.then(|| Expression::Variable(name.to_string(), record_key(name, sources)))
+    }
+
+    fn evaluate(&self, record: &HashMap<String, String>) -> String {
+        match self {
+            Expression::Variable(_, key) => key.as_ref().and_then(|key| record.get(key)).cloned().unwrap_or_default(),
+            Expression::Literal(text) => text.clone(),
+            Expression::Filtered(value, filters) => {
+                filters.iter().fold(value.evaluate(record), |value, filter| filter.apply(&value))
+            }
+            Expression::Compare(left, equal, right) => {
+                let matches = (left.evaluate(record) == right.evaluate(record)) == *equal;
+                if matches { "true".to_string() } else { String::new() }
+            }
+            Expression::Conditional(condition, then, otherwise) => {
+                if truthy(&condition.evaluate(record)) { then.evaluate(record) } else { otherwise.evaluate(record) }
+            }
+        }
+    }
+
+    fn unknown_variables<'a>(&'a self, keys: &HashSet<String>, unknown: &mut Vec<&'a str>) {
+        match self {
+            Expression::Variable(name, key) => {
+                if !key.as_ref().is_some_and(|key| keys.contains(key)) && !unknown.contains(&name.as_str()) {
+                    unknown.push(name);
+                }
+            }
+            Expression::Literal(_) => {}
+            Expression::Filtered(value, _) => value.unknown_variables(keys, unknown),
+            Expression::Compare(left, _, right) => {
+                left.unknown_variables(keys, unknown);
+                right.unknown_variables(keys, unknown);
+            }
+            Expression::Conditional(condition, then, otherwise) => {
+                condition.unknown_variables(keys, unknown);
+                then.unknown_variables(keys, unknown);
+                otherwise.unknown_variables(keys, unknown);
+            }
+        }
+    }
+}
+
+fn truthy(value: &str) -> bool {
+    let value = value.trim();
+    !(value.is_empty() || value == "0" || value.eq_ignore_ascii_case("false") || value.eq_ignore_ascii_case("no"))
+}
+
+/// The record key `name` refers to, if it resolves
+fn record_key(name: &str, sources: &[DataSource]) -> Option<String> {
+    let Some((source, field)) = name.split_once('.') else {
+        return Some(name.to_string());
+    };
+    if field.contains('.') {
+        return None;
+    }
+
+    let mut csv_sources = sources.iter().filter(|s| s.kind == "csv");
+    let resolves = if source.is_empty() {
+        csv_sources.clone().count() == 1
+    } else {
+        csv_sources.any(|s| s.name == source)
+    };
+    resolves.then(|| field.to_string())
+}
+
+#[derive(PartialEq)]
+enum Token {
+    /// A name or unquoted argument; may contain inner spaces
+    Word(String),
+    Quoted(String),
+    Pipe,
+    Colon,
+    Question,
+    Equal,
+    NotEqual,
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "'{}'", word),
+            Token::Quoted(text) => write!(f, "\"{}\"", text),
+            Token::Pipe => write!(f, "'|'"),
+            Token::Colon => write!(f, "':'"),
+            Token::Question => write!(f, "'?'"),
+            Token::Equal => write!(f, "'=='"),
+            Token::NotEqual => write!(f, "'!='"),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        let (token, len) = match c {
+            _ if c.is_whitespace() => (None, c.len_utf8()),
+            '"' | '\'' => {
+                let end = rest[1..].find(c).ok_or_else(|| format!("Unclosed {} quote", c))?;
+                (Some(Token::Quoted(rest[1..end + 1].to_string())), end + 2)
+            }
+            '|' => (Some(Token::Pipe), 1),
+            ':' => (Some(Token::Colon), 1),
+            '?' => (Some(Token::Question), 1),
+            '(' => (Some(Token::Open), 1),
+            ')' => (Some(Token::Close), 1),
+            _ if rest.starts_with("==") => (Some(Token::Equal), 2),
+            _ if rest.starts_with("!=") => (Some(Token::NotEqual), 2),
+            _ => {
+                let end = rest
+                    .char_indices()
+                    .find(|&(i, c)| "\"'|:?()".contains(c) || rest[i..].starts_with("==") || rest[i..].starts_with("!="))
+                    .map_or(rest.len(), |(i, _)| i);
+                (Some(Token::Word(rest[..end].trim_end().to_string())), end)
+            }
+        };
+        tokens.extend(token);
+        rest = &rest[len..];
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    sources: &'a [DataSource],
+    pos: usize,
+    /// Inside the `then` branch of a conditional, where a ':' past a filter's
+    /// last argument ends the branch
+    in_branch: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: Token) -> bool {
+        let found = self.tokens.get(self.pos) == Some(&token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+        match self.next() {
+            Some(found) if *found == token => Ok(()),
+            Some(found) => Err(format!("Expected {} but found {}", token, found)),
+            None => Err(format!("Expected {}", token)),
+        }
+    }
+
+    /// `comparison ('?' conditional ':' conditional)?`
+    fn conditional(&mut self) -> Result<Expression, String> {
+        let condition = self.comparison()?;
+        if !self.eat(Token::Question) {
+            return Ok(condition);
+        }
+        let outer = std::mem::replace(&mut self.in_branch, true);
+        let then = self.conditional()?;
+        self.in_branch = outer;
+        self.expect(Token::Colon)?;
+        let otherwise = self.conditional()?;
+        Ok(Expression::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise)))
+    }
+
+    /// `pipeline (('==' | '!=') pipeline)?`
+    fn comparison(&mut self) -> Result<Expression, String> {
+        let left = self.pipeline()?;
+        let equal = if self.eat(Token::Equal) {
+            true
+        } else if self.eat(Token::NotEqual) {
+            false
+        } else {
+            return Ok(left);
+        };
+        let right = self.pipeline()?;
+        Ok(Expression::Compare(Box::new(left), equal, Box::new(right)))
+    }
+
+    /// `value ('|' name (':' argument)*)*`
+    fn pipeline(&mut self) -> Result<Expression, String> {
+        let value = self.value()?;
+        let mut filters = Vec::new();
+        while self.eat(Token::Pipe) {
+            let name = match self.next() {
+                Some(Token::Word(name)) => name.clone(),
+                Some(token) => return Err(format!("Expected a filter name but found {}", token)),
+                None => return Err("Missing filter name after '|'".to_string()),
+            };
+            let max_args = Filter::arity(&name).map_or(usize::MAX, |(_, max)| max);
+            let mut args = Vec::new();
+            while !(self.in_branch && args.len() == max_args) && self.eat(Token::Colon) {
+                match self.next() {
+                    Some(Token::Word(arg) | Token::Quoted(arg)) => args.push(arg.clone()),
+                    Some(token) => return Err(format!("Expected an argument for '{}' but found {}", name, token)),
+                    None => return Err(format!("Missing argument for '{}' after ':'", name)),
+                }
+            }
+            filters.push(Filter::parse(&name, &args)?);
+        }
+        Ok(if filters.is_empty() { value } else { Expression::Filtered(Box::new(value), filters) })
+    }
+
+    /// A name, quoted text or a parenthesized expression
+    fn value(&mut self) -> Result<Expression, String> {
+        match self.next() {
+            Some(Token::Word(name)) => Ok(Expression::Variable(name.clone(), record_key(name, self.sources))),
+            Some(Token::Quoted(text)) => Ok(Expression::Literal(text.clone())),
+            Some(Token::Open) => {
+                let outer = std::mem::replace(&mut self.in_branch, false);
+                let expression = self.conditional()?;
+                self.in_branch = outer;
+                self.expect(Token::Close)?;
+                Ok(expression)
+            }
+            Some(token) => Err(format!("Expected a value but found {}", token)),
+            None => Err("Expected a value".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A group of cases from template-vectors.json sharing sources and a record
+    #[derive(Deserialize)]
+    struct Vectors {
+        description: String,
+        #[serde(rename = "dataSources")]
+        data_sources: Vec<DataSource>,
+        record: HashMap<String, String>,
+        cases: Vec<Case>,
+    }
+
+    #[derive(Deserialize)]
+    struct Case {
+        template: String,
+        expected: Option<String>,
+        #[serde(default)]
+        error: bool,
+    }
+
+    #[test]
+    fn agrees_with_the_shared_vectors() {
+        let groups: Vec<Vectors> = serde_json::from_str(include_str!("../../src/lib/engine/template-vectors.json")).unwrap();
+        let mut failures = Vec::new();
+        for group in &groups {
+            for case in &group.cases {
+                let result = Template::compile(&case.template, &group.data_sources).map(|t| t.render(&group.record));
+                let passed = match (&result, &case.expected) {
+                    (Ok(rendered), Some(expected)) => rendered == expected,
+                    (Err(_), None) => case.error,
+                    _ => false,
+                };
+                if !passed {
+                    failures.push(format!("{}: {} gave {:?}", group.description, case.template, result));
+                }
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn looks_up_names_that_are_not_expressions_only_if_a_source_has_them() {
+        let record = HashMap::from([("Price (EUR)".to_string(), "12.50".to_string())]);
+        let guests = |columns: &[&str]| DataSource {
+            name: "guests".to_string(),
+            kind: "csv".to_string(),
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+        };
+
+        let template = Template::compile("{{Price (EUR)}} each", &[guests(&["Price (EUR)"])]).unwrap();
+        assert_eq!(template.render(&record), "12.50 each");
+        assert!(template.unknown_variables(&record.keys().cloned().collect()).is_empty());
+
+        let error = Template::compile("{{Price (EUR)}} each", &[guests(&["Price"])]).err();
+        assert_eq!(error.as_deref(), Some("In '{{Price (EUR)}}': Unexpected '('"));
+    }
+}
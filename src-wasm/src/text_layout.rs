@@ -0,0 +1,452 @@
+// Shaping and line breaking for text stamps.
+
+use ab_glyph::{Font, FontRef, GlyphId, PxScale, ScaleFont, VariableFont};
+use std::ops::Range;
+use rustybuzz::ttf_parser::Tag;
+use rustybuzz::{Direction, Face, UnicodeBuffer, Variation};
+use unicode_bidi::BidiInfo;
+use unicode_script::{Script, UnicodeScript};
+
+/// A font loaded both for shaping and for rasterizing glyphs
+pub struct TextFont<'a> {
+    pub glyphs: FontRef<'a>,
+    face: Face<'a>,
+    /// Font file, for embedding in PDFs
+    pub data: &'a [u8],
+    /// Axes set away from their default value
+    varied: Vec<[u8; 4]>,
+}
+
+impl<'a> TextFont<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, String> {
+        let glyphs = FontRef::try_from_slice(data).map_err(|e| e.to_string())?;
+        let face = Face::from_slice(data, 0).ok_or("Unsupported font data")?;
+        Ok(TextFont { glyphs, face, data, varied: Vec::new() })
+    }
+
+    pub fn has_axis(&self, tag: &[u8; 4]) -> bool {
+        self.glyphs.variations().iter().any(|axis| &axis.tag == tag)
+    }
+
+    /// Set a variable font axis, clamped to its range. Returns false when the
+    /// font has no such axis.
+    pub fn set_variation(&mut self, tag: &[u8; 4], value: f32) -> bool {
+        let Some(axis) = self.glyphs.variations().into_iter().find(|axis| &axis.tag == tag) else {
+            return false;
+        };
+        let value = value.clamp(axis.min_value, axis.max_value);
+        self.glyphs.set_variation(tag, value);
+        self.face.set_variations(&[Variation { tag: Tag::from_bytes(tag), value }]);
+        self.varied.retain(|varied| varied != tag);
+        if value != axis.default_value {
+            self.varied.push(*tag);
+        }
+        true
+    }
+
+    /// Whether glyphs are drawn from the font's default instance, so the
+    /// outlines in its file are the ones shown
+    pub fn is_default_instance(&self) -> bool {
+        self.varied.is_empty()
+    }
+
+    pub fn ascent(&self, scale: PxScale) -> f32 {
+        self.glyphs.as_scaled(scale).ascent()
+    }
+
+    pub fn descent(&self, scale: PxScale) -> f32 {
+        self.glyphs.as_scaled(scale).descent()
+    }
+}
+
+pub struct PositionedGlyph {
+    /// Style of the run the glyph was shaped in
+    pub style: usize,
+    /// Index of the font in that run's fallback chain
+    pub font: usize,
+    pub id: GlyphId,
+    pub scale: PxScale,
+    pub x: f32,
+    pub y: f32,
+}
+
+/// A shaped line; glyph positions are relative to the line start on its baseline
+pub struct LineLayout {
+    pub glyphs: Vec<PositionedGlyph>,
+    /// Total advance, including kerning
+    pub width: f32,
+}
+
+/// A range of text set in one font fallback chain and size. `style` is
+/// copied to the glyphs so callers can find the fonts and colour again.
+#[derive(Clone)]
+pub struct TextRun<'a, 'f> {
+    pub range: Range<usize>,
+    pub style: usize,
+    pub fonts: &'a [TextFont<'f>],
+    pub scale: PxScale,
+}
+
+/// Runs covering `range` of the text, rebased to start at zero
+pub fn runs_in<'a, 'f>(runs: &[TextRun<'a, 'f>], range: Range<usize>) -> Vec<TextRun<'a, 'f>> {
+    runs.iter()
+        .filter(|run| run.range.start < range.end && run.range.end > range.start)
+        .map(|run| TextRun {
+            range: run.range.start.max(range.start) - range.start..run.range.end.min(range.end) - range.start,
+            ..run.clone()
+        })
+        .collect()
+}
+
+/// Shape a single line of text set in one font fallback chain
+pub fn layout_line(fonts: &[TextFont], scale: PxScale, text: &str, letter_spacing: f32) -> LineLayout {
+    layout_runs(text, &[TextRun { range: 0..text.len(), style: 0, fonts, scale }], letter_spacing)
+}
+
+/// Shape a single line of styled runs. The line is split into bidi runs,
+/// shown in visual order, then into the styled runs, then into script runs
+/// shaped with that script's rules, then into runs of the first font covering
+/// each character. `letter_spacing` pixels are added between characters.
+pub fn layout_runs(text: &str, runs: &[TextRun], letter_spacing: f32) -> LineLayout {
+    let mut glyphs = Vec::new();
+    let mut x = 0.0;
+
+    let bidi = BidiInfo::new(text, None);
+    for paragraph in &bidi.paragraphs {
+        let (levels, visual_runs) = bidi.visual_runs(paragraph, paragraph.range.clone());
+        for visual in visual_runs {
+            let rtl = levels[visual.start].is_rtl();
+            let mut items: Vec<(&TextRun, usize, &str)> = Vec::new();
+            for run in runs.iter().filter(|run| run.range.start < visual.end && run.range.end > visual.start) {
+                let range = run.range.start.max(visual.start)..run.range.end.min(visual.end);
+                for item in script_runs(&text[range]) {
+                    items.extend(font_runs(run.fonts, item).into_iter().map(|(index, item)| (run, index, item)));
+                }
+            }
+            if rtl {
+                items.reverse();
+            }
+
+            for (run, index, item) in items {
+                let font = &run.fonts[index];
+                // Fallback fonts are sized to the same em as the first font
+                let scale = matching_scale(&run.fonts[0], font, run.scale);
+                let scaled = font.glyphs.as_scaled(scale);
+                let (sx, sy) = (scaled.h_scale_factor(), scaled.v_scale_factor());
+
+                let mut buffer = UnicodeBuffer::new();
+                buffer.push_str(item);
+                buffer.set_direction(if rtl { Direction::RightToLeft } else { Direction::LeftToRight });
+                buffer.guess_segment_properties();
+                let shaped = rustybuzz::shape(&font.face, &[], buffer);
+
+                let infos = shaped.glyph_infos();
+                for (i, (info, pos)) in infos.iter().zip(shaped.glyph_positions()).enumerate() {
+                    glyphs.push(PositionedGlyph {
+                        style: run.style,
+                        font: index,
+                        id: GlyphId(info.glyph_id as u16),
+                        scale,
+                        x: x + pos.x_offset as f32 * sx,
+                        y: -pos.y_offset as f32 * sy,
+                    });
+                    x += pos.x_advance as f32 * sx;
+                    // Space after each cluster, so marks stay on their base character
+                    if infos.get(i + 1).is_none_or(|next| next.cluster != info.cluster) {
+                        x += letter_spacing;
+                    }
+                }
+            }
+        }
+    }
+
+    // No spacing after the last character
+    if !glyphs.is_empty() {
+        x -= letter_spacing;
+    }
+
+    LineLayout { glyphs, width: x }
+}
+
+/// Characters of `text` that no font in the chain has a glyph for
+pub fn uncovered_chars(fonts: &[TextFont], text: &str) -> Vec<char> {
+    let mut missing: Vec<char> = text
+        .chars()
+        .filter(|&c| !joins_neighbours(c) && font_for(fonts, c).is_none())
+        .collect();
+    missing.sort_unstable();
+    missing.dedup();
+    missing
+}
+
+/// Scale for `font` whose em matches that of `primary` at `scale`
+fn matching_scale(primary: &TextFont, font: &TextFont, scale: PxScale) -> PxScale {
+    let em = |f: &TextFont| f.glyphs.units_per_em().unwrap_or(1000.0) / f.glyphs.height_unscaled();
+    let factor = em(primary) / em(font);
+    PxScale { x: scale.x * factor, y: scale.y * factor }
+}
+
+fn font_for(fonts: &[TextFont], c: char) -> Option<usize> {
+    fonts.iter().position(|font| font.glyphs.glyph_id(c).0 != 0)
+}
+
+/// Whitespace, controls and marks stay in the font of the text around them
+fn joins_neighbours(c: char) -> bool {
+    c.is_whitespace() || c.is_control() || c.script() == Script::Inherited
+}
+
+/// Split text into runs set in a single font, in logical order. Characters no
+/// font covers stay in the first font, which draws its missing-glyph box.
+fn font_runs<'t>(fonts: &[TextFont], text: &'t str) -> Vec<(usize, &'t str)> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut current = None;
+    for (i, c) in text.char_indices() {
+        if joins_neighbours(c) {
+            continue;
+        }
+        let font = font_for(fonts, c).unwrap_or(0);
+        match current {
+            Some(f) if f != font => {
+                runs.push((f, &text[start..i]));
+                start = i;
+            }
+            _ => {}
+        }
+        current = Some(font);
+    }
+    runs.push((current.unwrap_or(0), &text[start..]));
+    runs
+}
+
+/// Split text into runs of a single script, in logical order. Common and
+/// inherited characters (spaces, digits, marks) join the surrounding run.
+fn script_runs(text: &str) -> Vec<&str> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut current = Script::Common;
+    for (i, c) in text.char_indices() {
+        let script = c.script();
+        if matches!(script, Script::Common | Script::Inherited | Script::Unknown) {
+            continue;
+        }
+        if current != Script::Common && script != current {
+            runs.push(&text[start..i]);
+            start = i;
+        }
+        current = script;
+    }
+    runs.push(&text[start..]);
+    runs
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Wrap {
+    None,
+    Word,
+    Character,
+}
+
+impl Wrap {
+    pub fn parse(value: Option<&str>) -> Result<Self, String> {
+        match value {
+            None | Some("none") => Ok(Wrap::None),
+            Some("word") => Ok(Wrap::Word),
+            Some("character") => Ok(Wrap::Character),
+            Some(other) => Err(format!("Unknown wrap mode '{}'", other)),
+        }
+    }
+}
+
+/// Split `text` into lines no wider than `max_width`, returned as byte ranges
+/// of `text`. Newlines always break; word wrapping falls back to character
+/// breaks for words wider than a line.
+pub fn break_lines(text: &str, wrap: Wrap, max_width: f32, measure: impl Fn(Range<usize>) -> f32) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for paragraph in text.split('\n') {
+        let range = start..start + paragraph.trim_end_matches('\r').len();
+        start += paragraph.len() + 1;
+        match wrap {
+            Wrap::None => lines.push(range),
+            Wrap::Word => break_words(text, range, max_width, &measure, &mut lines),
+            Wrap::Character => break_characters(text, range, max_width, &measure, &mut lines),
+        }
+    }
+    lines
+}
+
+fn break_words(text: &str, paragraph: Range<usize>, max_width: f32, measure: &impl Fn(Range<usize>) -> f32, lines: &mut Vec<Range<usize>>) {
+    let mut line: Option<Range<usize>> = None;
+    for word in words(text, paragraph.clone()) {
+        let candidate = match &line {
+            Some(line) => line.start..word.end,
+            None => word.clone(),
+        };
+        if measure(candidate.clone()) <= max_width {
+            line = Some(candidate);
+            continue;
+        }
+
+        if let Some(line) = line.take() {
+            lines.push(line);
+        }
+        if measure(word.clone()) <= max_width {
+            line = Some(word);
+        } else {
+            // Too long for any line: break inside the word and carry on after its last piece
+            break_characters(text, word, max_width, measure, lines);
+            line = lines.pop();
+        }
+    }
+    lines.push(line.unwrap_or(paragraph.start..paragraph.start));
+}
+
+fn break_characters(text: &str, paragraph: Range<usize>, max_width: f32, measure: &impl Fn(Range<usize>) -> f32, lines: &mut Vec<Range<usize>>) {
+    let mut start = paragraph.start;
+    for (i, c) in text[paragraph.clone()].char_indices() {
+        let i = paragraph.start + i;
+        if i > start && measure(start..i + c.len_utf8()) > max_width {
+            lines.push(start..i);
+            start = i;
+        }
+    }
+    lines.push(start..paragraph.end);
+}
+
+/// Byte ranges of the whitespace-separated words in `range`
+fn words(text: &str, range: Range<usize>) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text[range.clone()].char_indices() {
+        let i = range.start + i;
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                words.push(s..i);
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        words.push(s..range.end);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTO: &[u8] = include_bytes!("../../static/fonts/Roboto-Regular.ttf");
+    // Unlike Roboto, has arrows
+    const INTER: &[u8] = include_bytes!("../../static/fonts/Inter-Regular.ttf");
+    // Has Arabic and Hebrew, which no bundled font covers
+    const DEJAVU: &[u8] = include_bytes!("../tests/fonts/DejaVuSans.ttf");
+
+    /// Glyph ids of a line shaped in `font`, in visual order
+    fn shaped(font: &[u8], text: &str) -> Vec<u16> {
+        let fonts = [TextFont::new(font).unwrap()];
+        layout_line(&fonts, PxScale::from(20.0), text, 0.0).glyphs.iter().map(|glyph| glyph.id.0).collect()
+    }
+
+    /// Glyph ids the font maps each character to, before shaping
+    fn nominal(font: &[u8], text: &str) -> Vec<u16> {
+        let font = TextFont::new(font).unwrap();
+        text.chars().map(|c| font.glyphs.glyph_id(c).0).collect()
+    }
+
+    /// Lines of `text` with every character 10px wide
+    fn lines(text: &str, wrap: Wrap, max_width: f32) -> Vec<&str> {
+        let measure = |range: Range<usize>| text[range].chars().count() as f32 * 10.0;
+        break_lines(text, wrap, max_width, measure).into_iter().map(|range| &text[range]).collect()
+    }
+
+    #[test]
+    fn breaks_only_at_newlines_without_wrap() {
+        assert_eq!(lines("first line\r\nsecond", Wrap::None, 10.0), ["first line", "second"]);
+        assert_eq!(lines("a\n\nb", Wrap::None, 10.0), ["a", "", "b"]);
+    }
+
+    #[test]
+    fn wraps_words() {
+        assert_eq!(lines("the quick brown fox", Wrap::Word, 100.0), ["the quick", "brown fox"]);
+        // Runs of spaces between lines are dropped, those within a line kept
+        assert_eq!(lines("one  two   three", Wrap::Word, 80.0), ["one  two", "three"]);
+        assert_eq!(lines("a\n\nb c", Wrap::Word, 10.0), ["a", "", "b", "c"]);
+        assert_eq!(lines("   ", Wrap::Word, 10.0), [""]);
+    }
+
+    #[test]
+    fn breaks_long_words_and_carries_on_after_them() {
+        // The last piece of the long word starts the next line
+        assert_eq!(lines("a verylongword b", Wrap::Word, 50.0), ["a", "veryl", "ongwo", "rd b"]);
+    }
+
+    #[test]
+    fn wraps_characters() {
+        assert_eq!(lines("abcdefghij", Wrap::Character, 40.0), ["abcd", "efgh", "ij"]);
+        assert_eq!(lines("ééééé", Wrap::Character, 20.0), ["éé", "éé", "é"]);
+        // A character wider than the line still gets a line of its own
+        assert_eq!(lines("abc", Wrap::Character, 5.0), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn runs_switch_to_the_first_font_with_a_glyph() {
+        let fonts = [TextFont::new(ROBOTO).unwrap(), TextFont::new(INTER).unwrap()];
+        assert_eq!(font_runs(&fonts, "Go ↑ now"), [(0, "Go "), (1, "↑ "), (0, "now")]);
+        // Leading spaces and marks join the run of the character they follow or precede
+        assert_eq!(font_runs(&fonts, " ↑\u{301}x"), [(1, " ↑\u{301}"), (0, "x")]);
+        // Nothing has a star, so it stays in the first font
+        assert_eq!(font_runs(&fonts, "a★b"), [(0, "a★b")]);
+        assert_eq!(font_runs(&fonts, ""), [(0, "")]);
+    }
+
+    #[test]
+    fn lists_uncovered_characters_once_in_order() {
+        let fonts = [TextFont::new(ROBOTO).unwrap(), TextFont::new(INTER).unwrap()];
+        assert_eq!(uncovered_chars(&fonts, "★ Ж ★ ↑ x"), ['Ж', '★']);
+        assert_eq!(uncovered_chars(&fonts[..1], "↑\n"), ['↑']);
+    }
+
+    #[test]
+    fn kerns_pairs() {
+        let fonts = [TextFont::new(ROBOTO).unwrap()];
+        // One pixel per font unit
+        let units = PxScale::from(fonts[0].glyphs.height_unscaled());
+        let width = |text: &str| layout_line(&fonts, units, text, 0.0).width;
+        assert_eq!((width("A"), width("V")), (1336.0, 1304.0));
+        assert_eq!(width("AV"), 1336.0 + 1304.0 - 87.0);
+        assert_eq!(layout_line(&fonts, units, "AV", 10.0).width, 1336.0 + 1304.0 - 87.0 + 10.0);
+    }
+
+    #[test]
+    fn shapes_arabic_in_contextual_forms_right_to_left() {
+        // Seen joins the lam-alef ligature, which meem doesn't join; shown
+        // right to left as the presentation forms of each
+        let expected = [nominal(DEJAVU, "\u{645}"), nominal(DEJAVU, "\u{FEFC}\u{FEB3}")].concat();
+        assert_eq!(shaped(DEJAVU, "\u{633}\u{644}\u{627}\u{645}"), expected);
+    }
+
+    #[test]
+    fn orders_hebrew_right_to_left_around_numbers() {
+        // Alef bet, then 12: the number keeps its own order at the left
+        assert_eq!(shaped(DEJAVU, "\u{5D0}\u{5D1} 12"), nominal(DEJAVU, "12 \u{5D1}\u{5D0}"));
+        assert_eq!(shaped(DEJAVU, "ab \u{5D0}\u{5D1} cd"), nominal(DEJAVU, "ab \u{5D1}\u{5D0} cd"));
+    }
+
+    #[test]
+    fn keeps_devanagari_signs_in_the_cluster_of_their_consonant() {
+        // No font here has Devanagari, so every glyph is a 908 unit wide
+        // missing-glyph box; letter spacing shows where clusters end
+        let fonts = [TextFont::new(ROBOTO).unwrap()];
+        let units = PxScale::from(fonts[0].glyphs.height_unscaled());
+        let xs = |text: &str| layout_line(&fonts, units, text, 100.0).glyphs.iter().map(|glyph| glyph.x).collect::<Vec<_>>();
+        // Ka ka, then ki ka: the vowel sign joins its consonant
+        assert_eq!(xs("\u{915}\u{915}"), [0.0, 1008.0]);
+        assert_eq!(xs("\u{915}\u{93F}\u{915}"), [0.0, 908.0, 1916.0]);
+        // As do a virama and an anusvara
+        assert_eq!(xs("\u{915}\u{94D}\u{915}\u{902}"), [0.0, 908.0, 1916.0, 2824.0]);
+    }
+}
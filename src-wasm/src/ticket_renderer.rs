@@ -1,8 +1,22 @@
 use image::{ImageBuffer, Rgba, RgbaImage};
 use serde::Deserialize;
-use std::collections::HashMap;
-use ab_glyph::{FontRef, PxScale, Font, ScaleFont};
-use imageproc::drawing::text_size;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use ab_glyph::PxScale;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::sync::Arc;
+use crate::barcode;
+use crate::aztec::{self, AztecMode};
+use crate::bit_matrix::BitMatrix;
+use crate::datamatrix;
+use crate::drawing::{self, Canvas, DrawOp, Drawing, Paint};
+use crate::mask::Mask;
+use crate::font_set::{FontSet, FontStyle, NORMAL_WEIGHT};
+use crate::markup::{self, Span, SpanFormat, SpanSize};
+use crate::template::{DataSource, Template};
+use crate::text_layout::{break_lines, layout_line, layout_runs, runs_in, uncovered_chars, LineLayout, TextFont, TextRun, Wrap};
+use crate::diagnostics::{Diagnostic, Diagnostics};
 
 #[derive(Deserialize, Clone)]
 pub struct TemplateData {
@@ -11,6 +25,8 @@ pub struct TemplateData {
     pub data: Vec<u8>, // RGBA bytes
 }
 
+// Stamps are parsed once per job, so text stamp options aren't boxed
+#[allow(clippy::large_enum_variant)]
 #[derive(Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum Stamp {
@@ -20,6 +36,10 @@ pub enum Stamp {
     Barcode(BarcodeStamp),
     #[serde(rename = "qrcode")]
     QrCode(QrCodeStamp),
+    #[serde(rename = "datamatrix")]
+    DataMatrix(DataMatrixStamp),
+    #[serde(rename = "aztec")]
+    Aztec(AztecStamp),
 }
 
 #[derive(Deserialize, Clone)]
@@ -29,17 +49,67 @@ pub struct TextStamp {
     pub y: f32,
     pub width: f32,
     pub height: f32,
+    /// Clockwise rotation in degrees around (x, y)
+    pub rotation: Option<f32>,
     pub template: String,
     #[serde(rename = "fontFamily")]
     pub font_family: String,
+    /// Families tried in order for characters `font_family` has no glyph for
+    #[serde(rename = "fallbackFonts")]
+    pub fallback_fonts: Option<Vec<String>>,
+    /// CSS weight (default 400); picks the closest registered face and sets the
+    /// `wght` axis of variable fonts
+    #[serde(rename = "fontWeight")]
+    pub font_weight: Option<f32>,
+    #[serde(rename = "fontStyle")]
+    pub font_style: Option<String>, // "normal" | "italic" | "oblique"
+    /// Variable font axis values by tag, e.g. {"wdth": 75, "slnt": -10}
+    #[serde(rename = "fontVariations")]
+    pub font_variations: Option<HashMap<String, f32>>,
+    /// Interpret inline markup in `template`: **bold**, *italic* and
+    /// [spans]{color=… size=… weight=… style=…}
+    pub markup: Option<bool>,
     #[serde(rename = "fontSize")]
     pub font_size: f32,
     pub color: String,
     pub alignment: String, // "left" | "center" | "right"
     #[serde(rename = "verticalAlign")]
     pub vertical_align: Option<String>, // "top" | "middle" | "bottom"
+    /// Fit the font size to the stamp's `width`/`height` box (measured around
+    /// the anchor point as the editor does), shrinking text that overflows it
     #[serde(rename = "autoSize")]
     pub auto_size: Option<bool>,
+    /// With `autoSize`, also enlarge short text to fill the box
+    #[serde(rename = "autoSizeGrow")]
+    pub auto_size_grow: Option<bool>,
+    pub wrap: Option<String>, // "none" | "word" | "character"
+    /// Distance between baselines as a multiple of the font size (default 1.2)
+    #[serde(rename = "lineHeight")]
+    pub line_height: Option<f32>,
+    /// Extra space between characters, in template pixels
+    #[serde(rename = "letterSpacing")]
+    pub letter_spacing: Option<f32>,
+    /// Outline drawn around the glyphs, `strokeWidth` template pixels thick
+    #[serde(rename = "strokeColor")]
+    pub stroke_color: Option<String>,
+    #[serde(rename = "strokeWidth")]
+    pub stroke_width: Option<f32>,
+    /// Drop shadow of the text and its outline; blur is in template pixels, as
+    /// for a canvas `shadowBlur`
+    #[serde(rename = "shadowColor")]
+    pub shadow_color: Option<String>,
+    #[serde(rename = "shadowOffsetX")]
+    pub shadow_offset_x: Option<f32>,
+    #[serde(rename = "shadowOffsetY")]
+    pub shadow_offset_y: Option<f32>,
+    #[serde(rename = "shadowBlur")]
+    pub shadow_blur: Option<f32>,
+    /// Rectangle drawn behind the text, `backgroundPadding` template pixels
+    /// larger than it on every side
+    #[serde(rename = "backgroundColor")]
+    pub background_color: Option<String>,
+    #[serde(rename = "backgroundPadding")]
+    pub background_padding: Option<f32>,
 }
 
 #[derive(Deserialize, Clone)]
@@ -49,8 +119,16 @@ pub struct BarcodeStamp {
     pub y: f32,
     pub width: f32,
     pub height: f32,
+    /// Clockwise rotation in degrees around (x, y)
+    pub rotation: Option<f32>,
     pub template: String,
     pub format: String, // "code128", "code39", etc.
+    #[serde(rename = "hriPosition")]
+    pub hri_position: Option<String>, // "above" | "below" | "none"
+    #[serde(rename = "hriFontFamily")]
+    pub hri_font_family: Option<String>,
+    #[serde(rename = "hriFontSize")]
+    pub hri_font_size: Option<f32>,
 }
 
 #[derive(Deserialize, Clone)]
@@ -60,19 +138,150 @@ pub struct QrCodeStamp {
     pub y: f32,
     pub width: f32,
     pub height: f32,
+    /// Clockwise rotation in degrees around (x, y)
+    pub rotation: Option<f32>,
     pub template: String,
     #[serde(rename = "errorCorrection")]
     pub error_correction: String, // "L" | "M" | "Q" | "H"
+    /// Module size in template pixels; best fit to the box when absent
+    #[serde(rename = "moduleSize")]
+    pub module_size: Option<f32>,
+    /// Light margin around the symbol, in modules (default 4)
+    #[serde(rename = "quietZone")]
+    pub quiet_zone: Option<u32>,
+    #[serde(rename = "foregroundColor")]
+    pub foreground_color: Option<String>, // dark modules, default black
+    #[serde(rename = "backgroundColor")]
+    pub background_color: Option<String>, // light modules and quiet zone, default white; may be transparent
+    /// Swap the colours to print light modules on a dark background
+    pub inverted: Option<bool>,
+    /// Image drawn over a cleared square in the centre of the symbol
+    pub logo: Option<TemplateData>,
+    /// Logo width as a fraction of the symbol width (default 0.2)
+    #[serde(rename = "logoRatio")]
+    pub logo_ratio: Option<f32>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct DataMatrixStamp {
+    pub id: String,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// Clockwise rotation in degrees around (x, y)
+    pub rotation: Option<f32>,
+    pub template: String,
+    pub shape: Option<String>, // "square" | "rectangle"
+}
+
+#[derive(Deserialize, Clone)]
+pub struct AztecStamp {
+    pub id: String,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// Clockwise rotation in degrees around (x, y)
+    pub rotation: Option<f32>,
+    pub template: String,
+    pub mode: Option<String>, // "auto" | "compact" | "full"
+}
+
+impl Stamp {
+    /// Position and rotation shared by every stamp type
+    fn placement(&self) -> (f32, f32, f32, f32, f32) {
+        let (x, y, width, height, rotation) = match self {
+            Stamp::Text(s) => (s.x, s.y, s.width, s.height, s.rotation),
+            Stamp::Barcode(s) => (s.x, s.y, s.width, s.height, s.rotation),
+            Stamp::QrCode(s) => (s.x, s.y, s.width, s.height, s.rotation),
+            Stamp::DataMatrix(s) => (s.x, s.y, s.width, s.height, s.rotation),
+            Stamp::Aztec(s) => (s.x, s.y, s.width, s.height, s.rotation),
+        };
+        (x, y, width, height, rotation.unwrap_or(0.0).rem_euclid(360.0))
+    }
+
+    /// Kind for messages, id and template source
+    fn template(&self) -> (&'static str, &str, &String) {
+        match self {
+            Stamp::Text(s) => ("Text", &s.id, &s.template),
+            Stamp::Barcode(s) => ("Barcode", &s.id, &s.template),
+            Stamp::QrCode(s) => ("QR", &s.id, &s.template),
+            Stamp::DataMatrix(s) => ("DataMatrix", &s.id, &s.template),
+            Stamp::Aztec(s) => ("Aztec", &s.id, &s.template),
+        }
+    }
+}
+
+/// Fonts and colour for one format of a text stamp; `size` is a multiple of
+/// the stamp's font size
+struct TextStyle<'a> {
+    fonts: Vec<TextFont<'a>>,
+    size: f32,
+    color: Rgba<u8>,
+}
+
+/// A style picked when the renderer is set up, loaded for each ticket
+struct StyleChoice {
+    fonts: Vec<FontChoice>,
+    size: f32,
+    color: Rgba<u8>,
+}
+
+impl StyleChoice {
+    fn load(&self) -> Result<TextStyle<'_>, String> {
+        let fonts = self.fonts.iter().map(FontChoice::load).collect::<Result<_, _>>()?;
+        Ok(TextStyle { fonts, size: self.size, color: self.color })
+    }
+}
+
+/// A face picked from the loaded fonts, with the variable font axes to set
+struct FontChoice {
+    data: Arc<[u8]>,
+    variations: Vec<([u8; 4], f32)>,
+}
+
+impl FontChoice {
+    /// Parse the face for shaping and drawing. Axes the font lacks are
+    /// ignored, as in CSS font-variation-settings.
+    fn load(&self) -> Result<TextFont<'_>, String> {
+        let mut font = TextFont::new(&self.data)?;
+        for (tag, value) in &self.variations {
+            font.set_variation(tag, *value);
+        }
+        Ok(font)
+    }
+}
+
+/// A laid out line, with its baseline measured from the top of the text block
+struct TextLine {
+    layout: LineLayout,
+    baseline: f32,
+    descent: f32,
 }
 
 pub struct TicketRenderer {
     template_image: RgbaImage,
     stamps: Vec<Stamp>,
-    fonts: HashMap<String, Vec<u8>>,
+    /// Compiled template of each stamp, as formatted spans
+    templates: Vec<Vec<(Template, SpanFormat)>>,
+    /// Style of each span of text stamps (none for other stamps), and the
+    /// font of each barcode's human-readable line
+    styles: Vec<Vec<StyleChoice>>,
+    hri_fonts: Vec<Option<FontChoice>>,
+    /// Device resolution of rendered tickets, used for print-size checks
+    dpi: f64,
+    diagnostics: RefCell<Diagnostics>,
 }
 
 impl TicketRenderer {
-    pub fn new(template_data: TemplateData, stamps: Vec<Stamp>, fonts: HashMap<String, Vec<u8>>) -> Result<Self, String> {
+    pub fn new(
+        template_data: TemplateData,
+        stamps: Vec<Stamp>,
+        sources: Vec<DataSource>,
+        fonts: HashMap<String, Vec<u8>>,
+        dpi: f64,
+    ) -> Result<Self, String> {
         // Convert template data bytes to RgbaImage
         let template_image = ImageBuffer::from_raw(
             template_data.width,
@@ -83,18 +292,102 @@ impl TicketRenderer {
 
         // Validate all fonts
         for (font_name, font_bytes) in &fonts {
-            FontRef::try_from_slice(font_bytes)
+            TextFont::new(font_bytes)
                 .map_err(|e| format!("Failed to load font '{}': {}", font_name, e))?;
         }
+        let fonts = FontSet::new(fonts)?;
+
+        // Compile templates once, reporting their errors before any ticket
+        let templates = stamps
+            .iter()
+            .map(|stamp| {
+                let (kind, id, template) = stamp.template();
+                let compile = || -> Result<Vec<(Template, SpanFormat)>, String> {
+                    // Spans of template text with their formatting; without
+                    // markup the whole template is a single span
+                    let spans = match stamp {
+                        Stamp::Text(s) if s.markup.unwrap_or(false) => markup::parse(template)?,
+                        _ => vec![Span { text: template.clone(), format: SpanFormat::default() }],
+                    };
+                    spans
+                        .into_iter()
+                        .map(|span| Ok((Template::compile(&span.text, &sources)?, span.format)))
+                        .collect()
+                };
+                compile().map_err(|e| format!("{} stamp '{}': {}", kind, id, e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        // Pick the fonts of every style once rather than for each ticket
+        let mut styles = Vec::new();
+        let mut hri_fonts = Vec::new();
+        for (stamp, spans) in stamps.iter().zip(&templates) {
+            styles.push(match stamp {
+                Stamp::Text(s) => spans
+                    .iter()
+                    .map(|(_, format)| text_style(&fonts, s, format))
+                    .collect::<Result<Vec<_>, String>>()
+                    .map_err(|e| format!("Text stamp '{}': {}", s.id, e))?,
+                _ => Vec::new(),
+            });
+            hri_fonts.push(match stamp {
+                Stamp::Barcode(s) => hri_font(&fonts, s).map_err(|e| format!("Barcode stamp '{}': {}", s.id, e))?,
+                _ => None,
+            });
+        }
 
         Ok(TicketRenderer {
             template_image,
             stamps,
-            fonts,
+            templates,
+            styles,
+            hri_fonts,
+            dpi,
+            diagnostics: RefCell::new(Diagnostics::default()),
         })
     }
 
-    pub fn render(&self, record: &HashMap<String, String>, target_width: u32, target_height: u32) -> Result<RgbaImage, String> {
+    /// Check that every placeholder resolves to one of the record `keys`.
+    /// Unknown ones would render empty on every ticket: with `strict` they
+    /// fail the job, listing each stamp and placeholder, otherwise they are
+    /// reported as diagnostics.
+    pub fn check_record_keys(&self, keys: &HashSet<String>, strict: bool) -> Result<(), String> {
+        let mut problems = Vec::new();
+        for (stamp, spans) in self.stamps.iter().zip(&self.templates) {
+            let mut unknown: Vec<&str> = Vec::new();
+            for name in spans.iter().flat_map(|(template, _)| template.unknown_variables(keys)) {
+                if !unknown.contains(&name) {
+                    unknown.push(name);
+                }
+            }
+            if !unknown.is_empty() {
+                let (_, id, _) = stamp.template();
+                let names: Vec<String> = unknown.iter().map(|name| format!("{{{{{}}}}}", name)).collect();
+                problems.push((id, names.join(", ")));
+            }
+        }
+
+        if strict && !problems.is_empty() {
+            let listed: Vec<String> = problems.iter().map(|(id, names)| format!("stamp '{}': {}", id, names)).collect();
+            return Err(format!("Unknown template variables in {}", listed.join("; ")));
+        }
+        for (id, names) in problems {
+            self.diagnostics.borrow_mut().push(Diagnostic {
+                code: "unknown_variable",
+                stamp_id: id.to_string(),
+                record_index: None,
+                message: format!("No data field for {}", names),
+            });
+        }
+        Ok(())
+    }
+
+    /// Diagnostics collected since the last call
+    pub fn take_diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.borrow_mut().take()
+    }
+
+    pub fn render(&self, record: &HashMap<String, String>, record_index: usize, target_width: u32, target_height: u32) -> Result<RgbaImage, String> {
         // Create output image with scaled dimensions
         let mut img: RgbaImage = ImageBuffer::new(target_width, target_height);
 
@@ -111,136 +404,366 @@ impl TicketRenderer {
             img.put_pixel(x, y, *pixel);
         }
 
+        self.render_stamps(&mut Canvas::Raster(&mut img), record, record_index, target_width, target_height)?;
+        Ok(img)
+    }
+
+    /// Draw the ticket as vector operations over a `target_width` x
+    /// `target_height` pixel box, with the template image stretched under them
+    pub fn draw<'f>(&'f self, drawing: &mut Drawing<'f>, record: &HashMap<String, String>, record_index: usize, target_width: u32, target_height: u32) -> Result<(), String> {
+        drawing.ops.push(DrawOp::Image {
+            image: Cow::Borrowed(&self.template_image),
+            x: 0.0,
+            y: 0.0,
+            width: target_width as f32,
+            height: target_height as f32,
+        });
+        self.render_stamps(&mut Canvas::Vector(drawing), record, record_index, target_width, target_height)
+    }
+
+    fn render_stamps<'f>(&'f self, canvas: &mut Canvas<'_, 'f>, record: &HashMap<String, String>, record_index: usize, target_width: u32, target_height: u32) -> Result<(), String> {
         // Calculate scale factor for stamp coordinates
         // Stamps are positioned relative to ORIGINAL template size
         let template_scale_x = target_width as f32 / self.template_image.width() as f32;
         let template_scale_y = target_height as f32 / self.template_image.height() as f32;
 
         // Render stamps
-        for stamp in &self.stamps {
-            match stamp {
-                Stamp::Text(text_stamp) => {
-                    self.render_text_stamp(&mut img, text_stamp, record, template_scale_x, template_scale_y)?;
+        for (index, stamp) in self.stamps.iter().enumerate() {
+            let (x, y, width, height, rotation) = stamp.placement();
+            let pivot = (x * template_scale_x, y * template_scale_y);
+
+            // Ticket colour under the stamp, for QR contrast checks; vector
+            // drawings sample the template artwork
+            let backdrop = match stamp {
+                Stamp::QrCode(_) => {
+                    let (bx, by, bw, bh) = rotated_bounds(pivot, width * template_scale_x, height * template_scale_y, rotation);
+                    canvas.average_color(bx, by, bw, bh).unwrap_or_else(|| {
+                        let (tx, ty, tw, th) = rotated_bounds((x, y), width, height, rotation);
+                        average_color(&self.template_image, tx, ty, tw, th)
+                    })
                 }
-                Stamp::Barcode(barcode_stamp) => {
-                    self.render_barcode_stamp(&mut img, barcode_stamp, record, template_scale_x, template_scale_y)?;
+                _ => WHITE,
+            };
+
+            if rotation == 0.0 {
+                self.render_stamp(canvas, index, record, record_index, backdrop, template_scale_x, template_scale_y)?;
+                continue;
+            }
+            match canvas {
+                Canvas::Raster(img) => {
+                    // Rotated stamps are drawn upright on their own layer, then turned into place.
+                    // The layer also covers any part of the upright box hanging off the ticket.
+                    let layer_width = target_width.max(((x + width) * template_scale_x).ceil() as u32);
+                    let layer_height = target_height.max(((y + height) * template_scale_y).ceil() as u32);
+                    let mut layer = RgbaImage::new(layer_width, layer_height);
+                    self.render_stamp(&mut Canvas::Raster(&mut layer), index, record, record_index, backdrop, template_scale_x, template_scale_y)?;
+                    composite_rotated(img, &layer, pivot, rotation);
                 }
-                Stamp::QrCode(qr_stamp) => {
-                    self.render_qr_stamp(&mut img, qr_stamp, record, template_scale_x, template_scale_y)?;
+                Canvas::Vector(drawing) => {
+                    drawing.ops.push(DrawOp::Begin { transform: drawing::rotation(pivot, rotation), clip: None });
+                    self.render_stamp(&mut Canvas::Vector(drawing), index, record, record_index, backdrop, template_scale_x, template_scale_y)?;
+                    drawing.ops.push(DrawOp::End);
                 }
             }
         }
 
-        Ok(img)
+        Ok(())
     }
 
-    fn render_text_stamp(
-        &self,
-        img: &mut RgbaImage,
+    #[allow(clippy::too_many_arguments)]
+    fn render_stamp<'f>(
+        &'f self,
+        canvas: &mut Canvas<'_, 'f>,
+        index: usize,
+        record: &HashMap<String, String>,
+        record_index: usize,
+        backdrop: Rgba<u8>,
+        scale_x: f32,
+        scale_y: f32,
+    ) -> Result<(), String> {
+        let spans = &self.templates[index];
+        // Only text stamps have more than one span
+        let text = || spans.iter().map(|(template, _)| template.render(record)).collect::<String>();
+        match &self.stamps[index] {
+            Stamp::Text(text_stamp) => self.render_text_stamp(canvas, text_stamp, spans, &self.styles[index], record, record_index, scale_x, scale_y),
+            Stamp::Barcode(barcode_stamp) => {
+                self.render_barcode_stamp(canvas, barcode_stamp, &text(), self.hri_fonts[index].as_ref(), record_index, scale_x, scale_y)
+            }
+            Stamp::QrCode(qr_stamp) => self.render_qr_stamp(canvas, qr_stamp, &text(), record_index, backdrop, scale_x, scale_y),
+            Stamp::DataMatrix(dm_stamp) => self.render_datamatrix_stamp(canvas, dm_stamp, &text(), record_index, scale_x, scale_y),
+            Stamp::Aztec(aztec_stamp) => self.render_aztec_stamp(canvas, aztec_stamp, &text(), record_index, scale_x, scale_y),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn render_text_stamp<'f>(
+        &'f self,
+        canvas: &mut Canvas<'_, 'f>,
         stamp: &TextStamp,
+        spans: &[(Template, SpanFormat)],
+        styles: &'f [StyleChoice],
         record: &HashMap<String, String>,
+        record_index: usize,
         scale_x: f32,
         scale_y: f32,
     ) -> Result<(), String> {
-        let text = resolve_template(&stamp.template, record);
+        // Resolve placeholders span by span, each in the style of its span
+        let mut text = String::new();
+        let mut styled: Vec<(Range<usize>, usize)> = Vec::new();
+        for (style, (template, _)) in spans.iter().enumerate() {
+            let resolved = template.render(record);
+            if resolved.is_empty() {
+                continue;
+            }
+            styled.push((text.len()..text.len() + resolved.len(), style));
+            text.push_str(&resolved);
+        }
 
         if text.is_empty() {
             return Ok(());
         }
 
-        // Get font data for this stamp's font family
-        let font_data = self.fonts.get(&stamp.font_family)
-            .ok_or_else(|| format!("Font '{}' not found in loaded fonts", stamp.font_family))?;
+        let styles = styles
+            .iter()
+            .map(StyleChoice::load)
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|e| format!("Text stamp '{}': {}", stamp.id, e))?;
+
+        let mut missing: Vec<char> = styled
+            .iter()
+            .flat_map(|(range, style)| uncovered_chars(&styles[*style].fonts, &text[range.clone()]))
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+        if !missing.is_empty() {
+            self.diagnostics.borrow_mut().push(Diagnostic {
+                code: "missing_glyphs",
+                stamp_id: stamp.id.clone(),
+                record_index: Some(record_index),
+                message: format!(
+                    "No font covers {}",
+                    missing.iter().map(|c| format!("'{}' (U+{:04X})", c, *c as u32)).collect::<Vec<_>>().join(", "),
+                ),
+            });
+        }
 
-        // Load font from font data
-        let font = FontRef::try_from_slice(font_data)
-            .map_err(|e| format!("Failed to load font '{}': {}", stamp.font_family, e))?;
+        let wrap = Wrap::parse(stamp.wrap.as_deref())
+            .map_err(|e| format!("Text stamp '{}': {}", stamp.id, e))?;
+        let auto_size = stamp.auto_size.unwrap_or(false);
+        let line_height = stamp.line_height.unwrap_or(1.2);
 
         // Stamp coordinates are relative to ORIGINAL template image size
-        // (x, y) is the ANCHOR POINT, not a bounding box
-        // Scale the anchor point to match the SCALED template size
-        let anchor_x = (stamp.x * scale_x) as i32;
-        let anchor_y = (stamp.y * scale_y) as i32;
-
-        // Parse color
-        let color = parse_color(&stamp.color).unwrap_or(Rgba([0, 0, 0, 255]));
+        let box_x = stamp.x * scale_x;
+        let box_y = stamp.y * scale_y;
+        let box_width = stamp.width * scale_x;
+        let box_height = stamp.height * scale_y;
+
+        // Auto-sized stamps are placed by their (x, y) anchor point; fixed-size
+        // stamps align their text inside the (x, y, width, height) box
+        let (anchor_x, anchor_y) = if auto_size {
+            (box_x, box_y)
+        } else {
+            let x = match stamp.alignment.as_str() {
+                "center" => box_x + box_width / 2.0,
+                "right" => box_x + box_width,
+                _ => box_x,
+            };
+            let y = match stamp.vertical_align.as_deref() {
+                Some("middle") => box_y + box_height / 2.0,
+                Some("bottom") => box_y + box_height,
+                _ => box_y,
+            };
+            (x, y)
+        };
 
         // Calculate font size scaled - use average of x and y scale for font size
         let avg_scale = (scale_x + scale_y) / 2.0;
-        let font_size = stamp.font_size * avg_scale;
-        let scale = PxScale::from(font_size);
-
-        // Calculate text dimensions
-        let (text_width, text_height) = text_size(scale, &font, &text);
-
-        // Calculate position based on alignment relative to anchor point
-        // Horizontal alignment: left/center/right relative to anchor
-        let text_x = match stamp.alignment.as_str() {
-            "center" => anchor_x - (text_width as i32 / 2),
-            "right" => anchor_x - text_width as i32,
-            _ => anchor_x, // left - anchor is at left edge of text
+        let letter_spacing = stamp.letter_spacing.unwrap_or(0.0) * avg_scale;
+
+        // Lines are shaped once and the same layout is used to measure and draw.
+        // Each line is as tall as its largest style, with baselines `line_height`
+        // times the larger font size of neighbouring lines apart.
+        let layout = |font_size: f32| {
+            let runs: Vec<TextRun> = styled
+                .iter()
+                .map(|(range, style)| TextRun {
+                    range: range.clone(),
+                    style: *style,
+                    fonts: &styles[*style].fonts,
+                    scale: PxScale::from(font_size * styles[*style].size),
+                })
+                .collect();
+            let max_width = if wrap == Wrap::None { f32::INFINITY } else { box_width };
+            let measure = |range: Range<usize>| layout_runs(&text[range.clone()], &runs_in(&runs, range), letter_spacing).width;
+
+            let mut lines = Vec::new();
+            let mut baseline = 0.0;
+            let mut previous_size = None;
+            for range in break_lines(&text, wrap, max_width, measure) {
+                let line_runs = runs_in(&runs, range.clone());
+                // Empty lines take the metrics of the text they follow
+                let metrics: Vec<&TextRun> = if line_runs.is_empty() {
+                    runs.iter().rfind(|run| run.range.start <= range.start).into_iter().collect()
+                } else {
+                    line_runs.iter().collect()
+                };
+                let ascent = metrics.iter().map(|run| run.fonts[0].ascent(run.scale)).fold(0.0, f32::max);
+                let descent = metrics.iter().map(|run| run.fonts[0].descent(run.scale)).fold(0.0, f32::min);
+                let size = metrics.iter().map(|run| font_size * styles[run.style].size).fold(0.0, f32::max);
+
+                baseline += match previous_size {
+                    Some(previous) => line_height * f32::max(previous, size),
+                    None => ascent,
+                };
+                previous_size = Some(size);
+                lines.push(TextLine { layout: layout_runs(&text[range], &line_runs, letter_spacing), baseline, descent });
+            }
+
+            let width = lines.iter().map(|line| line.layout.width).fold(0.0, f32::max);
+            let height = lines.last().map_or(0.0, |line| line.baseline - line.descent);
+            (lines, width, height)
         };
 
-        // Vertical alignment: top/middle/bottom relative to anchor
+        let mut font_size = stamp.font_size * avg_scale;
+        if auto_size {
+            font_size = fit_font_size(
+                |size| {
+                    let (_, w, h) = layout(size);
+                    (w, h)
+                },
+                font_size,
+                box_width,
+                box_height,
+                stamp.auto_size_grow.unwrap_or(false),
+            );
+        }
+        let (lines, _, block_height) = layout(font_size);
+
+        // Vertical alignment: top/middle/bottom of the block relative to anchor
         let vertical_align = stamp.vertical_align.as_deref().unwrap_or("top");
-        let text_y = match vertical_align {
-            "middle" => anchor_y - (text_height as i32 / 2),
-            "bottom" => anchor_y - text_height as i32,
+        let block_y = match vertical_align {
+            "middle" => anchor_y - block_height / 2.0,
+            "bottom" => anchor_y - block_height,
             _ => anchor_y, // top - anchor is at top edge of text
         };
 
-        // Manual text rendering - draw each glyph
-        let (img_w, img_h) = img.dimensions();
-
-        let scaled_font = font.as_scaled(scale);
-        let mut cursor_x = text_x as f32;
-
-        for ch in text.chars() {
-            let glyph_id = font.glyph_id(ch);
-            let glyph = glyph_id.with_scale(scale);
-
-            if let Some(outlined) = font.outline_glyph(glyph) {
-                let bounds = outlined.px_bounds();
-
-                outlined.draw(|gx, gy, coverage| {
-                    if coverage > 0.0 {
-                        let px = (cursor_x + gx as f32 + bounds.min.x) as i32;
-                        let py = (text_y as f32 + gy as f32 + bounds.min.y) as i32;
-
-                        if px >= 0 && py >= 0 && (px as u32) < img_w && (py as u32) < img_h {
-                            let bg = img.get_pixel(px as u32, py as u32);
-                            let alpha = coverage * (color[3] as f32 / 255.0);
-                            let inv_alpha = 1.0 - alpha;
+        // Horizontal alignment: left/center/right of each line relative to anchor
+        let line_xs: Vec<f32> = lines
+            .iter()
+            .map(|line| match stamp.alignment.as_str() {
+                "center" => anchor_x - line.layout.width / 2.0,
+                "right" => anchor_x - line.layout.width,
+                _ => anchor_x, // left - anchor is at left edge of text
+            })
+            .collect();
+        let left = line_xs.iter().copied().fold(f32::INFINITY, f32::min);
+        let right = lines.iter().zip(&line_xs).map(|(line, x)| x + line.layout.width).fold(f32::NEG_INFINITY, f32::max);
+
+        let decoration_color = |value: &Option<String>| value.as_deref().map(parse_color).transpose();
+        let stroke_color = decoration_color(&stamp.stroke_color)?;
+        let shadow_color = decoration_color(&stamp.shadow_color)?;
+        let background_color = decoration_color(&stamp.background_color)?;
+        let stroke_width = stamp.stroke_width.unwrap_or(0.0).max(0.0) * avg_scale;
+        let shadow_offset = (
+            (stamp.shadow_offset_x.unwrap_or(0.0) * avg_scale).round() as i32,
+            (stamp.shadow_offset_y.unwrap_or(0.0) * avg_scale).round() as i32,
+        );
+        let shadow_blur = stamp.shadow_blur.unwrap_or(0.0).max(0.0) * avg_scale;
 
-                            let blended = Rgba([
-                                (color[0] as f32 * alpha + bg[0] as f32 * inv_alpha) as u8,
-                                (color[1] as f32 * alpha + bg[1] as f32 * inv_alpha) as u8,
-                                (color[2] as f32 * alpha + bg[2] as f32 * inv_alpha) as u8,
-                                255,
-                            ]);
+        if let Some(background) = background_color {
+            let padding = stamp.background_padding.unwrap_or(0.0) * avg_scale;
+            canvas.fill_rect(left - padding, block_y - padding, right - left + 2.0 * padding, block_height + 2.0 * padding, background);
+        }
 
-                            img.put_pixel(px as u32, py as u32, blended);
+        // Vector drawings keep the text as glyphs of the embedded fonts, unless
+        // a font is set to a variable instance its file doesn't hold
+        let vector = matches!(canvas, Canvas::Vector(_))
+            && styled.iter().all(|(_, style)| styles[*style].fonts.iter().all(TextFont::is_default_instance));
+
+        if !vector || shadow_color.is_some() {
+            // Glyph coverage is collected in a mask around the text block, with room
+            // for glyph overshoot, the outline and the shadow
+            let margin = (font_size + stroke_width + 1.5 * shadow_blur).ceil() as i32
+                + shadow_offset.0.abs().max(shadow_offset.1.abs());
+            let mask_x = left.floor() as i32 - margin;
+            let mask_y = block_y.floor() as i32 - margin;
+            let mut mask = Mask::new(
+                mask_x,
+                mask_y,
+                (right.ceil() as i32 + margin - mask_x).max(0) as usize,
+                ((block_y + block_height).ceil() as i32 + margin - mask_y).max(0) as usize,
+            );
+            // One fill mask per colour; the outline and shadow follow all the text
+            let mut fills: Vec<(Rgba<u8>, Mask)> = Vec::new();
+            for (line, x) in lines.iter().zip(&line_xs) {
+                for glyph in &line.layout.glyphs {
+                    let style = &styles[glyph.style];
+                    let index = match fills.iter().position(|(color, _)| *color == style.color) {
+                        Some(index) => index,
+                        None => {
+                            fills.push((style.color, mask.clone()));
+                            fills.len() - 1
                         }
-                    }
-                });
+                    };
+                    fills[index].1.add_glyph(&style.fonts[glyph.font], glyph, *x, block_y + line.baseline);
+                }
+            }
+            for (_, fill) in &fills {
+                mask.add(fill);
             }
 
-            cursor_x += scaled_font.h_advance(glyph_id);
+            // Shadow, outline and fill are composited bottom to top
+            let outline = match stroke_color {
+                Some(_) if stroke_width > 0.0 => Some(mask.dilate(stroke_width)),
+                _ => None,
+            };
+            let shape = outline.as_ref().unwrap_or(&mask);
+            if let Some(shadow) = shadow_color {
+                // A canvas shadowBlur is twice the Gaussian standard deviation
+                canvas.fill_mask(&shape.blur(shadow_blur / 2.0), shadow_offset.0, shadow_offset.1, shadow);
+            }
+            if !vector {
+                if let (Some(outline), Some(stroke)) = (&outline, stroke_color) {
+                    canvas.fill_mask(outline, 0, 0, stroke);
+                }
+                for (color, fill) in &fills {
+                    canvas.fill_mask(fill, 0, 0, *color);
+                }
+                return Ok(());
+            }
+        }
+
+        if let Canvas::Vector(drawing) = canvas {
+            // Glyphs in their own colours, or all in `paint`
+            let mut push = |paint: Option<Paint>| {
+                for (line, x) in lines.iter().zip(&line_xs) {
+                    drawing.push_line(&line.layout, *x, block_y + line.baseline, |glyph| {
+                        let style = &styles[glyph.style];
+                        (&style.fonts[glyph.font], paint.unwrap_or(Paint::Fill(style.color)))
+                    });
+                }
+            };
+            // Outlines go under all of the text, as on images
+            if let Some(stroke) = stroke_color.filter(|_| stroke_width > 0.0) {
+                push(Some(Paint::Stroke(stroke, stroke_width)));
+            }
+            push(None);
         }
 
         Ok(())
     }
 
-    fn render_barcode_stamp(
-        &self,
-        img: &mut RgbaImage,
+    #[allow(clippy::too_many_arguments)]
+    fn render_barcode_stamp<'f>(
+        &'f self,
+        canvas: &mut Canvas<'_, 'f>,
         stamp: &BarcodeStamp,
-        record: &HashMap<String, String>,
+        text: &str,
+        hri_font: Option<&'f FontChoice>,
+        record_index: usize,
         scale_x: f32,
         scale_y: f32,
     ) -> Result<(), String> {
-        let text = resolve_template(&stamp.template, record);
-
         if text.is_empty() {
             return Ok(());
         }
@@ -250,54 +773,315 @@ impl TicketRenderer {
         let w = (stamp.width * scale_x) as u32;
         let h = (stamp.height * scale_y) as u32;
 
-        // Generate barcode using the barcode crate
-        let barcode_img = generate_barcode(&text, &stamp.format, w, h)?;
+        let symbol = barcode::encode(text, &stamp.format)
+            .map_err(|e| format!("Barcode stamp '{}': {}", stamp.id, e))?;
+
+        let hri_font = hri_font
+            .map(FontChoice::load)
+            .transpose()
+            .map_err(|e| format!("Barcode stamp '{}': {}", stamp.id, e))?;
+        let hri = hri_font.map(|font| {
+            let font_size = stamp.hri_font_size.unwrap_or(stamp.height * 0.2);
+            HriStyle {
+                font,
+                scale: PxScale::from(font_size * (scale_x + scale_y) / 2.0),
+                above: stamp.hri_position.as_deref() == Some("above"),
+            }
+        });
+
+        // Whole device pixels per module, leaving the quiet zones inside the box.
+        // Below one pixel per module the bars would spill out of the box.
+        let span = symbol.modules.len() as u32 + symbol.quiet_left + symbol.quiet_right;
+        if w < span {
+            self.diagnostics.borrow_mut().push(Diagnostic {
+                code: "barcode_too_narrow",
+                stamp_id: stamp.id.clone(),
+                record_index: Some(record_index),
+                message: format!(
+                    "{} needs {} modules with its quiet zones, more than the {}px wide box holds; the stamp needs to be at least {:.0}px wide and was not drawn",
+                    stamp.format,
+                    span,
+                    w,
+                    (span as f32 / scale_x).ceil(),
+                ),
+            });
+            return Ok(());
+        }
+        let module_width = w / span;
+
+        let mm_per_px = 25.4 / self.dpi;
+        let min_module_px = ((symbol.min_x_mm / mm_per_px).ceil() as u32).max(1);
+        if w < min_module_px * span {
+            self.diagnostics.borrow_mut().push(Diagnostic {
+                code: "barcode_x_dimension",
+                stamp_id: stamp.id.clone(),
+                record_index: None,
+                message: format!(
+                    "Box fits a {:.3}mm X-dimension at {} DPI, below the {:.3}mm minimum for {}; the stamp needs to be at least {:.0}px wide",
+                    module_width as f64 * mm_per_px,
+                    self.dpi,
+                    symbol.min_x_mm,
+                    stamp.format,
+                    (min_module_px * span) as f32 / scale_x,
+                ),
+            });
+        }
 
-        // Composite barcode onto ticket
-        composite_image(img, &barcode_img, x, y);
+        draw_barcode(canvas, &symbol, hri.as_ref(), module_width, x, y, w, h);
 
         Ok(())
     }
 
-    fn render_qr_stamp(
-        &self,
-        img: &mut RgbaImage,
+    #[allow(clippy::too_many_arguments)]
+    fn render_qr_stamp<'f>(
+        &'f self,
+        canvas: &mut Canvas<'_, 'f>,
         stamp: &QrCodeStamp,
-        record: &HashMap<String, String>,
+        text: &str,
+        record_index: usize,
+        backdrop: Rgba<u8>,
         scale_x: f32,
         scale_y: f32,
     ) -> Result<(), String> {
-        let text = resolve_template(&stamp.template, record);
-
         if text.is_empty() {
             return Ok(());
         }
 
         let x = (stamp.x * scale_x) as u32;
         let y = (stamp.y * scale_y) as u32;
-        // Use minimum scale to maintain aspect ratio for QR codes
-        let size = (stamp.width * scale_x.min(scale_y)) as u32;
+        let width = (stamp.width * scale_x) as u32;
+        let height = (stamp.height * scale_y) as u32;
+
+        let logo_ratio = stamp.logo.as_ref().map(|_| stamp.logo_ratio.unwrap_or(0.2));
+        let error_correction = match logo_ratio {
+            // Covering modules needs at least Q-level redundancy
+            Some(ratio) if matches!(stamp.error_correction.as_str(), "L" | "M") => {
+                if ratio * ratio <= max_logo_area("Q") { "Q" } else { "H" }
+            }
+            _ => stamp.error_correction.as_str(),
+        };
 
-        // Generate QR code
-        let qr_img = generate_qr_code(&text, &stamp.error_correction, size)?;
+        let mut matrix = encode_qr_code(text, error_correction)?;
 
-        // Composite QR code onto ticket
-        composite_image(img, &qr_img, x, y);
+        // Logo square in modules, with the same parity as the symbol so it stays centred
+        let logo_modules = match logo_ratio {
+            Some(ratio) => {
+                let mut n = ((ratio * matrix.width as f32).round() as usize).clamp(1, matrix.width);
+                if (matrix.width - n) % 2 == 1 {
+                    n -= 1;
+                }
+                let area = (n * n) as f32 / (matrix.width * matrix.width) as f32;
+                if area > max_logo_area(error_correction) {
+                    return Err(format!(
+                        "QR stamp '{}': logo ratio {} covers {:.0}% of the symbol, more than error correction level {} can recover",
+                        stamp.id,
+                        ratio,
+                        area * 100.0,
+                        error_correction,
+                    ));
+                }
+                let start = (matrix.width - n) / 2;
+                for my in start..start + n {
+                    for mx in start..start + n {
+                        matrix.set(mx, my, false);
+                    }
+                }
+                n
+            }
+            None => 0,
+        };
+
+        // Whole-pixel modules: the requested size if the symbol and its quiet
+        // zone fit the box, otherwise the largest size that does
+        let quiet_zone = stamp.quiet_zone.unwrap_or(4) as usize;
+        let Some(fit) = self.matrix_module_size("QR", &stamp.id, &matrix, quiet_zone, width, height, record_index, scale_x, scale_y) else {
+            return Ok(());
+        };
+        let module_size = match stamp.module_size {
+            Some(size) if size > 0.0 => {
+                let requested = ((size * scale_x.min(scale_y)).round() as u32).max(1);
+                if requested > fit {
+                    // Reported once per stamp, for the first ticket that hits it
+                    self.diagnostics.borrow_mut().push(Diagnostic {
+                        code: "qr_module_size",
+                        stamp_id: stamp.id.clone(),
+                        record_index: None,
+                        message: format!(
+                            "{}x{} QR code with a {}-module quiet zone does not fit the box at module size {} ({} device pixels); using {} device pixels per module",
+                            matrix.width,
+                            matrix.width,
+                            quiet_zone,
+                            size,
+                            requested,
+                            fit,
+                        ),
+                    });
+                }
+                requested.min(fit)
+            }
+            _ => fit,
+        };
+
+        let mut dark = match &stamp.foreground_color {
+            Some(color) => parse_color(color)?,
+            None => BLACK,
+        };
+        let mut light = match &stamp.background_color {
+            Some(color) => parse_color(color)?,
+            None => WHITE,
+        };
+        if stamp.inverted.unwrap_or(false) {
+            std::mem::swap(&mut dark, &mut light);
+        }
+
+        // Judge contrast against what will actually be printed, with translucent
+        // colours blended over the ticket artwork under the stamp
+        let printed_light = blend_over(light, backdrop);
+        let printed_dark = blend_over(dark, printed_light);
+        let ratio = contrast_ratio(printed_dark, printed_light);
+        if ratio < MIN_QR_CONTRAST {
+            self.diagnostics.borrow_mut().push(Diagnostic {
+                code: "qr_contrast",
+                stamp_id: stamp.id.clone(),
+                record_index: None,
+                message: format!(
+                    "QR code colours have a contrast ratio of {:.1}:1, below the {:.0}:1 minimum for reliable scanning",
+                    ratio, MIN_QR_CONTRAST,
+                ),
+            });
+        }
+
+        draw_matrix_code(canvas, &matrix, module_size, x, y, width, height, dark, light);
+
+        if let Some(logo) = &stamp.logo {
+            let logo_img: RgbaImage = ImageBuffer::from_raw(logo.width, logo.height, logo.data.clone())
+                .ok_or_else(|| format!("QR stamp '{}': logo data does not match its size", stamp.id))?;
+
+            // Fit the logo inside the cleared square, keeping half a module of margin
+            let space = (logo_modules as u32 * module_size).saturating_sub(module_size).max(1);
+            let scale = (space as f32 / logo.width as f32).min(space as f32 / logo.height as f32);
+            let logo_w = ((logo.width as f32 * scale) as u32).max(1);
+            let logo_h = ((logo.height as f32 * scale) as u32).max(1);
+            let resized = image::imageops::resize(&logo_img, logo_w, logo_h, image::imageops::FilterType::Lanczos3);
+            canvas.draw_image(Cow::Owned(resized), x + width.saturating_sub(logo_w) / 2, y + height.saturating_sub(logo_h) / 2);
+        }
 
         Ok(())
     }
-}
 
-// Helper function to resolve template strings like "{{number}}" with record data
-fn resolve_template(template: &str, record: &HashMap<String, String>) -> String {
-    let mut result = template.to_string();
+    fn render_datamatrix_stamp<'f>(
+        &'f self,
+        canvas: &mut Canvas<'_, 'f>,
+        stamp: &DataMatrixStamp,
+        text: &str,
+        record_index: usize,
+        scale_x: f32,
+        scale_y: f32,
+    ) -> Result<(), String> {
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let rectangular = match stamp.shape.as_deref() {
+            None | Some("square") => false,
+            Some("rectangle") => true,
+            Some(other) => return Err(format!("DataMatrix stamp '{}': unknown shape '{}'", stamp.id, other)),
+        };
+        let matrix = datamatrix::encode(text, rectangular)
+            .map_err(|e| format!("DataMatrix stamp '{}': {}", stamp.id, e))?;
+
+        let x = (stamp.x * scale_x) as u32;
+        let y = (stamp.y * scale_y) as u32;
+        let width = (stamp.width * scale_x) as u32;
+        let height = (stamp.height * scale_y) as u32;
+
+        // ECC200 requires a one-module quiet zone
+        let Some(module_size) = self.matrix_module_size("DataMatrix", &stamp.id, &matrix, 1, width, height, record_index, scale_x, scale_y) else {
+            return Ok(());
+        };
+        draw_matrix_code(canvas, &matrix, module_size, x, y, width, height, BLACK, WHITE);
 
-    for (key, value) in record {
-        let placeholder = format!("{{{{{}}}}}", key);
-        result = result.replace(&placeholder, value);
+        Ok(())
     }
 
-    result
+    fn render_aztec_stamp<'f>(
+        &'f self,
+        canvas: &mut Canvas<'_, 'f>,
+        stamp: &AztecStamp,
+        text: &str,
+        record_index: usize,
+        scale_x: f32,
+        scale_y: f32,
+    ) -> Result<(), String> {
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let mode = match stamp.mode.as_deref() {
+            None | Some("auto") => AztecMode::Auto,
+            Some("compact") => AztecMode::Compact,
+            Some("full") => AztecMode::Full,
+            Some(other) => return Err(format!("Aztec stamp '{}': unknown mode '{}'", stamp.id, other)),
+        };
+        let matrix = aztec::encode(text, mode)
+            .map_err(|e| format!("Aztec stamp '{}': {}", stamp.id, e))?;
+
+        let x = (stamp.x * scale_x) as u32;
+        let y = (stamp.y * scale_y) as u32;
+        let width = (stamp.width * scale_x) as u32;
+        let height = (stamp.height * scale_y) as u32;
+
+        // Aztec needs no quiet zone thanks to its central finder
+        let Some(module_size) = self.matrix_module_size("Aztec", &stamp.id, &matrix, 0, width, height, record_index, scale_x, scale_y) else {
+            return Ok(());
+        };
+        draw_matrix_code(canvas, &matrix, module_size, x, y, width, height, BLACK, WHITE);
+
+        Ok(())
+    }
+
+    /// Largest whole-pixel module size at which a 2D symbol and its quiet zone
+    /// fit the `width` x `height` box. `None` when not even one pixel per
+    /// module does, as a clipped symbol wouldn't scan; the stamp is reported
+    /// and not drawn.
+    #[allow(clippy::too_many_arguments)]
+    fn matrix_module_size(
+        &self,
+        kind: &str,
+        stamp_id: &str,
+        matrix: &BitMatrix,
+        quiet_zone: usize,
+        width: u32,
+        height: u32,
+        record_index: usize,
+        scale_x: f32,
+        scale_y: f32,
+    ) -> Option<u32> {
+        let module_size = fit_module_size(matrix, quiet_zone, width, height);
+        if module_size == 0 {
+            let span_x = matrix.width + 2 * quiet_zone;
+            let span_y = matrix.height + 2 * quiet_zone;
+            self.diagnostics.borrow_mut().push(Diagnostic {
+                code: "matrix_code_too_small",
+                stamp_id: stamp_id.to_string(),
+                record_index: Some(record_index),
+                message: format!(
+                    "{}x{} {} symbol needs {}x{} modules with its quiet zone, more than the {}x{}px box holds; the stamp needs to be at least {:.0}x{:.0}px and was not drawn",
+                    matrix.width,
+                    matrix.height,
+                    kind,
+                    span_x,
+                    span_y,
+                    width,
+                    height,
+                    (span_x as f32 / scale_x).ceil(),
+                    (span_y as f32 / scale_y).ceil(),
+                ),
+            });
+            return None;
+        }
+        Some(module_size)
+    }
 }
 
 // Parse CSS color string to RGBA
@@ -314,26 +1098,182 @@ fn parse_color(color: &str) -> Result<Rgba<u8>, String> {
     ]))
 }
 
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// Contrast ratio below which QR codes are unlikely to scan reliably
+const MIN_QR_CONTRAST: f64 = 3.0;
+
+/// Largest share of a QR symbol a logo may cover at an error correction
+/// level: half of what the level recovers, since cleared modules also break
+/// codewords that are only partly covered
+fn max_logo_area(error_correction: &str) -> f32 {
+    match error_correction {
+        "L" => 0.035,
+        "Q" => 0.125,
+        "H" => 0.15,
+        _ => 0.075,
+    }
+}
+
+/// Mean colour of the pixels in a box, clipped to the image
+pub fn average_color(img: &RgbaImage, x: u32, y: u32, width: u32, height: u32) -> Rgba<u8> {
+    let mut sum = [0u64; 3];
+    let mut count = 0u64;
+    for py in y..(y + height).min(img.height()) {
+        for px in x..(x + width).min(img.width()) {
+            let p = img.get_pixel(px, py);
+            for c in 0..3 {
+                sum[c] += p[c] as u64;
+            }
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return WHITE;
+    }
+    Rgba([(sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8, 255])
+}
 
+/// Opaque result of drawing `color` over `backdrop`
+fn blend_over(color: Rgba<u8>, backdrop: Rgba<u8>) -> Rgba<u8> {
+    let alpha = color[3] as f32 / 255.0;
+    let mix = |c: usize| (color[c] as f32 * alpha + backdrop[c] as f32 * (1.0 - alpha)) as u8;
+    Rgba([mix(0), mix(1), mix(2), 255])
+}
+
+/// WCAG contrast ratio between two opaque colours, from 1 to 21
+fn contrast_ratio(a: Rgba<u8>, b: Rgba<u8>) -> f64 {
+    let luminance = |p: Rgba<u8>| {
+        let channel = |v: u8| {
+            let v = v as f64 / 255.0;
+            if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+        };
+        0.2126 * channel(p[0]) + 0.7152 * channel(p[1]) + 0.0722 * channel(p[2])
+    };
+    let (la, lb) = (luminance(a), luminance(b));
+    (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+}
 
 // Alpha blend a pixel
-fn blend_pixel(img: &mut RgbaImage, x: u32, y: u32, color: Rgba<u8>) {
+pub fn blend_pixel(img: &mut RgbaImage, x: u32, y: u32, color: Rgba<u8>) {
     let bg = img.get_pixel(x, y);
     let alpha = color[3] as f32 / 255.0;
-    let inv_alpha = 1.0 - alpha;
+    let bg_alpha = bg[3] as f32 / 255.0 * (1.0 - alpha);
+    let out_alpha = alpha + bg_alpha;
+    if out_alpha <= 0.0 {
+        return;
+    }
 
     let blended = Rgba([
-        (color[0] as f32 * alpha + bg[0] as f32 * inv_alpha) as u8,
-        (color[1] as f32 * alpha + bg[1] as f32 * inv_alpha) as u8,
-        (color[2] as f32 * alpha + bg[2] as f32 * inv_alpha) as u8,
-        255,
+        ((color[0] as f32 * alpha + bg[0] as f32 * bg_alpha) / out_alpha) as u8,
+        ((color[1] as f32 * alpha + bg[1] as f32 * bg_alpha) / out_alpha) as u8,
+        ((color[2] as f32 * alpha + bg[2] as f32 * bg_alpha) / out_alpha) as u8,
+        (out_alpha * 255.0).round() as u8,
     ]);
 
     img.put_pixel(x, y, blended);
 }
 
+/// Blend `color` over the pixels whose centres fall inside a rectangle
+pub fn fill_rect(img: &mut RgbaImage, left: f32, top: f32, right: f32, bottom: f32, color: Rgba<u8>) {
+    let (w, h) = img.dimensions();
+    let x0 = left.round().clamp(0.0, w as f32) as u32;
+    let y0 = top.round().clamp(0.0, h as f32) as u32;
+    let x1 = right.round().clamp(0.0, w as f32) as u32;
+    let y1 = bottom.round().clamp(0.0, h as f32) as u32;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            blend_pixel(img, x, y, color);
+        }
+    }
+}
+
+/// Axis-aligned box, clipped at zero, covering a `width` x `height` box with
+/// its top-left corner at `pivot` after rotating it around that corner
+fn rotated_bounds(pivot: (f32, f32), width: f32, height: f32, degrees: f32) -> (u32, u32, u32, u32) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let corners = [(0.0, 0.0), (width, 0.0), (0.0, height), (width, height)]
+        .map(|(dx, dy)| (pivot.0 + dx * cos - dy * sin, pivot.1 + dx * sin + dy * cos));
+    let min_x = corners.iter().map(|c| c.0).fold(f32::INFINITY, f32::min).max(0.0);
+    let min_y = corners.iter().map(|c| c.1).fold(f32::INFINITY, f32::min).max(0.0);
+    let max_x = corners.iter().map(|c| c.0).fold(0.0, f32::max);
+    let max_y = corners.iter().map(|c| c.1).fold(0.0, f32::max);
+    (min_x as u32, min_y as u32, (max_x - min_x) as u32, (max_y - min_y) as u32)
+}
+
+/// Composite `layer` (the size of `base`) rotated clockwise by `degrees`
+/// around `pivot`. Quarter turns move whole pixels so barcode modules stay
+/// crisp; other angles are resampled bilinearly.
+fn composite_rotated(base: &mut RgbaImage, layer: &RgbaImage, pivot: (f32, f32), degrees: f32) {
+    let (w, h) = base.dimensions();
+
+    if degrees % 90.0 == 0.0 {
+        let (px, py) = (pivot.0.round() as i64, pivot.1.round() as i64);
+        for (lx, ly, pixel) in layer.enumerate_pixels() {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let (dx, dy) = (lx as i64 - px, ly as i64 - py);
+            // Pixel squares map onto pixel squares
+            let (rx, ry) = match degrees as u32 {
+                90 => (-dy - 1, dx),
+                180 => (-dx - 1, -dy - 1),
+                270 => (dy, -dx - 1),
+                _ => (dx, dy),
+            };
+            let (x, y) = (px + rx, py + ry);
+            if x >= 0 && y >= 0 && x < w as i64 && y < h as i64 {
+                blend_pixel(base, x as u32, y as u32, *pixel);
+            }
+        }
+        return;
+    }
+
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    for y in 0..h {
+        for x in 0..w {
+            // Inverse-rotate the destination pixel centre into the layer
+            let (dx, dy) = (x as f32 + 0.5 - pivot.0, y as f32 + 0.5 - pivot.1);
+            let sx = pivot.0 + dx * cos + dy * sin - 0.5;
+            let sy = pivot.1 - dx * sin + dy * cos - 0.5;
+            if let Some(color) = sample_bilinear(layer, sx, sy) {
+                blend_pixel(base, x, y, color);
+            }
+        }
+    }
+}
+
+/// Bilinear sample with premultiplied alpha; `None` where fully transparent
+fn sample_bilinear(img: &RgbaImage, x: f32, y: f32) -> Option<Rgba<u8>> {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let mut sum = [0.0f32; 4];
+    for (ox, oy, weight) in [(0, 0, (1.0 - fx) * (1.0 - fy)), (1, 0, fx * (1.0 - fy)), (0, 1, (1.0 - fx) * fy), (1, 1, fx * fy)] {
+        let (px, py) = (x0 as i64 + ox, y0 as i64 + oy);
+        if px < 0 || py < 0 || px >= img.width() as i64 || py >= img.height() as i64 {
+            continue;
+        }
+        let p = img.get_pixel(px as u32, py as u32);
+        let a = p[3] as f32 / 255.0 * weight;
+        for c in 0..3 {
+            sum[c] += p[c] as f32 * a;
+        }
+        sum[3] += a;
+    }
+    if sum[3] <= 0.0 {
+        return None;
+    }
+    Some(Rgba([
+        (sum[0] / sum[3]) as u8,
+        (sum[1] / sum[3]) as u8,
+        (sum[2] / sum[3]) as u8,
+        (sum[3] * 255.0).round() as u8,
+    ]))
+}
+
 // Composite one image onto another with alpha blending
-fn composite_image(base: &mut RgbaImage, overlay: &RgbaImage, x: u32, y: u32) {
+pub fn composite_image(base: &mut RgbaImage, overlay: &RgbaImage, x: u32, y: u32) {
     let (base_w, base_h) = base.dimensions();
     let (overlay_w, overlay_h) = overlay.dimensions();
 
@@ -352,34 +1292,215 @@ fn composite_image(base: &mut RgbaImage, overlay: &RgbaImage, x: u32, y: u32) {
     }
 }
 
-// Generate barcode image
-// Note: For now, this generates a simple bar pattern placeholder.
-// Full barcode generation will be added in a future iteration.
-// The TypeScript barcode service will continue to work for actual barcodes.
-fn generate_barcode(_text: &str, _format: &str, width: u32, height: u32) -> Result<RgbaImage, String> {
-    let mut img = RgbaImage::new(width, height);
+/// Fonts, relative size and colour for text in `format` within `stamp`
+fn text_style(fonts: &FontSet, stamp: &TextStamp, format: &SpanFormat) -> Result<StyleChoice, String> {
+    let weight = format.weight.or(stamp.font_weight).unwrap_or(NORMAL_WEIGHT);
+    let style = match format.style {
+        Some(style) => style,
+        None => FontStyle::parse(stamp.font_style.as_deref())?,
+    };
 
-    // Fill with white background
-    for pixel in img.pixels_mut() {
-        *pixel = Rgba([255, 255, 255, 255]);
+    let mut faces = vec![font(fonts, &stamp.font_family, weight, style)?];
+    for family in stamp.fallback_fonts.iter().flatten() {
+        faces.push(font(fonts, family, weight, style)?);
+    }
+    for (tag, value) in stamp.font_variations.iter().flatten() {
+        let tag: [u8; 4] = tag.as_bytes().try_into()
+            .map_err(|_| format!("font variation tag '{}' is not four characters", tag))?;
+        for font in &mut faces {
+            font.variations.push((tag, *value));
+        }
     }
 
-    // Draw simple black bars as placeholder to indicate barcode area
-    let bar_width = (width / 20).max(1);
-    for i in 0..10 {
-        let x = i * bar_width * 2;
-        for bx in x..(x + bar_width).min(width) {
-            for by in 0..height {
-                img.put_pixel(bx, by, Rgba([0, 0, 0, 255]));
-            }
+    let color = match &format.color {
+        Some(color) => parse_color(color)?,
+        None => parse_color(&stamp.color).unwrap_or(Rgba([0, 0, 0, 255])),
+    };
+    let size = match format.size {
+        Some(SpanSize::Pixels(px)) => px / stamp.font_size,
+        Some(SpanSize::Percent(percent)) => percent / 100.0,
+        None => 1.0,
+    };
+
+    Ok(StyleChoice { fonts: faces, size, color })
+}
+
+/// Look up the loaded face of `family` closest to `weight` and `style`.
+/// Variable faces are set to the weight, and slanted when no italic face exists.
+fn font(fonts: &FontSet, family: &str, weight: f32, style: FontStyle) -> Result<FontChoice, String> {
+    let face = fonts.select(family, weight, style)
+        .ok_or_else(|| format!("Font '{}' not found in loaded fonts", family))?;
+
+    let font = TextFont::new(&face.data)
+        .map_err(|e| format!("Failed to load font '{}': {}", family, e))?;
+    let mut variations = vec![(*b"wght", weight)];
+    if face.style == FontStyle::Normal {
+        match style {
+            FontStyle::Italic if font.has_axis(b"ital") => variations.push((*b"ital", 1.0)),
+            FontStyle::Italic | FontStyle::Oblique => variations.push((*b"slnt", -14.0)),
+            FontStyle::Normal => {}
+        }
+    }
+    Ok(FontChoice { data: face.data.clone(), variations })
+}
+
+/// Font of the human-readable line of a barcode stamp that shows one
+fn hri_font(fonts: &FontSet, stamp: &BarcodeStamp) -> Result<Option<FontChoice>, String> {
+    if !matches!(stamp.hri_position.as_deref(), Some("above" | "below")) {
+        return Ok(None);
+    }
+    // Without an explicit family, fall back to the first loaded font
+    let family = match &stamp.hri_font_family {
+        Some(family) => family.as_str(),
+        None => fonts.first_family().ok_or("no font loaded for HRI text")?,
+    };
+    font(fonts, family, NORMAL_WEIGHT, FontStyle::Normal).map(Some)
+}
+
+/// Font and placement of the human-readable line of a barcode
+struct HriStyle<'a> {
+    font: TextFont<'a>,
+    scale: PxScale,
+    above: bool,
+}
+
+// Draw a barcode in the `width` x `height` box at (x, y), centering the symbol and
+// its quiet zones. EAN/UPC digits outside the guard bars are drawn in the quiet zones.
+#[allow(clippy::too_many_arguments)]
+fn draw_barcode<'f>(
+    canvas: &mut Canvas<'_, 'f>,
+    symbol: &barcode::Symbol,
+    hri: Option<&HriStyle<'f>>,
+    module_width: u32,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) {
+    canvas.fill_rect(x as f32, y as f32, width as f32, height as f32, WHITE);
+
+    let span = symbol.modules.len() as u32 + symbol.quiet_left + symbol.quiet_right;
+    let origin_x = width.saturating_sub(module_width * span) / 2 + symbol.quiet_left * module_width;
+
+    // Vertical split between bars and the text line
+    let text_height = hri.map(|h| (h.font.ascent(h.scale) - h.font.descent(h.scale)).ceil() as u32).unwrap_or(0);
+    let gap = if hri.is_some() { module_width } else { 0 };
+    let bars_height = height.saturating_sub(text_height + gap);
+    let (bars_top, text_top) = match hri {
+        Some(h) if h.above => (text_height + gap, 0),
+        _ => (0, bars_height + gap),
+    };
+
+    // Guard bars reach halfway into a text line below the symbol
+    let guard_bottom = match hri {
+        Some(h) if !h.above => (bars_top + bars_height + gap + text_height / 2).min(height),
+        _ => bars_top + bars_height,
+    };
+    let bottom = |i: usize| {
+        if symbol.guards.iter().any(|g| g.contains(&i)) {
+            guard_bottom
+        } else {
+            bars_top + bars_height
+        }
+    };
+
+    // Adjacent bar modules of the same height are drawn as one bar
+    let mut i = 0;
+    while i < symbol.modules.len() {
+        if !symbol.modules[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < symbol.modules.len() && symbol.modules[i] && bottom(i) == bottom(start) {
+            i += 1;
+        }
+        let x0 = origin_x + start as u32 * module_width;
+        let x1 = (origin_x + i as u32 * module_width).min(width);
+        if x1 > x0 {
+            canvas.fill_rect((x + x0) as f32, (y + bars_top) as f32, (x1 - x0) as f32, (bottom(start) - bars_top) as f32, BLACK);
         }
     }
 
-    Ok(img)
+    if let Some(hri) = hri {
+        let baseline = (y + text_top) as f32 + hri.font.ascent(hri.scale);
+        for segment in &symbol.hri {
+            let fonts = std::slice::from_ref(&hri.font);
+            let line = layout_line(fonts, hri.scale, &segment.text, 0.0);
+            let center = origin_x as f32 + (segment.start + segment.end) as f32 / 2.0 * module_width as f32;
+            canvas.draw_glyphs(fonts, &line, x as f32 + center - line.width / 2.0, baseline, BLACK);
+        }
+    }
 }
 
-// Generate QR code image
-fn generate_qr_code(text: &str, error_correction: &str, size: u32) -> Result<RgbaImage, String> {
+/// Largest font size up to `font_size` (or beyond it when `grow` is set) at
+/// which text whose extents are given by `measure` fits a `box_width` x
+/// `box_height` box
+fn fit_font_size(measure: impl Fn(f32) -> (f32, f32), font_size: f32, box_width: f32, box_height: f32, grow: bool) -> f32 {
+    if box_width <= 0.0 || box_height <= 0.0 {
+        return font_size;
+    }
+
+    // Text extents scale roughly linearly with the font size
+    let (w, h) = measure(font_size);
+    if w <= 0.0 || h <= 0.0 {
+        return font_size;
+    }
+    let ratio = (box_width / w).min(box_height / h);
+    let mut size = if grow { font_size * ratio } else { font_size * ratio.min(1.0) };
+
+    while size > 1.0 {
+        let (w, h) = measure(size);
+        if w <= box_width && h <= box_height {
+            break;
+        }
+        size *= 0.95;
+    }
+    size.max(1.0)
+}
+
+/// Largest whole-pixel module size at which `matrix` and its quiet zone fit
+/// the box, or 0 when they don't fit at one pixel per module
+fn fit_module_size(matrix: &BitMatrix, quiet_zone: usize, width: u32, height: u32) -> u32 {
+    let span_x = (matrix.width + 2 * quiet_zone) as u32;
+    let span_y = (matrix.height + 2 * quiet_zone) as u32;
+    (width / span_x).min(height / span_y)
+}
+
+/// Draw a 2D symbol with `module_size`-pixel modules centred in the `width` x
+/// `height` box at (x, y), filled with the light colour. Modules outside the box are clipped.
+#[allow(clippy::too_many_arguments)]
+fn draw_matrix_code(canvas: &mut Canvas, matrix: &BitMatrix, module_size: u32, x: u32, y: u32, width: u32, height: u32, dark: Rgba<u8>, light: Rgba<u8>) {
+    canvas.fill_rect(x as f32, y as f32, width as f32, height as f32, light);
+
+    let offset_x = (width as i64 - (matrix.width as u32 * module_size) as i64) / 2;
+    let offset_y = (height as i64 - (matrix.height as u32 * module_size) as i64) / 2;
+    let clip = |start: i64, length: i64, size: u32| (start.max(0), (start + length).min(size as i64));
+
+    // Runs of dark modules along a row are drawn as one rectangle
+    for my in 0..matrix.height {
+        let mut mx = 0;
+        while mx < matrix.width {
+            if !matrix.get(mx, my) {
+                mx += 1;
+                continue;
+            }
+            let start = mx;
+            while mx < matrix.width && matrix.get(mx, my) {
+                mx += 1;
+            }
+            let size = module_size as i64;
+            let (x0, x1) = clip(offset_x + start as i64 * size, (mx - start) as i64 * size, width);
+            let (y0, y1) = clip(offset_y + my as i64 * size, size, height);
+            if x1 > x0 && y1 > y0 {
+                canvas.fill_rect((x as i64 + x0) as f32, (y as i64 + y0) as f32, (x1 - x0) as f32, (y1 - y0) as f32, dark);
+            }
+        }
+    }
+}
+
+// Encode a QR code into its module grid
+fn encode_qr_code(text: &str, error_correction: &str) -> Result<BitMatrix, String> {
     use qrcode::QrCode;
     use qrcode::EcLevel;
 
@@ -394,46 +1515,340 @@ fn generate_qr_code(text: &str, error_correction: &str, size: u32) -> Result<Rgb
     let qr = QrCode::with_error_correction_level(text, ec_level)
         .map_err(|e| format!("QR code generation failed: {}", e))?;
 
-    let qr_data = qr.to_colors();
     let qr_width = qr.width();
+    let mut matrix = BitMatrix::new(qr_width, qr_width);
+    for (idx, color) in qr.to_colors().iter().enumerate() {
+        matrix.set(idx % qr_width, idx / qr_width, *color == qrcode::Color::Dark);
+    }
+
+    Ok(matrix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ab_glyph::Font;
+
+    const FONT: &[u8] = include_bytes!("../../static/fonts/Roboto-Regular.ttf");
+
+    /// Renderer for `stamps_json` over a blank `width` x `height` template
+    fn ticket_renderer(stamps_json: &str, width: u32, height: u32, dpi: f64) -> TicketRenderer {
+        let template = TemplateData { width, height, data: vec![255; (width * height * 4) as usize] };
+        let stamps = serde_json::from_str(stamps_json).unwrap();
+        let fonts = HashMap::from([("Roboto".to_string(), FONT.to_vec())]);
+        TicketRenderer::new(template, stamps, Vec::new(), fonts, dpi).unwrap()
+    }
+
+    fn record(fields: &[(&str, &str)]) -> HashMap<String, String> {
+        fields.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn codes(renderer: &TicketRenderer) -> Vec<&'static str> {
+        renderer.take_diagnostics().iter().map(|d| d.code).collect()
+    }
+
+    /// A QR stamp of "HELLO" (21 modules at level L) filling a `width` x
+    /// `height` box, with `options` added to it
+    fn qr_stamp(width: u32, height: u32, options: &str) -> String {
+        format!(
+            r#"[{{"type":"qrcode","id":"q","x":0,"y":0,"width":{},"height":{},"template":"HELLO","errorCorrection":"L"{}}}]"#,
+            width, height, options
+        )
+    }
+
+    /// Left, top, right and bottom edges of the pixels matching `ink`
+    fn ink_bounds(img: &RgbaImage, ink: impl Fn(&Rgba<u8>) -> bool) -> (u32, u32, u32, u32) {
+        let inked: Vec<(u32, u32)> = img.enumerate_pixels().filter(|(_, _, p)| ink(p)).map(|(x, y, _)| (x, y)).collect();
+        let xs = inked.iter().map(|(x, _)| *x);
+        let ys = inked.iter().map(|(_, y)| *y);
+        (xs.clone().min().unwrap(), ys.clone().min().unwrap(), xs.max().unwrap() + 1, ys.max().unwrap() + 1)
+    }
+
+    fn black(p: &Rgba<u8>) -> bool {
+        p.0 == [0, 0, 0, 255]
+    }
+
+    #[test]
+    fn fits_font_size_to_box() {
+        // Text ten times wider than its font size, one line high
+        let measure = |size: f32| (size * 10.0, size * 1.2);
+        assert_eq!(fit_font_size(measure, 20.0, 100.0, 100.0, false), 10.0);
+        assert_eq!(fit_font_size(measure, 20.0, 500.0, 12.0, false), 10.0);
+        // Short text keeps its size unless growing is allowed
+        assert_eq!(fit_font_size(measure, 5.0, 100.0, 100.0, false), 5.0);
+        assert_eq!(fit_font_size(measure, 5.0, 100.0, 100.0, true), 10.0);
+        // No box to fit
+        assert_eq!(fit_font_size(measure, 20.0, 0.0, 100.0, false), 20.0);
+        assert_eq!(fit_font_size(measure, 20.0, 1.0, 1.0, false), 1.0);
+    }
+
+    #[test]
+    fn shrinks_until_text_that_does_not_scale_linearly_fits() {
+        // A fixed 30px overhang makes the linear estimate too large
+        let measure = |size: f32| (size * 10.0 + 30.0, size);
+        let size = fit_font_size(measure, 20.0, 100.0, 100.0, false);
+        assert!(size <= 7.0 && size > 7.0 * 0.95, "{}", size);
+    }
+
+    #[test]
+    fn skips_barcodes_narrower_than_a_pixel_per_module() {
+        // EAN-13 spans 113 modules with its quiet zones
+        let stamps = r#"[{"type":"barcode","id":"b","x":0,"y":0,"width":100,"height":40,"template":"{{n}}","format":"ean13"}]"#;
+        let renderer = ticket_renderer(stamps, 120, 40, 300.0);
+        let img = renderer.render(&record(&[("n", "4006381333931")]), 0, 120, 40).unwrap();
+        assert!(img.pixels().all(|p| p[0] == 255));
+        assert_eq!(codes(&renderer), ["barcode_too_narrow"]);
+
+        let img = renderer.render(&record(&[("n", "4006381333931")]), 0, 240, 80).unwrap();
+        assert!(img.pixels().any(|p| p[0] == 0));
+        assert!(img.pixels().enumerate().all(|(i, p)| (i as u32 % 240) < 200 || p[0] == 255));
+    }
 
-    // Scale QR code to target size
-    let module_size = (size as f32 / qr_width as f32).ceil() as u32;
-    let actual_size = module_size * qr_width as u32;
+    #[test]
+    fn warns_below_minimum_x_dimension() {
+        // At 300 DPI, 0.264mm needs 4px modules: 452px for EAN-13
+        let stamps = r#"[{"type":"barcode","id":"b","x":0,"y":0,"width":400,"height":100,"template":"{{n}}","format":"ean13"}]"#;
+        let renderer = ticket_renderer(stamps, 500, 100, 300.0);
+        renderer.render(&record(&[("n", "4006381333931")]), 0, 500, 100).unwrap();
+        assert_eq!(codes(&renderer), ["barcode_x_dimension"]);
+
+        let stamps = r#"[{"type":"barcode","id":"b","x":0,"y":0,"width":452,"height":100,"template":"{{n}}","format":"ean13"}]"#;
+        let renderer = ticket_renderer(stamps, 500, 100, 300.0);
+        renderer.render(&record(&[("n", "4006381333931")]), 0, 500, 100).unwrap();
+        assert!(codes(&renderer).is_empty());
+    }
 
-    let mut img = RgbaImage::new(actual_size, actual_size);
+    #[test]
+    fn turns_pixels_a_quarter_at_a_time_around_the_pivot() {
+        let red = Rgba([255, 0, 0, 255]);
+        let mut layer = RgbaImage::new(8, 8);
+        layer.put_pixel(5, 2, red);
+        for (degrees, expected) in [(0.0, (5, 2)), (90.0, (5, 5)), (180.0, (2, 5)), (270.0, (2, 2))] {
+            let mut base = RgbaImage::new(8, 8);
+            composite_rotated(&mut base, &layer, (4.0, 4.0), degrees);
+            let drawn: Vec<_> = base.enumerate_pixels().filter(|(_, _, p)| p[3] != 0).map(|(x, y, p)| (x, y, *p)).collect();
+            assert_eq!(drawn, [(expected.0, expected.1, red)], "{} degrees", degrees);
+        }
 
-    // Fill with white background
-    for pixel in img.pixels_mut() {
-        *pixel = Rgba([255, 255, 255, 255]);
+        // Pixels turned off the base are dropped
+        let mut base = RgbaImage::new(8, 8);
+        composite_rotated(&mut base, &layer, (0.0, 0.0), 90.0);
+        assert!(base.pixels().all(|p| p[3] == 0));
     }
 
-    // Draw QR modules
-    for (idx, color) in qr_data.iter().enumerate() {
-        let module_x = (idx % qr_width) as u32;
-        let module_y = (idx / qr_width) as u32;
+    #[test]
+    fn reports_missing_fonts_before_any_ticket() {
+        let template = || TemplateData { width: 10, height: 10, data: vec![255; 400] };
+        let fonts = || HashMap::from([("Roboto".to_string(), FONT.to_vec())]);
+        let text = r##"[{"type":"text","id":"t","x":0,"y":0,"width":10,"height":10,"template":"{{n}}","fontFamily":"Lora","fontSize":8,"color":"#000","alignment":"left"}]"##;
+        let error = TicketRenderer::new(template(), serde_json::from_str(text).unwrap(), Vec::new(), fonts(), 96.0).err();
+        assert_eq!(error.as_deref(), Some("Text stamp 't': Font 'Lora' not found in loaded fonts"));
+
+        let barcode = r#"[{"type":"barcode","id":"b","x":0,"y":0,"width":10,"height":10,"template":"{{n}}","format":"code128","hriPosition":"below","hriFontFamily":"Lora"}]"#;
+        let error = TicketRenderer::new(template(), serde_json::from_str(barcode).unwrap(), Vec::new(), fonts(), 96.0).err();
+        assert_eq!(error.as_deref(), Some("Barcode stamp 'b': Font 'Lora' not found in loaded fonts"));
+    }
 
-        let pixel_color = match color {
-            qrcode::Color::Dark => Rgba([0, 0, 0, 255]),
-            qrcode::Color::Light => Rgba([255, 255, 255, 255]),
+    #[test]
+    fn sets_variable_font_axes_for_weight_slant_and_variations() {
+        let fonts = FontSet::new(HashMap::from([("Var".to_string(), crate::font_set::tests::variable_font())])).unwrap();
+        let stamp = |style: &str| -> TextStamp {
+            serde_json::from_str(&format!(
+                r##"{{"id":"t","x":0,"y":0,"width":10,"height":10,"template":"I","fontFamily":"Var","fontWeight":650,"fontStyle":"{}","fontVariations":{{"wdth":75}},"fontSize":8,"color":"#000","alignment":"left"}}"##,
+                style
+            ))
+            .unwrap()
+        };
+        // Outline of 'I' from its left to its right edge
+        let extent = |choice: &FontChoice| {
+            let font = choice.load().unwrap();
+            let bounds = font.glyphs.outline(font.glyphs.glyph_id('I')).unwrap().bounds;
+            (bounds.min.x, bounds.max.x)
         };
 
-        // Fill the module area
-        for dy in 0..module_size {
-            for dx in 0..module_size {
-                let x = module_x * module_size + dx;
-                let y = module_y * module_size + dy;
-                if x < actual_size && y < actual_size {
-                    img.put_pixel(x, y, pixel_color);
-                }
-            }
+        let upright = text_style(&fonts, &stamp("normal"), &SpanFormat::default()).unwrap();
+        assert_eq!(upright.fonts[0].variations, [(*b"wght", 650.0), (*b"wdth", 75.0)]);
+        // Half the added weight, at three quarters of the width
+        assert_eq!(extent(&upright.fonts[0]), (75.0, 200.0));
+
+        // Without an italic face or axis, italic leans the upright face
+        let italic = text_style(&fonts, &stamp("italic"), &SpanFormat::default()).unwrap();
+        assert_eq!(italic.fonts[0].variations, [(*b"wght", 650.0), (*b"slnt", -14.0), (*b"wdth", 75.0)]);
+        assert_eq!(extent(&italic.fonts[0]), (75.0, 375.0));
+
+        let plain = FontChoice { data: italic.fonts[0].data.clone(), variations: Vec::new() };
+        assert!(plain.load().unwrap().is_default_instance());
+        assert_eq!(extent(&plain), (100.0, 200.0));
+    }
+
+    #[test]
+    fn puts_the_first_baseline_at_the_font_ascent() {
+        let text = r##"[{"type":"text","id":"t","x":0,"y":0,"width":80,"height":40,"template":"{{s}}","fontFamily":"Roboto","fontSize":24,"color":"#000","alignment":"left"}]"##;
+        let renderer = ticket_renderer(text, 80, 40, 96.0);
+        // Top and bottom rows with ink
+        let rows = |s: &str| {
+            let img = renderer.render(&record(&[("s", s)]), 0, 80, 40).unwrap();
+            let inked: Vec<u32> = (0..40).filter(|&y| (0..80).any(|x| img.get_pixel(x, y)[0] < 128)).collect();
+            (inked[0], inked[inked.len() - 1])
+        };
+        // Roboto's ascent is 1900 of its 2400 unit height: 19px at 24px.
+        // Letters of every height sit on it, whatever the tallest one is.
+        assert_eq!(rows("xxx").1, 18);
+        assert_eq!(rows("HHH").1, 18);
+        assert_eq!(rows("xHa").1, 18);
+        assert!(rows("HHH").0 < rows("xxx").0);
+    }
+
+    #[test]
+    fn reports_an_oversized_qr_module_once_per_stamp() {
+        let qr = r#"[{"type":"qrcode","id":"q","x":0,"y":0,"width":58,"height":58,"template":"{{s}}","errorCorrection":"L","moduleSize":3}]"#;
+        let renderer = ticket_renderer(qr, 58, 58, 96.0);
+        // Each symbol is too large, the last one a larger version
+        for (index, s) in ["HELLO", "HELLO AGAIN", "A MUCH LONGER MESSAGE THAN BEFORE"].into_iter().enumerate() {
+            renderer.render(&record(&[("s", s)]), index, 58, 58).unwrap();
         }
+        let diagnostics = renderer.take_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "21x21 QR code with a 4-module quiet zone does not fit the box at module size 3 (3 device pixels); using 2 device pixels per module"
+        );
+
+        renderer.render(&record(&[("s", "HELLO")]), 3, 58, 58).unwrap();
+        assert!(renderer.take_diagnostics().is_empty());
     }
 
-    // Resize to exact target size if needed
-    if actual_size != size {
-        Ok(image::imageops::resize(&img, size, size, image::imageops::FilterType::Nearest))
-    } else {
-        Ok(img)
+    #[test]
+    fn skips_matrix_codes_too_large_for_their_box() {
+        let stamps = r#"[
+            {"type":"datamatrix","id":"d","x":0,"y":0,"width":11,"height":11,"template":"A"},
+            {"type":"aztec","id":"a","x":20,"y":0,"width":14,"height":14,"template":"A"},
+            {"type":"qrcode","id":"q","x":40,"y":0,"width":28,"height":40,"template":"A","errorCorrection":"L"}
+        ]"#;
+        let renderer = ticket_renderer(stamps, 80, 40, 96.0);
+        let img = renderer.render(&record(&[]), 7, 80, 40).unwrap();
+        assert!(img.pixels().all(|p| p[0] == 255));
+
+        let diagnostics = renderer.take_diagnostics();
+        let reported: Vec<_> = diagnostics.iter().map(|d| (d.code, d.stamp_id.as_str(), d.record_index)).collect();
+        assert_eq!(
+            reported,
+            [("matrix_code_too_small", "d", Some(7)), ("matrix_code_too_small", "a", Some(7)), ("matrix_code_too_small", "q", Some(7))]
+        );
+        assert_eq!(
+            diagnostics[0].message,
+            "10x10 DataMatrix symbol needs 12x12 modules with its quiet zone, more than the 11x11px box holds; the stamp needs to be at least 12x12px and was not drawn"
+        );
+    }
+
+    #[test]
+    fn sizes_qr_modules_in_whole_pixels() {
+        let render = |options: &str, scale: u32| {
+            let renderer = ticket_renderer(&qr_stamp(58, 58, options), 58, 58, 96.0);
+            renderer.render(&record(&[]), 0, 58 * scale, 58 * scale).unwrap()
+        };
+        // Best fit: 2px modules, as 3px ones and the quiet zone need 87px
+        let img = render("", 1);
+        assert_eq!(ink_bounds(&img, black), (8, 8, 50, 50));
+        // The finder's light ring and dark centre are whole modules
+        assert!(black(img.get_pixel(9, 9)) && !black(img.get_pixel(10, 10)) && !black(img.get_pixel(11, 11)));
+        assert!(black(img.get_pixel(12, 12)));
+
+        // A requested size is rounded to device pixels
+        assert_eq!(ink_bounds(&render(r#","moduleSize":1"#, 1), black), (18, 18, 39, 39));
+        assert_eq!(ink_bounds(&render(r#","moduleSize":1.4"#, 1), black), (18, 18, 39, 39));
+        assert_eq!(ink_bounds(&render(r#","moduleSize":1"#, 2), black), (37, 37, 79, 79));
+    }
+
+    #[test]
+    fn keeps_a_quiet_zone_and_centres_qr_codes() {
+        let bounds = |width: u32, height: u32, options: &str| {
+            let renderer = ticket_renderer(&qr_stamp(width, height, options), width, height, 96.0);
+            ink_bounds(&renderer.render(&record(&[]), 0, width, height).unwrap(), black)
+        };
+        // Four modules on each side by default: 29 modules at 2px in 63px
+        assert_eq!(bounds(63, 63, ""), (10, 10, 52, 52));
+        assert_eq!(bounds(63, 63, r#","quietZone":0"#), (0, 0, 63, 63));
+        // Centred both ways in a wide box
+        assert_eq!(bounds(80, 58, ""), (19, 8, 61, 50));
+    }
+
+    /// Renderer for `stamps_json` over a `width` x `height` template of `color`
+    fn renderer_over(stamps_json: &str, width: u32, height: u32, color: [u8; 4]) -> TicketRenderer {
+        let template = TemplateData { width, height, data: color.repeat((width * height) as usize) };
+        TicketRenderer::new(template, serde_json::from_str(stamps_json).unwrap(), Vec::new(), HashMap::new(), 96.0).unwrap()
+    }
+
+    #[test]
+    fn colours_qr_codes() {
+        let pixels = |options: &str, template: [u8; 4]| {
+            let renderer = renderer_over(&qr_stamp(58, 58, options), 58, 58, template);
+            let img = renderer.render(&record(&[]), 0, 58, 58).unwrap();
+            assert!(codes(&renderer).is_empty());
+            // A dark finder module, and the quiet zone
+            (img.get_pixel(9, 9).0, img.get_pixel(1, 1).0)
+        };
+        let green = [0, 128, 0, 255];
+        assert_eq!(pixels("", green), ([0, 0, 0, 255], [255, 255, 255, 255]));
+        assert_eq!(
+            pixels(r##","foregroundColor":"#800000","backgroundColor":"#ffff00""##, green),
+            ([128, 0, 0, 255], [255, 255, 0, 255])
+        );
+        // The template shows through a transparent light colour
+        assert_eq!(pixels(r#","backgroundColor":"transparent""#, green), ([0, 0, 0, 255], green));
+        // Inverted codes swap the colours
+        assert_eq!(pixels(r#","inverted":true"#, green), ([255, 255, 255, 255], [0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn warns_about_low_contrast_qr_colours() {
+        let warnings = |options: &str, template: [u8; 4]| {
+            let renderer = renderer_over(&qr_stamp(58, 58, options), 58, 58, template);
+            renderer.render(&record(&[]), 0, 58, 58).unwrap();
+            renderer.take_diagnostics().into_iter().map(|d| (d.code, d.record_index, d.message)).collect::<Vec<_>>()
+        };
+        let white = [255; 4];
+        let low = |ratio: &str| vec![("qr_contrast", None, format!("QR code colours have a contrast ratio of {}:1, below the 3:1 minimum for reliable scanning", ratio))];
+        assert!(warnings(r##","foregroundColor":"#777777""##, white).is_empty());
+        assert_eq!(warnings(r##","foregroundColor":"#aaaaaa""##, white), low("2.3"));
+        // Judged against the artwork a transparent light colour shows
+        assert!(warnings(r#","backgroundColor":"transparent""#, white).is_empty());
+        assert_eq!(warnings(r#","backgroundColor":"transparent""#, [30, 30, 30, 255]), low("1.3"));
+    }
+
+    #[test]
+    fn raises_error_correction_for_qr_logos() {
+        let red_logo = r#""logo":{"width":2,"height":2,"data":[255,0,0,255,255,0,0,255,255,0,0,255,255,0,0,255]}"#;
+        let render = |options: &str| {
+            // 40 alphanumerics: 25 modules at level L, 29 at Q and 33 at H
+            let stamps = format!(
+                r#"[{{"type":"qrcode","id":"q","x":0,"y":0,"width":100,"height":100,"template":"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ ABC","moduleSize":1,"quietZone":0{}}}]"#,
+                options
+            );
+            ticket_renderer(&stamps, 100, 100, 96.0).render(&record(&[]), 0, 100, 100)
+        };
+        let width = |img: &RgbaImage| {
+            let (left, _, right, _) = ink_bounds(img, black);
+            right - left
+        };
+        assert_eq!(width(&render(r#","errorCorrection":"L""#).unwrap()), 25);
+
+        let img = render(&format!(r#","errorCorrection":"L","logoRatio":0.2,{}"#, red_logo)).unwrap();
+        assert_eq!(width(&img), 29);
+        assert_eq!(img.get_pixel(50, 50).0, [255, 0, 0, 255]);
+        let img = render(&format!(r#","errorCorrection":"M","logoRatio":0.37,{}"#, red_logo)).unwrap();
+        assert_eq!(width(&img), 33);
+
+        // Even H can't recover this much of the symbol
+        assert_eq!(
+            render(&format!(r#","errorCorrection":"L","logoRatio":0.5,{}"#, red_logo)).err().unwrap(),
+            "QR stamp 'q': logo ratio 0.5 covers 27% of the symbol, more than error correction level H can recover"
+        );
+        // A level chosen in the stamp is kept, and holds it to its own limit
+        assert_eq!(
+            render(&format!(r#","errorCorrection":"Q","logoRatio":0.37,{}"#, red_logo)).err().unwrap(),
+            "QR stamp 'q': logo ratio 0.37 covers 14% of the symbol, more than error correction level Q can recover"
+        );
+        assert_eq!(width(&render(&format!(r#","errorCorrection":"H","logoRatio":0.37,{}"#, red_logo)).unwrap()), 33);
     }
 }
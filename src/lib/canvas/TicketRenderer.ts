@@ -1,6 +1,14 @@
-import type { Stamp, TextStamp, BarcodeStamp, QrCodeStamp, DataSource } from '$lib/types';
+import type {
+	Stamp,
+	TextStamp,
+	BarcodeStamp,
+	QrCodeStamp,
+	DataMatrixStamp,
+	AztecStamp,
+	DataSource
+} from '$lib/types';
 import { resolveTemplate } from '$lib/engine/template';
-import { generateBarcode } from '$lib/services/barcode';
+import { generateBarcode, generateMatrixCode } from '$lib/services/barcode';
 import { generateQrCode } from '$lib/services/qrcode';
 
 export interface TicketRendererOptions {
@@ -56,17 +64,33 @@ export class TicketRenderer {
 				case 'qrcode':
 					await this.renderQrCodeStamp(stamp as QrCodeStamp, record);
 					break;
+				case 'datamatrix':
+				case 'aztec':
+					await this.renderMatrixCodeStamp(stamp as DataMatrixStamp | AztecStamp, record);
+					break;
 			}
 
 			ctx.restore();
 		}
 	}
 
+	/**
+	 * Resolves a stamp template, showing it unresolved while it has invalid
+	 * filters (e.g. while the user is still typing one).
+	 */
+	private resolve(template: string, record: Record<string, string>): string {
+		try {
+			return resolveTemplate(template, record, this.dataSources);
+		} catch {
+			return template;
+		}
+	}
+
 	private renderTextStamp(stamp: TextStamp, record: Record<string, string>): void {
 		const { ctx } = this;
-		const text = resolveTemplate(stamp.template, record, this.dataSources);
+		const text = this.resolve(stamp.template, record);
 
-		ctx.font = `${stamp.fontSize}px ${stamp.fontFamily}`;
+		ctx.font = `${stamp.fontStyle ?? 'normal'} ${stamp.fontWeight ?? 400} ${stamp.fontSize}px ${stamp.fontFamily}`;
 		ctx.fillStyle = stamp.color;
 
 		let x = stamp.x;
@@ -114,7 +138,7 @@ export class TicketRenderer {
 		record: Record<string, string>
 	): Promise<void> {
 		const { ctx } = this;
-		const text = resolveTemplate(stamp.template, record, this.dataSources);
+		const text = this.resolve(stamp.template, record);
 
 		if (!text) {
 			this.renderPlaceholder(stamp, 'Empty Barcode');
@@ -142,7 +166,7 @@ export class TicketRenderer {
 		record: Record<string, string>
 	): Promise<void> {
 		const { ctx } = this;
-		const text = resolveTemplate(stamp.template, record, this.dataSources);
+		const text = this.resolve(stamp.template, record);
 
 		if (!text) {
 			this.renderPlaceholder(stamp, 'Empty QR');
@@ -153,6 +177,7 @@ export class TicketRenderer {
 			const qrCanvas = await generateQrCode({
 				text,
 				errorCorrection: stamp.errorCorrection,
+				margin: stamp.quietZone ?? 4,
 				width: stamp.width * 2 // Higher resolution for drawing
 			});
 
@@ -163,6 +188,58 @@ export class TicketRenderer {
 		}
 	}
 
+	private async renderMatrixCodeStamp(
+		stamp: DataMatrixStamp | AztecStamp,
+		record: Record<string, string>
+	): Promise<void> {
+		const { ctx } = this;
+		const text = this.resolve(stamp.template, record);
+		const label = stamp.type === 'datamatrix' ? 'DataMatrix' : 'Aztec';
+
+		if (!text) {
+			this.renderPlaceholder(stamp, `Empty ${label}`);
+			return;
+		}
+
+		try {
+			const codeCanvas = await generateMatrixCode(
+				stamp.type === 'datamatrix'
+					? {
+							text,
+							symbology: stamp.shape === 'rectangle' ? 'datamatrixrectangular' : 'datamatrix'
+						}
+					: {
+							text,
+							symbology: 'azteccode',
+							format: stamp.mode === 'compact' || stamp.mode === 'full' ? stamp.mode : undefined
+						}
+			);
+
+			// DataMatrix keeps a one-module quiet zone on each side; Aztec needs none
+			const quietZone = stamp.type === 'datamatrix' ? 2 * dataMatrixModuleWidth(codeCanvas) : 0;
+
+			// Fit the symbol to the box without distorting it, centred as in the PDF
+			const scale = Math.min(
+				stamp.width / (codeCanvas.width + quietZone),
+				stamp.height / (codeCanvas.height + quietZone)
+			);
+			const width = codeCanvas.width * scale;
+			const height = codeCanvas.height * scale;
+
+			ctx.imageSmoothingEnabled = false;
+			ctx.drawImage(
+				codeCanvas,
+				stamp.x + (stamp.width - width) / 2,
+				stamp.y + (stamp.height - height) / 2,
+				width,
+				height
+			);
+		} catch (error) {
+			console.error(`${label} rendering failed:`, error);
+			this.renderPlaceholder(stamp, 'Error');
+		}
+	}
+
 	private renderPlaceholder(stamp: Stamp, label: string): void {
 		const { ctx } = this;
 
@@ -202,10 +279,10 @@ export class TicketRenderer {
 		record: Record<string, string>
 	): { width: number; height: number } {
 		const { ctx } = this;
-		const text = resolveTemplate(stamp.template, record, this.dataSources);
+		const text = this.resolve(stamp.template, record);
 
 		ctx.save();
-		ctx.font = `${stamp.fontSize}px ${stamp.fontFamily}`;
+		ctx.font = `${stamp.fontStyle ?? 'normal'} ${stamp.fontWeight ?? 400} ${stamp.fontSize}px ${stamp.fontFamily}`;
 		const metrics = ctx.measureText(text);
 		// Use fontSize for height to ensure stable bounding box matching baseline logic
 		const height = stamp.fontSize;
@@ -217,3 +294,21 @@ export class TicketRenderer {
 		};
 	}
 }
+
+/**
+ * Width of a DataMatrix module in canvas pixels, from the symbol's top
+ * timing pattern, which alternates dark and light modules.
+ */
+function dataMatrixModuleWidth(canvas: HTMLCanvasElement): number {
+	const ctx = canvas.getContext('2d');
+	if (!ctx || canvas.width === 0) return 0;
+
+	const row = ctx.getImageData(0, 0, canvas.width, 1).data;
+	let modules = 1;
+	for (let x = 1; x < canvas.width; x++) {
+		const dark = row[x * 4 + 3] > 127 && row[x * 4] < 128;
+		const wasDark = row[(x - 1) * 4 + 3] > 127 && row[(x - 1) * 4] < 128;
+		if (dark !== wasDark) modules++;
+	}
+	return canvas.width / modules;
+}
@@ -80,7 +80,48 @@ export function generatePreviewRecord(dataSources: DataSource[]): Record<string,
 		}
 	}
 
-	return record;
+	// As the first ticket of a one-ticket sheet
+	return withBuiltinVariables(record, 0, countRecords(dataSources), 1, 1);
+}
+
+/** Variables the sheet renderer adds to every record, all counted from 1 */
+export const BUILTIN_VARIABLES = [
+	{ name: '_index', description: 'Ticket number' },
+	{ name: '_page', description: 'Sheet number' },
+	{ name: '_page_count', description: 'Number of sheets' },
+	{ name: '_row', description: 'Row on the sheet' },
+	{ name: '_col', description: 'Column on the sheet' },
+	{ name: '_total', description: 'Number of tickets' }
+];
+
+/**
+ * Returns `record` plus the built-in variables for ticket `index` of `total`
+ * on a `rows` x `cols` sheet, matching the WASM sheet renderer. A record's own
+ * fields take precedence.
+ */
+export function withBuiltinVariables(
+	record: Record<string, string>,
+	index: number,
+	total: number,
+	rows: number,
+	cols: number
+): Record<string, string> {
+	const ticketsPerPage = Math.max(1, rows * cols);
+	const slot = index % ticketsPerPage;
+	const values = [
+		index + 1,
+		Math.floor(index / ticketsPerPage) + 1,
+		Math.ceil(total / ticketsPerPage),
+		Math.floor(slot / Math.max(1, cols)) + 1,
+		(slot % Math.max(1, cols)) + 1,
+		total
+	];
+
+	const builtins: Record<string, string> = {};
+	BUILTIN_VARIABLES.forEach(({ name }, i) => {
+		builtins[name] = String(values[i]);
+	});
+	return { ...builtins, ...record };
 }
 
 /**
@@ -142,6 +183,29 @@ export function generateRecords(dataSources: DataSource[]): Record<string, strin
 	return combinedRecords;
 }
 
+/**
+ * Number of records generateRecords returns, from the sizes of the data
+ * sources rather than by generating them.
+ */
+export function countRecords(dataSources: DataSource[]): number {
+	const counts = dataSources.map((source) => {
+		switch (source.type) {
+			case 'csv':
+				return source.rows.length;
+			case 'sequential': {
+				const { start, end, step } = source;
+				const steps = step === 0 ? -1 : Math.floor((end - start) / step);
+				return Math.max(0, steps + 1);
+			}
+			case 'random':
+				return Math.max(0, Math.ceil(source.count));
+			default:
+				return 0;
+		}
+	});
+	return Math.max(0, ...counts);
+}
+
 /**
  * Returns records for a CSV data source.
  */
@@ -0,0 +1,188 @@
+/**
+ * Template filters: `{{value | name:arg:arg}}`. Mirrors src-wasm/src/filters.rs.
+ */
+export type Filter = (value: string) => string;
+
+const MONTHS = [
+	'January',
+	'February',
+	'March',
+	'April',
+	'May',
+	'June',
+	'July',
+	'August',
+	'September',
+	'October',
+	'November',
+	'December'
+];
+const WEEKDAYS = ['Monday', 'Tuesday', 'Wednesday', 'Thursday', 'Friday', 'Saturday', 'Sunday'];
+
+const CURRENCIES: Record<string, [symbol: string, decimals: number]> = {
+	USD: ['$', 2],
+	EUR: ['€', 2],
+	GBP: ['£', 2],
+	JPY: ['¥', 0],
+	INR: ['₹', 2]
+};
+
+const ARITY: Record<string, [min: number, max: number]> = {
+	upper: [0, 0],
+	lower: [0, 0],
+	trim: [0, 0],
+	currency: [1, 1],
+	format: [1, 1],
+	default: [1, 1],
+	pad: [1, 2],
+	slice: [1, 2],
+	truncate: [1, 2]
+};
+
+/** The fewest and most arguments filter `name` takes, if it exists */
+export function filterArity(name: string): [min: number, max: number] | undefined {
+	return Object.hasOwn(ARITY, name) ? ARITY[name] : undefined;
+}
+
+/**
+ * Builds the filter `name` with its arguments, throwing on unknown filters
+ * and invalid arguments.
+ */
+export function parseFilter(name: string, args: string[]): Filter {
+	const arity = filterArity(name);
+	if (!arity) throw new Error(`Unknown filter '${name}'`);
+	const [min, max] = arity;
+	if (args.length < min || args.length > max) {
+		const expected = min === max ? `${min}` : `${min} to ${max}`;
+		throw new Error(`Filter '${name}' takes ${expected} argument(s), got ${args.length}`);
+	}
+	const number = (arg: string) => {
+		if (!/^[+-]?\d+$/.test(arg)) throw new Error(`Filter '${name}': '${arg}' is not a whole number`);
+		return parseInt(arg, 10);
+	};
+	const count = (arg: string) => {
+		if (!/^\+?\d+$/.test(arg)) throw new Error(`Filter '${name}': '${arg}' is not a count`);
+		return parseInt(arg, 10);
+	};
+
+	switch (name) {
+		case 'pad': {
+			const fill = args[1] ?? '0';
+			if ([...fill].length !== 1) {
+				throw new Error(`Filter 'pad': fill '${fill}' must be one character`);
+			}
+			const width = count(args[0]);
+			return (value) => fill.repeat(Math.max(0, width - [...value].length)) + value;
+		}
+		case 'upper':
+			return (value) => value.toUpperCase();
+		case 'lower':
+			return (value) => value.toLowerCase();
+		case 'trim':
+			return (value) => value.trim();
+		case 'currency': {
+			const code = args[0].toUpperCase();
+			return (value) => currency(value, code);
+		}
+		case 'format': {
+			const pattern = args[0];
+			return (value) => formatDate(value, pattern);
+		}
+		case 'slice': {
+			const start = number(args[0]);
+			const end = args[1] === undefined ? undefined : number(args[1]);
+			return (value) => [...value].slice(start, end).join('');
+		}
+		case 'truncate': {
+			const length = count(args[0]);
+			const suffix = args[1] ?? '…';
+			return (value) => {
+				const chars = [...value];
+				if (chars.length <= length) return value;
+				const keep = Math.max(0, length - [...suffix].length);
+				return [...chars.slice(0, keep), ...suffix].slice(0, length).join('');
+			};
+		}
+		default: {
+			const fallback = args[0];
+			return (value) => (value.trim() ? value : fallback);
+		}
+	}
+}
+
+/** Values that aren't numbers are left as they are */
+function currency(value: string, code: string): string {
+	const trimmed = value.trim();
+	if (!/^[+-]?(\d+\.?\d*|\.\d+)([eE][+-]?\d+)?$/.test(trimmed)) return value;
+	const amount = Number(trimmed);
+	if (!Number.isFinite(amount)) return value;
+	const [symbol, decimals] = CURRENCIES[code] ?? ['', 2];
+
+	const digits = Math.round(Math.abs(amount) * 10 ** decimals)
+		.toFixed(0)
+		.padStart(decimals + 1, '0');
+	const whole = digits.slice(0, digits.length - decimals);
+	const fraction = digits.slice(digits.length - decimals);
+	let grouped = whole.replace(/\B(?=(\d{3})+$)/g, ',');
+	if (fraction) grouped += `.${fraction}`;
+
+	const sign = amount < 0 && /[1-9]/.test(digits) ? '-' : '';
+	return symbol ? `${sign}${symbol}${grouped}` : `${sign}${code} ${grouped}`;
+}
+
+/**
+ * strftime subset: %Y %y %m %d %e %b %B %a %A %H %M %S %%. Values that
+ * aren't ISO dates are left as they are.
+ */
+function formatDate(value: string, pattern: string): string {
+	const match = /^(\d{4})-(\d{2})-(\d{2})(?:[T ](\d{2}):(\d{2})(?::(\d{2}).*)?)?$/.exec(value.trim());
+	if (!match) return value;
+	const [year, month, day, hour, minute, second] = match.slice(1).map((part) => Number(part ?? 0));
+	const date = new Date(Date.UTC(2000, month - 1, day));
+	date.setUTCFullYear(year);
+	if (
+		month < 1 ||
+		month > 12 ||
+		date.getUTCDate() !== day ||
+		hour > 23 ||
+		minute > 59 ||
+		second > 59
+	) {
+		return value;
+	}
+	const weekday = (date.getUTCDay() + 6) % 7;
+	const two = (n: number) => String(n).padStart(2, '0');
+
+	return pattern.replace(/%(.?)/gs, (field, code: string) => {
+		switch (code) {
+			case 'Y':
+				return String(year);
+			case 'y':
+				return two(year % 100);
+			case 'm':
+				return two(month);
+			case 'd':
+				return two(day);
+			case 'e':
+				return String(day);
+			case 'b':
+				return MONTHS[month - 1].slice(0, 3);
+			case 'B':
+				return MONTHS[month - 1];
+			case 'a':
+				return WEEKDAYS[weekday].slice(0, 3);
+			case 'A':
+				return WEEKDAYS[weekday];
+			case 'H':
+				return two(hour);
+			case 'M':
+				return two(minute);
+			case 'S':
+				return two(second);
+			case '%':
+				return '%';
+			default:
+				return field;
+		}
+	});
+}
@@ -0,0 +1,23 @@
+import { describe, expect, it } from 'vitest';
+import type { DataSource } from '$lib/types';
+import { resolveTemplate } from './template';
+import vectors from './template-vectors.json';
+
+// The same vectors are checked against the WASM renderer in src-wasm/src/template.rs
+describe('resolveTemplate', () => {
+	for (const group of vectors) {
+		describe(group.description, () => {
+			const dataSources = group.dataSources as DataSource[];
+			const record: Record<string, string> = group.record;
+			for (const { template, ...outcome } of group.cases) {
+				it(template, () => {
+					if ('error' in outcome) {
+						expect(() => resolveTemplate(template, record, dataSources)).toThrow();
+					} else {
+						expect(resolveTemplate(template, record, dataSources)).toBe(outcome.expected);
+					}
+				});
+			}
+		});
+	}
+});
@@ -1,5 +1,6 @@
 import type { DataSource } from '$lib/types';
-import { ensureDataSourceNames } from './data';
+import { BUILTIN_VARIABLES, ensureDataSourceNames } from './data';
+import { filterArity, parseFilter, type Filter } from './filters';
 
 /**
  * Resolves a template string with data from a record and named sources.
@@ -7,6 +8,19 @@ import { ensureDataSourceNames } from './data';
  * - {{name}} for scalar sources (sequential, random) or single CSV source
  * - {{source.field}} for CSV fields with named source
  * - {{.field}} for CSV fields when there's only one CSV source
+ * - {{"text"}} for literal text
+ * - {{value | filter:arg | ...}} to pass the value through filters
+ * - {{a == b}} and {{a != b}}, which are "true" or empty
+ * - {{condition ? then : else}}, where the condition is truthy unless it is
+ *   empty, "0", "false" or "no"
+ *
+ * Parentheses group, e.g. {{vip ? (seat | pad:3) : "GA"}}; without them a
+ * filter in the `then` branch takes arguments up to its maximum. A source or
+ * column name that isn't a valid expression, like {{Price (EUR)}}, is looked
+ * up as written. Throws on other invalid expressions.
+ *
+ * The WASM renderer implements the same grammar; both must agree with
+ * template-vectors.json.
  */
 export function resolveTemplate(
 	template: string,
@@ -16,66 +30,290 @@ export function resolveTemplate(
 	// Ensure all sources have names for backward compatibility
 	ensureDataSourceNames(dataSources);
 
-	return template.replace(/\{\{([^}]+)\}\}/g, (match, expression) => {
-		const trimmed = expression.trim();
+	return template.replace(/\{\{([^}]+)\}\}/g, (match, source: string) => {
+		let expression: Expression;
+		try {
+			expression = parseExpression(source);
+		} catch (e) {
+			if (!namesSourceOrColumn(source.trim(), dataSources)) {
+				throw new Error(`In '{{${source.trim()}}}': ${(e as Error).message}`);
+			}
+			expression = { kind: 'variable', name: source.trim() };
+		}
+		return evaluate(expression, record, dataSources);
+	});
+}
 
-		// Handle {{source.field}} syntax
-		if (trimmed.includes('.')) {
-			const parts = trimmed.split('.');
-			if (parts.length === 2) {
-				const [sourceName, fieldName] = parts;
+/** Whether `name`, or its field after a source prefix, is a source or column name */
+function namesSourceOrColumn(name: string, dataSources: DataSource[]): boolean {
+	const field = name.slice(name.indexOf('.') + 1);
+	return dataSources.some(
+		(s) => s.name === field || (s.type === 'csv' && s.columns.includes(field))
+	);
+}
 
-				// Handle {{.field}} - use the only CSV source
-				if (sourceName === '') {
-					const csvSources = dataSources.filter((s) => s.type === 'csv');
-					if (csvSources.length === 1 && csvSources[0].type === 'csv') {
-						return record[fieldName] || '';
-					}
-					return '';
-				}
+type Expression =
+	| { kind: 'variable'; name: string }
+	| { kind: 'literal'; text: string }
+	| { kind: 'filtered'; value: Expression; filters: Filter[] }
+	| { kind: 'compare'; left: Expression; equal: boolean; right: Expression }
+	| { kind: 'conditional'; condition: Expression; then: Expression; otherwise: Expression };
+
+function evaluate(
+	expression: Expression,
+	record: Record<string, string>,
+	dataSources: DataSource[]
+): string {
+	switch (expression.kind) {
+		case 'variable':
+			return lookup(expression.name, record, dataSources);
+		case 'literal':
+			return expression.text;
+		case 'filtered':
+			return expression.filters.reduce(
+				(value, filter) => filter(value),
+				evaluate(expression.value, record, dataSources)
+			);
+		case 'compare': {
+			const left = evaluate(expression.left, record, dataSources);
+			const right = evaluate(expression.right, record, dataSources);
+			return (left === right) === expression.equal ? 'true' : '';
+		}
+		case 'conditional':
+			return truthy(evaluate(expression.condition, record, dataSources))
+				? evaluate(expression.then, record, dataSources)
+				: evaluate(expression.otherwise, record, dataSources);
+	}
+}
+
+function truthy(value: string): boolean {
+	const trimmed = value.trim().toLowerCase();
+	return !['', '0', 'false', 'no'].includes(trimmed);
+}
 
-				// Handle {{source.field}} - find named source
-				const source = dataSources.find((s) => s.name === sourceName && s.type === 'csv');
-				if (source) {
+function lookup(name: string, record: Record<string, string>, dataSources: DataSource[]): string {
+	// Handle {{source.field}} syntax
+	if (name.includes('.')) {
+		const parts = name.split('.');
+		if (parts.length === 2) {
+			const [sourceName, fieldName] = parts;
+
+			// Handle {{.field}} - use the only CSV source
+			if (sourceName === '') {
+				const csvSources = dataSources.filter((s) => s.type === 'csv');
+				if (csvSources.length === 1 && csvSources[0].type === 'csv') {
 					return record[fieldName] || '';
 				}
+				return '';
+			}
+
+			// Handle {{source.field}} - find named source
+			const source = dataSources.find((s) => s.name === sourceName && s.type === 'csv');
+			if (source) {
+				return record[fieldName] || '';
 			}
-			return '';
 		}
+		return '';
+	}
 
-		// Handle {{name}} - direct lookup for scalar sources or CSV columns
-		return record[trimmed] || '';
-	});
+	// Handle {{name}} - direct lookup for scalar sources or CSV columns
+	return record[name] || '';
+}
+
+/** Words (names or unquoted arguments, possibly with inner spaces), quoted text and operators */
+type Token = { word: string } | { quoted: string } | { op: '|' | ':' | '?' | '==' | '!=' | '(' | ')' };
+
+function describe(token: Token): string {
+	if ('word' in token) return `'${token.word}'`;
+	if ('quoted' in token) return `"${token.quoted}"`;
+	return `'${token.op}'`;
+}
+
+function tokenize(source: string): Token[] {
+	const tokens: Token[] = [];
+	let i = 0;
+	while (i < source.length) {
+		const c = source[i];
+		const pair = source.slice(i, i + 2);
+		if (/\s/.test(c)) {
+			i++;
+		} else if (c === '"' || c === "'") {
+			const end = source.indexOf(c, i + 1);
+			if (end < 0) throw new Error(`Unclosed ${c} quote`);
+			tokens.push({ quoted: source.slice(i + 1, end) });
+			i = end + 1;
+		} else if ('|:?()'.includes(c)) {
+			tokens.push({ op: c as '|' | ':' | '?' | '(' | ')' });
+			i++;
+		} else if (pair === '==' || pair === '!=') {
+			tokens.push({ op: pair });
+			i += 2;
+		} else {
+			let end = i;
+			while (
+				end < source.length &&
+				!`"'|:?()`.includes(source[end]) &&
+				!['==', '!='].includes(source.slice(end, end + 2))
+			) {
+				end++;
+			}
+			tokens.push({ word: source.slice(i, end).trimEnd() });
+			i = end;
+		}
+	}
+	return tokens;
+}
+
+function parseExpression(source: string): Expression {
+	const tokens = tokenize(source);
+	let pos = 0;
+	// Inside the `then` branch of a conditional, where a ':' past a filter's
+	// last argument ends the branch
+	let inBranch = false;
+
+	const isOp = (op: string) => {
+		const token = tokens[pos];
+		return token !== undefined && 'op' in token && token.op === op;
+	};
+	const eat = (op: string) => {
+		const found = isOp(op);
+		if (found) pos++;
+		return found;
+	};
+	const expect = (op: string) => {
+		const token = tokens[pos++];
+		if (token === undefined) throw new Error(`Expected '${op}'`);
+		if (!('op' in token) || token.op !== op) {
+			throw new Error(`Expected '${op}' but found ${describe(token)}`);
+		}
+	};
+
+	// comparison ('?' conditional ':' conditional)?
+	const conditional = (): Expression => {
+		const condition = comparison();
+		if (!eat('?')) return condition;
+		const outer = inBranch;
+		inBranch = true;
+		const then = conditional();
+		inBranch = outer;
+		expect(':');
+		const otherwise = conditional();
+		return { kind: 'conditional', condition, then, otherwise };
+	};
+
+	// pipeline (('==' | '!=') pipeline)?
+	const comparison = (): Expression => {
+		const left = pipeline();
+		let equal: boolean;
+		if (eat('==')) equal = true;
+		else if (eat('!=')) equal = false;
+		else return left;
+		return { kind: 'compare', left, equal, right: pipeline() };
+	};
+
+	// value ('|' name (':' argument)*)*
+	const pipeline = (): Expression => {
+		const value = primary();
+		const filters: Filter[] = [];
+		while (eat('|')) {
+			const token = tokens[pos++];
+			if (token === undefined) throw new Error("Missing filter name after '|'");
+			if (!('word' in token)) throw new Error(`Expected a filter name but found ${describe(token)}`);
+			const name = token.word;
+			const maxArgs = filterArity(name)?.[1] ?? Infinity;
+			const args: string[] = [];
+			while (!(inBranch && args.length === maxArgs) && eat(':')) {
+				const arg = tokens[pos++];
+				if (arg === undefined) throw new Error(`Missing argument for '${name}' after ':'`);
+				if ('op' in arg) {
+					throw new Error(`Expected an argument for '${name}' but found ${describe(arg)}`);
+				}
+				args.push('word' in arg ? arg.word : arg.quoted);
+			}
+			filters.push(parseFilter(name, args));
+		}
+		return filters.length ? { kind: 'filtered', value, filters } : value;
+	};
+
+	// A name, quoted text or a parenthesized expression
+	const primary = (): Expression => {
+		const token = tokens[pos++];
+		if (token === undefined) throw new Error('Expected a value');
+		if ('word' in token) return { kind: 'variable', name: token.word };
+		if ('quoted' in token) return { kind: 'literal', text: token.quoted };
+		if (token.op === '(') {
+			const outer = inBranch;
+			inBranch = false;
+			const expression = conditional();
+			inBranch = outer;
+			expect(')');
+			return expression;
+		}
+		throw new Error(`Expected a value but found ${describe(token)}`);
+	};
+
+	const expression = conditional();
+	if (pos < tokens.length) throw new Error(`Unexpected ${describe(tokens[pos])}`);
+	return expression;
 }
 
 /**
- * Extracts all variables from a template.
+ * Extracts all variables from a template, including those inside filters,
+ * comparisons and conditionals. Invalid expressions are skipped, unless they
+ * name a source or column in `dataSources`.
  * Returns an array of objects describing each variable.
  */
 export function extractVariables(
-	template: string
+	template: string,
+	dataSources: DataSource[] = []
 ): Array<{ raw: string; sourceName?: string; fieldName: string }> {
 	const matches = template.matchAll(/\{\{([^}]+)\}\}/g);
 	const variables: Array<{ raw: string; sourceName?: string; fieldName: string }> = [];
 
-	for (const match of matches) {
-		const expression = match[1].trim();
+	const collect = (expression: Expression) => {
+		switch (expression.kind) {
+			case 'variable': {
+				const name = expression.name;
+				if (name.includes('.')) {
+					const parts = name.split('.');
+					if (parts.length === 2) {
+						const [sourceName, fieldName] = parts;
+						variables.push({
+							raw: name,
+							sourceName: sourceName || undefined,
+							fieldName
+						});
+					}
+				} else {
+					variables.push({ raw: name, fieldName: name });
+				}
+				break;
+			}
+			case 'literal':
+				break;
+			case 'filtered':
+				collect(expression.value);
+				break;
+			case 'compare':
+				collect(expression.left);
+				collect(expression.right);
+				break;
+			case 'conditional':
+				collect(expression.condition);
+				collect(expression.then);
+				collect(expression.otherwise);
+				break;
+		}
+	};
 
-		if (expression.includes('.')) {
-			const parts = expression.split('.');
-			if (parts.length === 2) {
-				const [sourceName, fieldName] = parts;
-				variables.push({
-					raw: expression,
-					sourceName: sourceName || undefined,
-					fieldName
-				});
+	for (const match of matches) {
+		try {
+			collect(parseExpression(match[1]));
+		} catch {
+			// Not a valid expression, so it has no variables unless it is a name
+			if (namesSourceOrColumn(match[1].trim(), dataSources)) {
+				collect({ kind: 'variable', name: match[1].trim() });
 			}
-		} else {
-			variables.push({
-				raw: expression,
-				fieldName: expression
-			});
 		}
 	}
 
@@ -140,5 +378,12 @@ export function getAvailableVariables(dataSources: DataSource[]): Array<{
 		}
 	}
 
+	// Built-ins only mean something once there are records to number
+	if (variables.length > 0) {
+		for (const { name, description } of BUILTIN_VARIABLES) {
+			variables.push({ syntax: `{{${name}}}`, description, source: 'built-in' });
+		}
+	}
+
 	return variables;
 }
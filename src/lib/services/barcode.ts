@@ -40,6 +40,38 @@ export async function generateBarcode(options: BarcodeOptions): Promise<HTMLCanv
 	}
 }
 
+export type MatrixSymbology = 'datamatrix' | 'datamatrixrectangular' | 'azteccode';
+
+export interface MatrixCodeOptions {
+	text: string;
+	symbology: MatrixSymbology;
+	format?: 'compact' | 'full'; // Aztec symbol size, the smallest that fits when unset
+	scale?: number;
+}
+
+/**
+ * Generates a DataMatrix or Aztec code using bwip-js and returns it as a canvas element.
+ * The canvas holds the symbol alone, without a quiet zone.
+ */
+export async function generateMatrixCode(options: MatrixCodeOptions): Promise<HTMLCanvasElement> {
+	const canvas = document.createElement('canvas');
+	const format = options.format ? { format: options.format } : {};
+
+	try {
+		bwipjs.toCanvas(canvas, {
+			bcid: options.symbology,
+			text: options.text,
+			scale: options.scale ?? 4,
+			...format
+		});
+
+		return canvas;
+	} catch (error) {
+		console.error('Failed to generate matrix code:', error);
+		throw error;
+	}
+}
+
 /**
  * Generates a barcode and returns it as a Data URL.
  */
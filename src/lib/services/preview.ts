@@ -4,7 +4,8 @@ export async function generatePreviewPng(
 	layout: SheetLayout,
 	ticketImages: Uint8Array[],
 	ticketWidthPx: number,
-	ticketHeightPx: number
+	ticketHeightPx: number,
+	pageIndex?: number
 ): Promise<string> {
 	// Dynamically import the WASM module
 	const { generate_preview_png, default: init } = await import('$lib/wasm/pdf_generator');
@@ -41,7 +42,13 @@ export async function generatePreviewPng(
 	}
 
 	try {
-		const pngBytes = generate_preview_png(configJson, imageData, ticketWidthPx, ticketHeightPx);
+		const pngBytes = generate_preview_png(
+			configJson,
+			imageData,
+			ticketWidthPx,
+			ticketHeightPx,
+			pageIndex
+		);
 
 		// Convert to data URL
 		const blob = new Blob(
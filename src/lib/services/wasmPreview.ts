@@ -1,5 +1,5 @@
-import type { Project, SheetLayout, Stamp } from '$lib/types';
-import { AVAILABLE_FONTS } from '$lib/types';
+import type { DataSource, Project, SheetLayout, Stamp } from '$lib/types';
+import { AVAILABLE_FONTS, fontKey } from '$lib/types';
 import { generateRecords } from '$lib/engine/data';
 
 let wasmModule: typeof import('$lib/wasm/pdf_generator') | null = null;
@@ -17,7 +17,7 @@ interface TemplateCache {
 let templateCache: TemplateCache | null = null;
 
 // ---------------------------------------------------------------------------
-// Per-ticket cache. Key = JSON.stringify(record) – ONLY the value.
+// Per-ticket cache. Key = the ticket's index and record (see ticketKey).
 // Stores an ImageBitmap ready for fast canvas drawImage calls.
 // ---------------------------------------------------------------------------
 interface CachedTicket {
@@ -64,6 +64,8 @@ export interface SheetGeometry {
 	cols: number;
 	/** Serialised records for this page (simple key-value objects) */
 	records: Record<string, string>[];
+	/** Number of records in the whole job, for built-in variables */
+	recordCount: number;
 	/** Distribution mode for extra space */
 	distributionMode: 'expand' | 'align';
 	/** Position when distributionMode is 'align' */
@@ -142,14 +144,23 @@ function serializeStamps(stamps: Stamp[]): unknown[] {
 function getFontUrlsFromStamps(stamps: Stamp[]): Record<string, string> {
 	const out: Record<string, string> = {};
 	for (const s of stamps) {
-		if (s.type === 'text') {
-			const def = AVAILABLE_FONTS.find((f) => f.name === s.fontFamily);
-			if (def && !out[s.fontFamily]) out[s.fontFamily] = def.url;
+		const families =
+			s.type === 'text'
+				? [s.fontFamily, ...(s.fallbackFonts ?? [])]
+				: s.type === 'barcode'
+					? [s.hriFontFamily]
+					: [];
+		for (const family of families) {
+			if (!family) continue;
+			// Every face of the family, so stamps can pick weights and styles
+			for (const def of AVAILABLE_FONTS.filter((f) => f.name === family)) {
+				out[fontKey(def)] = def.url;
+			}
 		}
 	}
 	if (Object.keys(out).length === 0) {
 		const d = AVAILABLE_FONTS[0];
-		out[d.name] = d.url;
+		out[fontKey(d)] = d.url;
 	}
 	return out;
 }
@@ -230,23 +241,34 @@ export async function computeSheetGeometry(
 		rows: layout.rows,
 		cols: layout.cols,
 		records: serialized,
+		recordCount: records.length,
 		distributionMode: layout.distributionMode,
 		marginAlignment: layout.marginAlignment
 	};
 }
 
+// ---------------------------------------------------------------------------
+// Cache key of ticket `index` on the sheet. The index is part of it because
+// built-in variables such as {{_index}} differ between tickets.
+// ---------------------------------------------------------------------------
+function ticketKey(geo: SheetGeometry, index: number): string {
+	return JSON.stringify([index, geo.records[index]]);
+}
+
 // ---------------------------------------------------------------------------
 // Render a single ticket via WASM (async). Returns an ImageBitmap.
-// Cache key is ONLY JSON.stringify(record).
+// WASM adds the built-in variables for the ticket's place in the whole job.
 // ---------------------------------------------------------------------------
 async function ensureTicketRendered(
 	wasm: typeof import('$lib/wasm/pdf_generator'),
 	templateData: { width: number; height: number; data: Uint8Array },
 	stamps: Stamp[],
-	record: Record<string, string>,
+	dataSources: DataSource[],
+	geo: SheetGeometry,
+	index: number,
 	fontsObject: Record<string, string>
 ): Promise<CachedTicket> {
-	const key = JSON.stringify(record);
+	const key = ticketKey(geo, index);
 	const cached = ticketCache.get(key);
 	if (cached) return cached;
 
@@ -273,7 +295,13 @@ async function ensureTicketRendered(
 		template_width: templateData.width,
 		template_height: templateData.height,
 		stamps: serializeStamps(stamps),
-		records: [record],
+		data_sources: dataSources.map((s) => ({
+			name: s.name,
+			type: s.type,
+			columns: s.type === 'csv' ? s.columns : []
+		})),
+		records: [geo.records[index]],
+		numbering: { first_index: index, total: geo.recordCount, rows: geo.rows, cols: geo.cols },
 		dpi: 96
 	};
 
@@ -310,7 +338,8 @@ async function ensureTicketRendered(
 // ---------------------------------------------------------------------------
 async function renderVisibleTickets(
 	project: Project,
-	records: Record<string, string>[]
+	geo: SheetGeometry,
+	indices: number[]
 ): Promise<void> {
 	const wasm = await initWasm();
 	if (!project.templateImage) throw new Error('No template image available');
@@ -319,17 +348,19 @@ async function renderVisibleTickets(
 	const fontsObject = getFontUrlsFromStamps(project.stamps);
 
 	// Only render tickets that aren't already cached
-	const toRender: Record<string, string>[] = [];
-	for (const record of records) {
-		const key = JSON.stringify(record);
-		if (!ticketCache.has(key)) {
-			toRender.push(record);
-		}
-	}
+	const toRender = indices.filter((index) => !ticketCache.has(ticketKey(geo, index)));
 
 	// Render missing tickets
-	for (const record of toRender) {
-		await ensureTicketRendered(wasm, templateData, project.stamps, record, fontsObject);
+	for (const index of toRender) {
+		await ensureTicketRendered(
+			wasm,
+			templateData,
+			project.stamps,
+			project.dataSources,
+			geo,
+			index,
+			fontsObject
+		);
 	}
 }
 
@@ -406,7 +437,7 @@ export async function composeSheet(
 	const total = geo.records.length;
 
 	// First pass: identify visible tickets that need rendering
-	const visibleRecords: Record<string, string>[] = [];
+	const visibleIndices: number[] = [];
 
 	// Calculate effective margins and spacing based on alignment mode
 	// Tickets are sized based on template aspect ratio, so one axis will have extra space
@@ -485,12 +516,12 @@ export async function composeSheet(
 		}
 
 		// This ticket is visible
-		visibleRecords.push(geo.records[i]);
+		visibleIndices.push(i);
 	}
 
 	// Render any visible tickets that aren't cached yet
-	if (visibleRecords.length > 0) {
-		await renderVisibleTickets(project, visibleRecords);
+	if (visibleIndices.length > 0) {
+		await renderVisibleTickets(project, geo, visibleIndices);
 	}
 
 	// Second pass: draw all visible tickets (now all cached)
@@ -517,8 +548,7 @@ export async function composeSheet(
 		}
 
 		// Look up cached bitmap (should always exist now)
-		const key = JSON.stringify(geo.records[i]);
-		const cached = ticketCache.get(key);
+		const cached = ticketCache.get(ticketKey(geo, i));
 		if (!cached) continue; // Skip if somehow not rendered
 
 		// Uniform scaling: maintain template aspect ratio within cell
@@ -4,11 +4,22 @@
 // Fonts
 // ============================================================================
 
+export type FontStyle = 'normal' | 'italic' | 'oblique';
+
 export interface FontDefinition {
 	name: string;
 	displayName: string;
 	fileName: string;
 	url: string;
+	weight?: number; // default 400, or the wght range of a variable font
+	style?: FontStyle;
+}
+
+/** Key a font face is registered under with the WASM renderer, e.g. "Roboto:700:italic" */
+export function fontKey(font: FontDefinition): string {
+	return [font.name, font.weight, font.style !== 'normal' ? font.style : undefined]
+		.filter((part) => part !== undefined)
+		.join(':');
 }
 
 export const AVAILABLE_FONTS: FontDefinition[] = [
@@ -48,7 +59,7 @@ export const AVAILABLE_FONTS: FontDefinition[] = [
 // Stamps
 // ============================================================================
 
-export type StampType = 'text' | 'barcode' | 'qrcode';
+export type StampType = 'text' | 'barcode' | 'qrcode' | 'datamatrix' | 'aztec';
 
 export interface BaseStamp {
 	id: string;
@@ -57,17 +68,37 @@ export interface BaseStamp {
 	y: number;
 	width: number;
 	height: number;
+	rotation?: number; // clockwise degrees around (x, y)
 }
 
+export type TextWrap = 'none' | 'word' | 'character';
+
 export interface TextStamp extends BaseStamp {
 	type: 'text';
 	template: string; // e.g., "{{number}}"
 	fontFamily: string;
+	fallbackFonts?: string[]; // tried in order for characters fontFamily lacks
+	fontWeight?: number; // default 400
+	fontStyle?: FontStyle;
+	fontVariations?: Record<string, number>; // variable font axes, e.g. { wdth: 75 }
+	markup?: boolean; // **bold**, *italic* and [span]{color=#c00 size=120% weight=600 style=italic} in template
 	fontSize: number;
 	color: string;
 	alignment: 'left' | 'center' | 'right';
 	verticalAlign?: 'top' | 'middle' | 'bottom';
 	autoSize?: boolean;
+	autoSizeGrow?: boolean; // with autoSize, enlarge short text to fill the box
+	wrap?: TextWrap;
+	lineHeight?: number; // multiple of fontSize, default 1.2
+	letterSpacing?: number; // extra space between characters
+	strokeColor?: string; // outline around the glyphs
+	strokeWidth?: number;
+	shadowColor?: string;
+	shadowOffsetX?: number;
+	shadowOffsetY?: number;
+	shadowBlur?: number; // as canvas shadowBlur
+	backgroundColor?: string; // box behind the text
+	backgroundPadding?: number;
 }
 
 export type BarcodeFormat =
@@ -80,22 +111,55 @@ export type BarcodeFormat =
 	| 'code93'
 	| 'itf14';
 
+export type HriPosition = 'above' | 'below' | 'none';
+
 export interface BarcodeStamp extends BaseStamp {
 	type: 'barcode';
 	template: string;
 	format: BarcodeFormat;
+	hriPosition?: HriPosition;
+	hriFontFamily?: string;
+	hriFontSize?: number;
 }
 
 export type QrErrorCorrection = 'L' | 'M' | 'Q' | 'H';
 
+export interface QrLogo {
+	width: number;
+	height: number;
+	data: number[]; // RGBA bytes
+}
+
 export interface QrCodeStamp extends BaseStamp {
 	type: 'qrcode';
 	template: string;
 	errorCorrection: QrErrorCorrection;
 	moduleSize: number;
+	quietZone?: number; // modules, default 4
+	foregroundColor?: string;
+	backgroundColor?: string; // may be 'transparent'
+	inverted?: boolean; // light modules on a dark background
+	logo?: QrLogo;
+	logoRatio?: number; // logo width as a fraction of the symbol, default 0.2
+}
+
+export type DataMatrixShape = 'square' | 'rectangle';
+
+export interface DataMatrixStamp extends BaseStamp {
+	type: 'datamatrix';
+	template: string;
+	shape?: DataMatrixShape; // ECC200 symbol shape, default square
+}
+
+export type AztecMode = 'auto' | 'compact' | 'full';
+
+export interface AztecStamp extends BaseStamp {
+	type: 'aztec';
+	template: string;
+	mode?: AztecMode; // default auto: the smallest symbol that fits
 }
 
-export type Stamp = TextStamp | BarcodeStamp | QrCodeStamp;
+export type Stamp = TextStamp | BarcodeStamp | QrCodeStamp | DataMatrixStamp | AztecStamp;
 
 // ============================================================================
 // Data Sources